@@ -0,0 +1,143 @@
+//! Just enough ACPI to find the processors: the RSDP, the RSDT or XSDT and
+//! the MADT. Tables are read through the physical memory mapping.
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use crate::memory;
+
+/// Root system description pointer, revision 2 layout.
+#[allow(dead_code)] // most fields only describe the layout
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Header shared by all system description tables.
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+/// MADT fields after the header: local APIC address and flags.
+const MADT_ENTRIES_OFFSET: usize = size_of::<SdtHeader>() + 8;
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_LOCAL_X2APIC: u8 = 9;
+const PROCESSOR_ENABLED: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    pub apic_id: u32,
+}
+
+fn phys_ptr<T>(addr: u64) -> *const T {
+    (memory::physical_memory_offset() + addr).as_ptr()
+}
+
+unsafe fn read<T: Copy>(addr: u64) -> T {
+    core::ptr::read_unaligned(phys_ptr::<T>(addr))
+}
+
+unsafe fn checksum_ok(addr: u64, len: usize) -> bool {
+    let bytes = core::slice::from_raw_parts(phys_ptr::<u8>(addr), len);
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+/// Looks for the RSDP in the first KiB of the EBDA and in the BIOS area.
+unsafe fn find_rsdp() -> Option<u64> {
+    let ebda = u64::from(read::<u16>(0x40E)) << 4;
+    let mut ranges = [(ebda, ebda + 1024), (0xE0000, 0x100000)];
+    if ebda == 0 {
+        ranges[0] = (0, 0);
+    }
+    for (start, end) in ranges {
+        for addr in (start..end).step_by(16) {
+            if &read::<[u8; 8]>(addr) == b"RSD PTR " && checksum_ok(addr, 20) {
+                return Some(addr);
+            }
+        }
+    }
+    None
+}
+
+/// Physical address of the table with `signature`.
+unsafe fn find_table(signature: &[u8; 4]) -> Option<u64> {
+    let rsdp: Rsdp = read(find_rsdp()?);
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address, 8)
+    } else {
+        (u64::from(rsdp.rsdt_address), 4)
+    };
+    let header: SdtHeader = read(root);
+    let length = header.length as usize;
+    if !checksum_ok(root, length) {
+        return None;
+    }
+
+    let entries = (length - size_of::<SdtHeader>()) / entry_size;
+    (0..entries)
+        .map(|index| root + (size_of::<SdtHeader>() + index * entry_size) as u64)
+        .map(|entry| match entry_size {
+            8 => read::<u64>(entry),
+            _ => u64::from(read::<u32>(entry)),
+        })
+        .find(|&table| {
+            let header: SdtHeader = read(table);
+            &header.signature == signature && checksum_ok(table, header.length as usize)
+        })
+}
+
+/// The usable processors listed in the MADT, including the bootstrap
+/// processor. Empty without ACPI.
+pub fn processors() -> Vec<Processor> {
+    let mut processors = Vec::new();
+    let madt = match unsafe { find_table(b"APIC") } {
+        Some(madt) => madt,
+        None => return processors,
+    };
+
+    let length = unsafe { read::<SdtHeader>(madt) }.length as usize;
+    let mut offset = MADT_ENTRIES_OFFSET;
+    while offset + 2 <= length {
+        let entry = madt + offset as u64;
+        let (kind, entry_length) = unsafe { (read::<u8>(entry), read::<u8>(entry + 1)) };
+        if entry_length < 2 {
+            break;
+        }
+        let processor = match kind {
+            // processor uid, APIC id, flags
+            MADT_LOCAL_APIC => unsafe {
+                Some((u32::from(read::<u8>(entry + 3)), read::<u32>(entry + 4)))
+            },
+            // reserved, x2APIC id, flags, processor uid
+            MADT_LOCAL_X2APIC => unsafe { Some((read::<u32>(entry + 4), read::<u32>(entry + 8))) },
+            _ => None,
+        };
+        if let Some((apic_id, flags)) = processor {
+            if flags & PROCESSOR_ENABLED != 0 {
+                processors.push(Processor { apic_id });
+            }
+        }
+        offset += usize::from(entry_length);
+    }
+    processors
+}
@@ -1,7 +1,9 @@
 use core::{
-    alloc::{GlobalAlloc, Layout}, mem, ptr::{self, NonNull}
+    alloc::{GlobalAlloc, Layout}, cell::UnsafeCell, mem, ptr::{self, NonNull}
 };
 
+use x86_64::instructions::interrupts;
+
 use super::Locked;
 
 struct Node {
@@ -37,6 +39,69 @@ impl FixedSizeBlockAlocator {
     }
 }
 
+/// Most blocks of one size a CPU keeps to itself.
+pub const CACHE_LIMIT: usize = 16;
+
+/// Freed blocks kept by one processor, so most small allocations do not
+/// take the allocator lock.
+struct BlockCache {
+    list_heads: [Option<&'static mut Node>; BLOCK_SIZES.len()],
+    lens: [usize; BLOCK_SIZES.len()],
+}
+
+impl BlockCache {
+    const fn new() -> Self {
+        const EMPTY: Option<&'static mut Node> = None;
+
+        BlockCache {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            lens: [0; BLOCK_SIZES.len()],
+        }
+    }
+
+    fn pop(&mut self, index: usize) -> Option<*mut u8> {
+        let node = self.list_heads[index].take()?;
+        self.list_heads[index] = node.next.take();
+        self.lens[index] -= 1;
+        Some(node as *mut Node as *mut u8)
+    }
+
+    /// Returns `false` if the cache for this size is full.
+    unsafe fn push(&mut self, index: usize, ptr: *mut u8) -> bool {
+        if self.lens[index] == CACHE_LIMIT {
+            return false;
+        }
+        self.list_heads[index] = Some(push_node(self.list_heads[index].take(), ptr));
+        self.lens[index] += 1;
+        true
+    }
+}
+
+crate::cpu_local! {
+    static CACHES: UnsafeCell<BlockCache> = UnsafeCell::new(BlockCache::new());
+}
+
+/// The calling CPU's cache. Interrupts have to stay disabled while it is
+/// used, an allocating interrupt handler would otherwise see it half updated.
+unsafe fn cache() -> &'static mut BlockCache {
+    &mut *CACHES.get().get()
+}
+
+/// Number of free `block_size` blocks in the calling CPU's cache.
+pub fn cached_blocks(block_size: usize) -> usize {
+    match BLOCK_SIZES.iter().position(|&s| s == block_size) {
+        Some(index) => interrupts::without_interrupts(|| unsafe { cache() }.lens[index]),
+        None => 0,
+    }
+}
+
+/// Turns the free block at `ptr` into a list node in front of `next`.
+unsafe fn push_node(next: Option<&'static mut Node>, ptr: *mut u8) -> &'static mut Node {
+    let new_node_ptr = ptr as *mut Node;
+    new_node_ptr.write(Node { next });
+    &mut *new_node_ptr
+}
+
 fn list_index(layout: &Layout) -> Option<usize> {
     let required_block_size = layout.size().max(layout.align());
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
@@ -44,46 +109,54 @@ fn list_index(layout: &Layout) -> Option<usize> {
 
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAlocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.lock();
-        match list_index(&layout) {
-            Some(index) => {
-                match allocator.list_heads[index].take() {
-                    Some(Node) => {
-                        allocator.list_heads[index] = Node.next.take();
-                        Node as *mut Node as *mut u8
-                    }
-                    None => {
-                        // no block exists in list => allocate new block
-                        let block_size = BLOCK_SIZES[index];
-                        // only works if all block sizes are a power of 2
-                        let block_align = block_size;
-                        let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                        allocator.fallback_alloc(layout)
+        // the scheduler allocates from the timer interrupt, so the heap lock
+        // must never be held with interrupts enabled
+        interrupts::without_interrupts(|| {
+            if let Some(block) = list_index(&layout).and_then(|index| cache().pop(index)) {
+                return block;
+            }
+            let mut allocator = self.lock();
+            match list_index(&layout) {
+                Some(index) => {
+                    match allocator.list_heads[index].take() {
+                        Some(Node) => {
+                            allocator.list_heads[index] = Node.next.take();
+                            Node as *mut Node as *mut u8
+                        }
+                        None => {
+                            // no block exists in list => allocate new block
+                            let block_size = BLOCK_SIZES[index];
+                            // only works if all block sizes are a power of 2
+                            let block_align = block_size;
+                            let layout = Layout::from_size_align(block_size, block_align).unwrap();
+                            allocator.fallback_alloc(layout)
+                        }
                     }
                 }
+                None => allocator.fallback_alloc(layout),
             }
-            None => allocator.fallback_alloc(layout),
-        }
+        })
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let mut allocator = self.lock();
-        match list_index(&layout) {
-            Some(index) => {
-                let new_node = Node {
-                    next: allocator.list_heads[index].take(),
-                };
-                // verify that block has size and alignment required for storing Node
-                assert!(mem::size_of::<Node>() <= BLOCK_SIZES[index]);
-                assert!(mem::align_of::<Node>() <= BLOCK_SIZES[index]);
-                let new_node_ptr = ptr as *mut Node;
-                new_node_ptr.write(new_node);
-                allocator.list_heads[index] = Some(&mut *new_node_ptr);
-            }
-            None => {
-                let ptr = NonNull::new(ptr).unwrap();
-                allocator.fallback_allocator.deallocate(ptr, layout);
+        interrupts::without_interrupts(|| {
+            match list_index(&layout) {
+                Some(index) => {
+                    // verify that block has size and alignment required for storing Node
+                    assert!(mem::size_of::<Node>() <= BLOCK_SIZES[index]);
+                    assert!(mem::align_of::<Node>() <= BLOCK_SIZES[index]);
+                    if cache().push(index, ptr) {
+                        return;
+                    }
+                    let mut allocator = self.lock();
+                    let next = allocator.list_heads[index].take();
+                    allocator.list_heads[index] = Some(push_node(next, ptr));
+                }
+                None => {
+                    let ptr = NonNull::new(ptr).unwrap();
+                    self.lock().fallback_allocator.deallocate(ptr, layout);
+                }
             }
-        }
+        })
     }
 }
@@ -0,0 +1,110 @@
+//! Local APIC of the running CPU, used for inter-processor interrupts.
+//!
+//! Device interrupts still come from the 8259 PIC, which the firmware wires
+//! to the bootstrap processor's LINT0, so the PIC handlers keep sending
+//! their end of interrupt there.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{registers::model_specific::Msr, PhysAddr};
+
+use crate::memory;
+
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const APIC_BASE_MSR: u32 = 0x1B;
+
+const ID: usize = 0x20;
+const TASK_PRIORITY: usize = 0x80;
+const END_OF_INTERRUPT: usize = 0xB0;
+const SPURIOUS: usize = 0xF0;
+const ICR_LOW: usize = 0x300;
+const ICR_HIGH: usize = 0x310;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+const DELIVERY_PENDING: u32 = 1 << 12;
+const LEVEL_ASSERT: u32 = 1 << 14;
+const DELIVERY_NMI: u32 = 0b100 << 8;
+const DELIVERY_INIT: u32 = 0b101 << 8;
+const DELIVERY_STARTUP: u32 = 0b110 << 8;
+const ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+/// Virtual address of the register page, mapped by the first [`init`].
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Apic(u32),
+    AllButSelf,
+}
+
+/// Maps the register page on first use and enables the local APIC of the
+/// calling CPU. Every processor calls this once.
+pub fn init() {
+    if BASE.load(Ordering::Acquire) == 0 {
+        let phys = unsafe { Msr::new(APIC_BASE_MSR).read() } & 0x000F_FFFF_FFFF_F000;
+        let virt = memory::map_mmio(PhysAddr::new(phys), 4096).expect("cannot map the local APIC");
+        BASE.store(virt.as_u64(), Ordering::Release);
+    }
+    unsafe {
+        write(TASK_PRIORITY, 0);
+        write(SPURIOUS, SOFTWARE_ENABLE | u32::from(SPURIOUS_VECTOR));
+    }
+}
+
+/// APIC id of the calling CPU.
+pub fn id() -> u32 {
+    unsafe { read(ID) >> 24 }
+}
+
+/// Acknowledges an interrupt delivered by the local APIC, i.e. an IPI.
+pub fn end_of_interrupt() {
+    unsafe { write(END_OF_INTERRUPT, 0) };
+}
+
+pub fn send_ipi(destination: Destination, vector: u8) {
+    send(destination, u32::from(vector));
+}
+
+/// Sends a non-maskable interrupt, which arrives even while the target runs
+/// with interrupts disabled.
+pub fn send_nmi(destination: Destination) {
+    send(destination, DELIVERY_NMI | LEVEL_ASSERT);
+}
+
+/// First half of the startup sequence: resets the processor.
+pub fn send_init(apic_id: u32) {
+    send(Destination::Apic(apic_id), DELIVERY_INIT | LEVEL_ASSERT);
+}
+
+/// Starts the processor in real mode at `page * 4096`.
+pub fn send_startup(apic_id: u32, page: u8) {
+    send(
+        Destination::Apic(apic_id),
+        DELIVERY_STARTUP | LEVEL_ASSERT | u32::from(page),
+    );
+}
+
+fn send(destination: Destination, command: u32) {
+    let (high, shorthand) = match destination {
+        Destination::Apic(apic_id) => (apic_id << 24, 0),
+        Destination::AllButSelf => (0, ALL_EXCLUDING_SELF),
+    };
+    // both halves have to be written without another IPI in between
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        write(ICR_HIGH, high);
+        write(ICR_LOW, command | shorthand);
+        while read(ICR_LOW) & DELIVERY_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    });
+}
+
+unsafe fn read(register: usize) -> u32 {
+    let base = BASE.load(Ordering::Relaxed) as usize;
+    core::ptr::read_volatile((base + register) as *const u32)
+}
+
+unsafe fn write(register: usize, value: u32) {
+    let base = BASE.load(Ordering::Relaxed) as usize;
+    core::ptr::write_volatile((base + register) as *mut u32, value);
+}
@@ -0,0 +1,83 @@
+use core::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+pub const QUEUE_SIZE: usize = 128;
+
+/// Single-producer single-consumer ring buffer of bytes.
+///
+/// Used to hand input from an interrupt handler (the only producer) to a
+/// single consumer outside interrupt context without taking a lock.
+pub struct ByteQueue {
+    buffer: [AtomicU8; QUEUE_SIZE],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+impl ByteQueue {
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicU8 = AtomicU8::new(0);
+        ByteQueue {
+            buffer: [ZERO; QUEUE_SIZE],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Appends a byte, counting it as dropped if the queue is full.
+    pub fn push(&self, byte: u8) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % QUEUE_SIZE;
+        if next == self.head.load(Ordering::Acquire) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        self.buffer[tail].store(byte, Ordering::Relaxed);
+        self.tail.store(next, Ordering::Release);
+        true
+    }
+
+    pub fn pop(&self) -> Option<u8> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let byte = self.buffer[head].load(Ordering::Relaxed);
+        self.head.store((head + 1) % QUEUE_SIZE, Ordering::Release);
+        Some(byte)
+    }
+
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail + QUEUE_SIZE - head) % QUEUE_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+
+    /// Number of bytes lost because the consumer fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for ByteQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test_case]
+fn test_byte_queue_overflow() {
+    let queue = ByteQueue::new();
+    for i in 0..QUEUE_SIZE - 1 {
+        assert!(queue.push(i as u8));
+    }
+    assert!(!queue.push(0xff));
+    assert_eq!(queue.dropped(), 1);
+    assert_eq!(queue.pop(), Some(0));
+    assert!(queue.push(0xff));
+}
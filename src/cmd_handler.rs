@@ -1,53 +1,263 @@
 use alloc::{string::{String, ToString}, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    mem,
+};
+use x86_64::instructions::interrupts;
 
 use crate::{
-    filesystem::file_tree::{self, fs_system, insert_content, list_files, File, Node},
-    print, println,
+    config::CONFIG,
+    exec::{self, ExecError},
+    filesystem::file_tree::{self, fs_system, list_files, FsError},
+    gdbstub,
+    ipc::{self, PipeReader, PipeWriter, Stdio},
+    keyboard::Keymap,
+    line_editor, logger, print,
+    serial::{self, uart::Parity},
+    signal::{self, Signal},
+    smp,
+    task::timer,
+    thread::{self, policy, ExitStatus, KillError, NiceError, ThreadId, WaitError},
     vga_buffer::{self, WRITER},
 };
 
-pub fn handle_cmd(command: &mut String) {
-    let comm: String;
-    let mut rest: String = String::new();
-    if let Some(cmd) = command.find(' ') {
-        comm = command[0..cmd].as_mut().to_string();
-        rest = command[cmd + 1..command.len()].to_string()
-    } else {
-        comm = command.to_string();
-    }
-
-    match comm.as_str() {
-        "help" => print!("\nthis is help"),
-        "sayhi" => say_hi(&rest),
-        "clear" => WRITER.lock().clear_screen(),
-        "touch" => make_file(rest),
-        "ls" => list_files(),
-        "hash" => {
-            let head = fs_system.lock().tree_head.nodes.clone();
-            file_tree::fs_system.lock().seriliaze(head, None);
+/// Where a command writes: the screen, or the next command of a pipeline.
+pub enum Stdout {
+    Console,
+    Pipe(PipeWriter),
+}
+
+impl fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self {
+            Stdout::Console => {
+                print!("{}", s);
+                Ok(())
+            }
+            Stdout::Pipe(pipe) => pipe.write_str(s),
+        }
+    }
+}
+
+/// Runs a line typed at the shell. Returns false if the command goes on as a
+/// foreground job, which prints the prompt itself once it is done.
+pub fn handle_cmd(command: &mut String) -> bool {
+    // `cmd &` runs the command on its own thread so the shell stays responsive
+    if let Some(background) = command.strip_suffix(" &") {
+        let background = background.to_string();
+        let id = thread::spawn(&background.clone(), move || run_command(&background, false));
+        print!("\n[{}]", id.as_u64());
+        return true;
+    }
+    // this runs on the executor, which must not block while the shell waits
+    if command.contains('|') {
+        if pipeline_stages(command).is_none() {
+            return true;
+        }
+        let command = command.to_string();
+        run_foreground(&command.clone(), move || run_pipeline(&command, true));
+        return false;
+    }
+    let (comm, rest) = split_command(command);
+    if comm == "wait" {
+        return wait_in_foreground(rest);
+    }
+    run_command(command, true);
+    true
+}
+
+/// Runs `job` on a thread of its own, which has the console until it is
+/// done, see [`line_editor::start_job`].
+fn run_foreground<F>(name: &str, job: F) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    line_editor::start_job();
+    thread::spawn(name, move || {
+        job();
+        line_editor::finish_job();
+    })
+}
+
+/// Runs `command`. Programs the shell waits for in the `foreground` are
+/// interrupted by Ctrl+C.
+fn run_command(command: &str, foreground: bool) {
+    if command.contains('|') {
+        run_pipeline(command, foreground);
+        return;
+    }
+
+    let (comm, rest) = split_command(command);
+    if run_builtin(comm, rest, None, &mut Stdout::Console).is_none() {
+        run_program(comm, rest);
+    }
+}
+
+fn split_command(command: &str) -> (&str, &str) {
+    match command.find(' ') {
+        Some(space) => (&command[..space], &command[space + 1..]),
+        None => (command, ""),
+    }
+}
+
+/// Runs the built-in command `comm`, or returns `None` if there is none by
+/// that name. `stdin` is the output of the previous command in a pipeline.
+fn run_builtin(
+    comm: &str,
+    rest: &str,
+    stdin: Option<&PipeReader>,
+    out: &mut Stdout,
+) -> Option<fmt::Result> {
+    let result = match comm {
+        "help" => write!(out, "\nthis is help"),
+        "sayhi" => say_hi(rest, out),
+        "clear" => {
+            WRITER.lock().clear_screen();
+            Ok(())
         }
-        "mkdir" => {
-            let node = Node::new(rest);
-            fs_system.lock().cur_node.lock().nodes.push(node);
+        "touch" => match file_tree::touch(rest) {
+            Ok(()) => Ok(()),
+            Err(err) => write!(out, "\ntouch: {}: {}", rest, fs_error(err)),
+        },
+        "ls" => ls(rest, out),
+        "grep" => grep(rest, stdin, out),
+        "keymap" => keymap(rest, out),
+        "threads" => list_threads(out),
+        "ps" => ps(out),
+        "kill" => kill(rest, out),
+        "nice" => nice(rest, out),
+        "renice" => renice(rest, out),
+        "wait" => wait(rest, out),
+        "cpus" => cpus(out),
+        "dmesg" => logger::dmesg()
+            .iter()
+            .try_for_each(|line| write!(out, "\n{}", line)),
+        "loglevel" => log_level(rest, out),
+        "stty" => stty(rest, out),
+        "logsink" => match logger::Sink::from_name(rest) {
+            Some(sink) => {
+                logger::set_sink(sink);
+                Ok(())
+            }
+            None => write!(out, "\nusage: logsink none|vga|serial|both"),
+        },
+        "gdb" => {
+            let result = write!(out, "\nwaiting for gdb on COM2");
+            gdbstub::breakpoint();
+            result
         }
+        "hash" => {
+            let hash = {
+                let tree = fs_system.lock();
+                tree.seriliaze(tree.root())
+            };
+            write!(out, "\n{}", hash)
+        }
+        "mkdir" => match file_tree::make_dir(rest) {
+            Ok(()) => Ok(()),
+            Err(err) => write!(out, "\nmkdir: {}: {}", rest, fs_error(err)),
+        },
         "cd" => {
-            unsafe { fs_system.force_unlock() };
-            fs_system.lock().change_node(rest)
+            let path = if rest.is_empty() { "/" } else { rest };
+            let result = fs_system.lock().change_node(path);
+            match result {
+                Ok(()) => Ok(()),
+                Err(err) => write!(out, "\ncd: {}: {}", rest, fs_error(err)),
+            }
         }
-        "list" => {
-            let names: Vec<String> = fs_system
-                .lock()
-                .cur_node
-                .lock()
-                .nodes
-                .iter()
-                .map(|x| x.dir_name.clone())
-                .collect();
+        "pwd" => write!(out, "\n{}", file_tree::current_path()),
+        "ln" => symlink(rest, out),
+        "list" => file_tree::dir_names()
+            .iter()
+            .try_for_each(|x| write!(out, "\n{}", x)),
 
-            names.iter().map(|x| println!("{}", x));
-        }
+        _default => return None,
+    };
+    Some(result)
+}
 
-        _default => print!("\ncommand not found"),
+/// Starts the user program `name` from the current directory without
+/// waiting for it.
+fn run_program(name: &str, args: &str) {
+    let args: Vec<&str> = args.split(' ').filter(|arg| !arg.is_empty()).collect();
+    match exec::spawn(name, &args, &[]) {
+        Ok(id) => print!("\n[{}] {}", id.as_u64(), name),
+        Err(ExecError::NotFound) => print!("\ncommand not found"),
+        Err(err) => print!("\n{}: {:?}", name, err),
+    }
+}
+
+/// The commands of a pipeline, or `None` after reporting an empty one.
+fn pipeline_stages(command: &str) -> Option<Vec<&str>> {
+    let stages: Vec<&str> = command.split('|').map(str::trim).collect();
+    if stages.iter().any(|stage| stage.is_empty()) {
+        print!("\nsyntax error near `|`");
+        return None;
+    }
+    Some(stages)
+}
+
+/// `a | b | c`: every command runs on its own thread with its output piped
+/// into the next one, the last one writes to the console. Returns once all
+/// of them are done.
+fn run_pipeline(command: &str, foreground: bool) {
+    let stages = match pipeline_stages(command) {
+        Some(stages) => stages,
+        None => return,
+    };
+
+    let mut stdin = None;
+    let mut workers = Vec::new();
+    for (index, stage) in stages.iter().enumerate() {
+        let (out, next) = if index == stages.len() - 1 {
+            (Stdout::Console, None)
+        } else {
+            let (writer, reader) = ipc::pipe();
+            (Stdout::Pipe(writer), Some(reader))
+        };
+        let input = mem::replace(&mut stdin, next);
+        let stage = stage.to_string();
+        workers.push(thread::spawn(&stage.clone(), move || {
+            run_stage(&stage, input, out, foreground)
+        }));
+    }
+    for worker in workers {
+        thread::join(worker);
+    }
+}
+
+/// Runs one command of a pipeline to completion, waiting for it if it is a
+/// program.
+fn run_stage(command: &str, stdin: Option<PipeReader>, mut out: Stdout, foreground: bool) {
+    let (comm, rest) = split_command(command);
+    if run_builtin(comm, rest, stdin.as_ref(), &mut out).is_some() {
+        return;
+    }
+
+    // the first program gets an empty input rather than the keyboard, the
+    // line editor drops keys while the shell waits for the pipeline
+    let stdin = stdin.unwrap_or_else(|| ipc::pipe().1);
+    let stdio = Stdio {
+        stdin: Some(stdin),
+        stdout: match out {
+            Stdout::Console => None,
+            Stdout::Pipe(pipe) => Some(pipe),
+        },
+    };
+    let args: Vec<&str> = rest.split(' ').filter(|arg| !arg.is_empty()).collect();
+    match exec::spawn_with_stdio(comm, &args, &[], stdio) {
+        Ok(id) => {
+            if foreground {
+                signal::add_foreground(id);
+            }
+            let status = thread::wait(id);
+            signal::remove_foreground(id);
+            if let Ok(status @ (ExitStatus::Killed | ExitStatus::Signaled(_))) = status {
+                print!("\n{}: {}", comm, status);
+            }
+        }
+        Err(ExecError::NotFound) => print!("\n{}: command not found", comm),
+        Err(err) => print!("\n{}: {:?}", comm, err),
     }
 }
 
@@ -58,19 +268,381 @@ pub fn handle_prefix_action(key: &str) {
     }
 }
 
-fn say_hi(command: &String) {
+fn say_hi(command: &str, out: &mut Stdout) -> fmt::Result {
     if command.is_empty() {
-        print!("\nwrong args")
+        write!(out, "\nwrong args")?;
     }
     WRITER.lock().change_color(vga_buffer::Color::Yellow);
 
-    print!("\nZlatovlas (god): ");
+    write!(out, "\nZlatovlas (god): ")?;
 
     WRITER.lock().change_color(vga_buffer::Color::Pink);
 
-    print!("{}", command);
+    write!(out, "{}", command)
+}
+
+fn keymap(name: &str, out: &mut Stdout) -> fmt::Result {
+    if name.is_empty() {
+        write!(out, "\ncurrent: {}\navailable:", CONFIG.keymap().name())?;
+        for keymap in Keymap::ALL.iter() {
+            write!(out, " {}", keymap.name())?;
+        }
+        return Ok(());
+    }
+
+    match Keymap::from_name(name) {
+        Some(keymap) => {
+            CONFIG.set_keymap(keymap);
+            write!(out, "\nkeymap set to {}", keymap.name())
+        }
+        None => write!(out, "\nunknown keymap: {}", name),
+    }
+}
+
+/// `loglevel <level> [module]`
+fn log_level(args: &str, out: &mut Stdout) -> fmt::Result {
+    let mut args = args.split(' ').filter(|arg| !arg.is_empty());
+    match args.next().and_then(logger::parse_level) {
+        Some(level) => {
+            logger::set_level(args.next(), level);
+            Ok(())
+        }
+        None => write!(
+            out,
+            "\nusage: loglevel off|error|warn|info|debug|trace [module]"
+        ),
+    }
+}
+
+/// `stty [comN [baud N] [bits 5-8] [parity none|odd|even|mark|space]
+/// [stop 1|2] [crtscts|-crtscts]]`
+fn stty(args: &str, out: &mut Stdout) -> fmt::Result {
+    let mut args = args.split(' ').filter(|arg| !arg.is_empty());
+    let index = match args.next() {
+        None => {
+            for index in 0..serial::uart::PORT_COUNT {
+                if let Some(port) = serial::port(index) {
+                    // not holding the lock while printing, COM1 mirrors the console
+                    let (name, config) = {
+                        let port = port.lock();
+                        (port.name(), port.config())
+                    };
+                    write!(out, "\n{}: {}", name, config)?;
+                }
+            }
+            return Ok(());
+        }
+        Some(name) => match name.strip_prefix("com").and_then(|n| n.parse::<usize>().ok()) {
+            Some(number @ 1..=4) => number - 1,
+            _ => return write!(out, "\nunknown port: {}", name),
+        },
+    };
+    let port = match serial::port(index) {
+        Some(port) => port,
+        None => return write!(out, "\ncom{} not present", index + 1),
+    };
+
+    let mut config = port.lock().config();
+    while let Some(setting) = args.next() {
+        let valid = match setting {
+            "crtscts" => {
+                config.flow_control = true;
+                true
+            }
+            "-crtscts" => {
+                config.flow_control = false;
+                true
+            }
+            "baud" => args.next().and_then(|v| v.parse().ok()).map(|v| config.baud = v).is_some(),
+            "bits" => args.next().and_then(|v| v.parse().ok()).map(|v| config.data_bits = v).is_some(),
+            "stop" => args.next().and_then(|v| v.parse().ok()).map(|v| config.stop_bits = v).is_some(),
+            "parity" => args.next().and_then(Parity::from_name).map(|v| config.parity = v).is_some(),
+            _ => false,
+        };
+        if !valid {
+            return write!(out, "\ninvalid setting: {}", setting);
+        }
+    }
+
+    let result = port.lock().set_config(config);
+    match result {
+        Ok(()) => write!(out, "\ncom{}: {}", index + 1, config),
+        Err(err) => write!(out, "\n{}", err),
+    }
+}
+
+fn list_threads(out: &mut Stdout) -> fmt::Result {
+    for info in thread::list() {
+        write!(
+            out,
+            "\n{:>4} {:<12} {:?}",
+            info.id.as_u64(),
+            info.name,
+            info.state
+        )?;
+    }
+    Ok(())
+}
+
+/// Process status: every thread with its parent, nice value, time spent
+/// running and sleeping, and memory.
+fn ps(out: &mut Stdout) -> fmt::Result {
+    write!(
+        out,
+        "\n{:>5} {:>5} {:>3} {:<8} {:>7} {:>7} {:>6} NAME",
+        "PID", "PPID", "NI", "STATE", "TIME", "SLEEP", "MEM"
+    )?;
+    for info in thread::list() {
+        let parent = match info.parent {
+            Some(parent) => parent.as_u64().to_string(),
+            None => "-".to_string(),
+        };
+        let centis = info.cpu_ticks * 100 / timer::TICKS_PER_SECOND;
+        let sleep_centis = info.sleep_ticks * 100 / timer::TICKS_PER_SECOND;
+        write!(
+            out,
+            "\n{:>5} {:>5} {:>3} {:<8} {:>4}.{:02} {:>4}.{:02} {:>5}K {}",
+            info.id.as_u64(),
+            parent,
+            info.nice,
+            info.state.name(),
+            centis / 100,
+            centis % 100,
+            sleep_centis / 100,
+            sleep_centis % 100,
+            info.memory / 1024,
+            info.name
+        )?;
+    }
+    Ok(())
+}
+
+fn parse_pid(args: &str) -> Option<ThreadId> {
+    let pid = args.trim().parse::<u64>().ok()?;
+    thread::list()
+        .into_iter()
+        .map(|info| info.id)
+        .find(|id| id.as_u64() == pid)
 }
 
-fn make_file(params: String) {
-    insert_content(File::new(params, String::new()));
+/// `kill [-SIG|-N] <pid>` sends SIGTERM or the given signal.
+fn kill(args: &str, out: &mut Stdout) -> fmt::Result {
+    let mut args = args.split(' ').filter(|arg| !arg.is_empty());
+    let (signal, pid) = match (args.next(), args.next()) {
+        (Some(flag), Some(pid)) if flag.starts_with('-') => (parse_signal(&flag[1..]), pid),
+        (Some(pid), None) => (Some(Signal::SIGTERM), pid),
+        _ => (None, ""),
+    };
+    let (signal, id) = match (signal, parse_pid(pid)) {
+        (Some(signal), Some(id)) => (signal, id),
+        _ => return write!(out, "\nusage: kill [-SIG|-N] <pid>"),
+    };
+    match thread::send_signal(id, signal) {
+        Ok(()) => Ok(()),
+        Err(KillError::NoSuchThread) => write!(out, "\nno such process"),
+        Err(KillError::KernelThread) => write!(out, "\ncannot kill kernel thread {}", id.as_u64()),
+    }
+}
+
+/// `INT`, `SIGINT` or `2`.
+fn parse_signal(arg: &str) -> Option<Signal> {
+    match arg.parse::<u64>() {
+        Ok(number) => Signal::from_number(number),
+        Err(_) => Signal::from_name(arg),
+    }
+}
+
+fn parse_nice(arg: Option<&str>) -> Option<i8> {
+    arg?.parse::<i8>()
+        .ok()
+        .filter(|&nice| policy::is_valid_nice(nice))
+}
+
+/// `nice <n> <command>`: runs the command in the background on a thread with
+/// nice value `n`, which programs it starts inherit.
+fn nice(args: &str, out: &mut Stdout) -> fmt::Result {
+    let (value, command) = split_command(args);
+    let nice = match parse_nice(Some(value)) {
+        Some(nice) if !command.trim().is_empty() => nice,
+        _ => return write!(out, "\nusage: nice <-20..19> <command>"),
+    };
+    let command = command.trim().to_string();
+    // the thread must not run before its nice value is set
+    let id = interrupts::without_interrupts(|| {
+        let id = thread::spawn(&command.clone(), move || {
+            run_stage(&command, None, Stdout::Console, false)
+        });
+        thread::set_nice(id, nice).expect("thread just spawned");
+        id
+    });
+    write!(out, "\n[{}]", id.as_u64())
+}
+
+/// `renice <n> <pid>`
+fn renice(args: &str, out: &mut Stdout) -> fmt::Result {
+    let mut args = args.split(' ').filter(|arg| !arg.is_empty());
+    let nice = parse_nice(args.next());
+    let id = args.next().and_then(parse_pid);
+    let (nice, id) = match (nice, id) {
+        (Some(nice), Some(id)) => (nice, id),
+        _ => return write!(out, "\nusage: renice <-20..19> <pid>"),
+    };
+    match thread::set_nice(id, nice) {
+        Ok(()) => write!(out, "\n{}: nice {}", id.as_u64(), nice),
+        Err(NiceError::NoSuchThread) => write!(out, "\nno such process"),
+        Err(NiceError::OutOfRange) => write!(out, "\ninvalid nice value"),
+    }
+}
+
+/// `wait <pid>`: blocks until the program exits and reports how. Ctrl+C
+/// interrupts the program meanwhile.
+fn wait(args: &str, out: &mut Stdout) -> fmt::Result {
+    match parse_pid(args) {
+        Some(id) => wait_for(id, out),
+        None => write!(out, "\nusage: wait <pid>"),
+    }
+}
+
+/// `wait` typed at the shell: a foreground job takes over the program from
+/// the shell and waits for it. Returns whether the shell is done already.
+fn wait_in_foreground(args: &str) -> bool {
+    let id = match parse_pid(args) {
+        Some(id) => id,
+        None => {
+            print!("\nusage: wait <pid>");
+            return true;
+        }
+    };
+    // the job must not wait before it is the parent; if the hand-over fails
+    // its wait reports why
+    interrupts::without_interrupts(|| {
+        let job = run_foreground("wait", move || {
+            let _ = wait_for(id, &mut Stdout::Console);
+        });
+        let _ = thread::hand_over_child(id, job);
+    });
+    false
+}
+
+fn wait_for(id: ThreadId, out: &mut Stdout) -> fmt::Result {
+    signal::add_foreground(id);
+    let status = thread::wait(id);
+    signal::remove_foreground(id);
+    match status {
+        Ok(status) => write!(out, "\n[{}] {}", id.as_u64(), status),
+        Err(WaitError::NoSuchThread) => write!(out, "\nno such process"),
+        Err(WaitError::NotAChild) => write!(out, "\n{} is not a child of the shell", id.as_u64()),
+        // the shell gets no signals and names the child
+        Err(err @ (WaitError::NoChildren | WaitError::Interrupted)) => {
+            write!(out, "\nwait failed: {:?}", err)
+        }
+    }
+}
+
+/// Processors with their state and the IPIs they handled.
+fn cpus(out: &mut Stdout) -> fmt::Result {
+    write!(
+        out,
+        "\n{:>3} {:>4} {:<8} {:>6} {:>7}",
+        "CPU", "APIC", "STATE", "TLB", "RESCHED"
+    )?;
+    for cpu in smp::cpus() {
+        write!(
+            out,
+            "\n{:>3} {:>4} {:<8} {:>6} {:>7}",
+            cpu.id(),
+            cpu.apic_id(),
+            cpu.state().name(),
+            cpu.tlb_shootdowns(),
+            cpu.reschedules()
+        )?;
+    }
+    Ok(())
+}
+
+/// `ls [path]`: the entries of a directory, the current one by default,
+/// highlighted on the screen and one per line when piped.
+fn ls(args: &str, out: &mut Stdout) -> fmt::Result {
+    let path = if args.is_empty() { "." } else { args };
+    let result = match *out {
+        Stdout::Console => list_files(path),
+        Stdout::Pipe(_) => match file_tree::entry_names(path) {
+            Ok(names) => return names.iter().try_for_each(|name| write!(out, "\n{}", name)),
+            Err(err) => Err(err),
+        },
+    };
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) => write!(out, "\nls: {}: {}", path, fs_error(err)),
+    }
+}
+
+/// `ln -s <target> <name>`: creates a symbolic link. Hard links are not
+/// supported.
+fn symlink(args: &str, out: &mut Stdout) -> fmt::Result {
+    let args: Vec<&str> = args.split(' ').filter(|arg| !arg.is_empty()).collect();
+    let (target, name) = match args[..] {
+        ["-s", target, name] => (target, name),
+        _ => return write!(out, "\nusage: ln -s <target> <name>"),
+    };
+    match file_tree::make_symlink(target, name) {
+        Ok(()) => Ok(()),
+        Err(err) => write!(out, "\nln: {}: {}", name, fs_error(err)),
+    }
+}
+
+/// `grep <pattern> [file]`: the lines of `file`, or of the piped input,
+/// containing `pattern`.
+fn grep(args: &str, stdin: Option<&PipeReader>, out: &mut Stdout) -> fmt::Result {
+    let mut args = args.split(' ').filter(|arg| !arg.is_empty());
+    let (pattern, file) = match (args.next(), args.next(), stdin) {
+        (Some(pattern), Some(file), _) => match file_tree::file_contents(file) {
+            Some(contents) => (pattern, Some(contents)),
+            None => return write!(out, "\ngrep: {}: no such file", file),
+        },
+        (Some(pattern), None, Some(_)) => (pattern, None),
+        _ => return write!(out, "\nusage: grep <pattern> [file]"),
+    };
+    let mut matching = |line: &[u8]| {
+        let line = String::from_utf8_lossy(line);
+        if !line.is_empty() && line.contains(pattern) {
+            write!(out, "\n{}", line)
+        } else {
+            Ok(())
+        }
+    };
+
+    if let Some(contents) = file {
+        return contents
+            .split(|&byte| byte == b'\n')
+            .try_for_each(&mut matching);
+    }
+    let stdin = stdin.unwrap();
+    let mut line = Vec::new();
+    let mut buf = [0; 256];
+    loop {
+        let count = stdin.read(&mut buf).unwrap_or(0);
+        if count == 0 {
+            break;
+        }
+        for &byte in &buf[..count] {
+            if byte == b'\n' {
+                matching(&line)?;
+                line.clear();
+            } else {
+                line.push(byte);
+            }
+        }
+    }
+    matching(&line)
+}
+
+fn fs_error(err: FsError) -> &'static str {
+    match err {
+        FsError::NotFound => "no such file or directory",
+        FsError::NotADirectory => "not a directory",
+        FsError::IsADirectory => "is a directory",
+        FsError::AlreadyExists => "already exists",
+        FsError::TooManyLinks => "too many levels of symbolic links",
+    }
 }
@@ -0,0 +1,36 @@
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::keyboard::Keymap;
+
+/// Runtime kernel configuration.
+///
+/// Values are stored in atomics so they can be read from interrupt context
+/// without taking a lock.
+pub struct KernelConfig {
+    keymap: AtomicU8,
+    serial_console: AtomicBool,
+}
+
+pub static CONFIG: KernelConfig = KernelConfig {
+    keymap: AtomicU8::new(Keymap::DEFAULT as u8),
+    serial_console: AtomicBool::new(true),
+};
+
+impl KernelConfig {
+    pub fn keymap(&self) -> Keymap {
+        Keymap::from_u8(self.keymap.load(Ordering::Relaxed)).unwrap_or(Keymap::DEFAULT)
+    }
+
+    pub fn set_keymap(&self, keymap: Keymap) {
+        self.keymap.store(keymap as u8, Ordering::Relaxed);
+    }
+
+    /// Whether console output is mirrored to COM1.
+    pub fn serial_console(&self) -> bool {
+        self.serial_console.load(Ordering::Relaxed)
+    }
+
+    pub fn set_serial_console(&self, enabled: bool) {
+        self.serial_console.store(enabled, Ordering::Relaxed);
+    }
+}
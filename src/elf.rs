@@ -0,0 +1,227 @@
+//! ELF64 executables for user programs.
+//!
+//! Only statically linked `ET_EXEC` images for x86_64 are supported, and all
+//! `PT_LOAD` segments have to lie in the user slot
+//! (`memory::address_space::USER_START..USER_END`), e.g. by linking with
+//! `-Ttext-segment=0x80000000000`.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use x86_64::{
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+use crate::memory::address_space::{AddressSpace, MapError, USER_END, USER_START};
+
+const MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const CLASS_64: u8 = 2;
+const DATA_LSB: u8 = 1;
+const TYPE_EXEC: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3e;
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+pub const PT_LOAD: u32 = 1;
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    NotElf,
+    /// Valid ELF, but not a 64-bit little-endian x86_64 executable.
+    Unsupported,
+    Truncated,
+    /// A segment lies outside the user slot or overlaps itself.
+    BadSegment,
+    Map(MapError),
+}
+
+impl From<MapError> for ElfError {
+    fn from(error: MapError) -> Self {
+        ElfError::Map(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+}
+
+/// A parsed executable borrowing the file contents.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    pub entry: u64,
+    pub program_header_offset: u64,
+    pub program_headers: Vec<ProgramHeader>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, ElfError> {
+    let bytes = data.get(offset..offset + 2).ok_or(ElfError::Truncated)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, ElfError> {
+    let bytes = data.get(offset..offset + 4).ok_or(ElfError::Truncated)?;
+    let mut value = [0; 4];
+    value.copy_from_slice(bytes);
+    Ok(u32::from_le_bytes(value))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Result<u64, ElfError> {
+    let bytes = data.get(offset..offset + 8).ok_or(ElfError::Truncated)?;
+    let mut value = [0; 8];
+    value.copy_from_slice(bytes);
+    Ok(u64::from_le_bytes(value))
+}
+
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if !is_elf(data) {
+            return Err(ElfError::NotElf);
+        }
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[4] != CLASS_64
+            || data[5] != DATA_LSB
+            || u16_at(data, 16)? != TYPE_EXEC
+            || u16_at(data, 18)? != MACHINE_X86_64
+        {
+            return Err(ElfError::Unsupported);
+        }
+
+        let entry = u64_at(data, 24)?;
+        let program_header_offset = u64_at(data, 32)?;
+        let entry_size = usize::from(u16_at(data, 54)?);
+        let count = usize::from(u16_at(data, 56)?);
+        if count > 0 && entry_size < PROGRAM_HEADER_SIZE {
+            return Err(ElfError::Unsupported);
+        }
+
+        let mut program_headers = Vec::with_capacity(count);
+        for i in 0..count {
+            let base = usize::try_from(program_header_offset)
+                .ok()
+                .and_then(|offset| offset.checked_add(i * entry_size))
+                .ok_or(ElfError::Truncated)?;
+            program_headers.push(ProgramHeader {
+                kind: u32_at(data, base)?,
+                flags: u32_at(data, base + 4)?,
+                offset: u64_at(data, base + 8)?,
+                vaddr: u64_at(data, base + 16)?,
+                file_size: u64_at(data, base + 32)?,
+                mem_size: u64_at(data, base + 40)?,
+            });
+        }
+
+        Ok(Elf {
+            data,
+            entry,
+            program_header_offset,
+            program_headers,
+        })
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = &ProgramHeader> {
+        self.program_headers.iter().filter(|ph| ph.kind == PT_LOAD)
+    }
+
+    /// Where the program headers end up in memory, if a segment covers them.
+    pub fn program_headers_addr(&self) -> Option<u64> {
+        let offset = self.program_header_offset;
+        self.segments()
+            .find(|ph| offset >= ph.offset && offset < ph.offset + ph.file_size)
+            .map(|ph| ph.vaddr + (offset - ph.offset))
+    }
+
+    /// Maps every `PT_LOAD` segment into `space` and copies its file contents.
+    /// Pages shared by two segments get the union of their permissions; the
+    /// rest of each segment (`.bss`) stays zeroed.
+    pub fn load(&self, space: &mut AddressSpace) -> Result<(), ElfError> {
+        let mut pages: BTreeMap<Page, PageTableFlags> = BTreeMap::new();
+        for ph in self.segments() {
+            let end = ph
+                .vaddr
+                .checked_add(ph.mem_size)
+                .ok_or(ElfError::BadSegment)?;
+            let file_end = ph
+                .offset
+                .checked_add(ph.file_size)
+                .ok_or(ElfError::BadSegment)?;
+            if ph.vaddr < USER_START
+                || end > USER_END
+                || ph.file_size > ph.mem_size
+                || file_end > self.data.len() as u64
+            {
+                return Err(ElfError::BadSegment);
+            }
+            if ph.mem_size == 0 {
+                continue;
+            }
+
+            let mut flags = PageTableFlags::empty();
+            if ph.flags & PF_W != 0 {
+                flags |= PageTableFlags::WRITABLE;
+            }
+            if ph.flags & PF_X == 0 {
+                flags |= PageTableFlags::NO_EXECUTE;
+            }
+            let first = Page::containing_address(VirtAddr::new(ph.vaddr));
+            let last = Page::containing_address(VirtAddr::new(end - 1));
+            for page in Page::range_inclusive(first, last) {
+                pages
+                    .entry(page)
+                    .and_modify(|existing| {
+                        let no_execute = existing.contains(PageTableFlags::NO_EXECUTE)
+                            && flags.contains(PageTableFlags::NO_EXECUTE);
+                        *existing |= flags;
+                        existing.set(PageTableFlags::NO_EXECUTE, no_execute);
+                    })
+                    .or_insert(flags);
+            }
+        }
+
+        for (page, flags) in pages {
+            space.map(page, flags)?;
+        }
+        for ph in self.segments() {
+            let start = ph.offset as usize;
+            let contents = &self.data[start..start + ph.file_size as usize];
+            space.write(VirtAddr::new(ph.vaddr), contents)?;
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_rejects_malformed_images() {
+    assert!(matches!(Elf::parse(b"#!/bin/sh"), Err(ElfError::NotElf)));
+    assert!(matches!(
+        Elf::parse(b"\x7fELF\x02\x01"),
+        Err(ElfError::Truncated)
+    ));
+
+    let mut header = [0u8; HEADER_SIZE];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4] = 1; // 32-bit
+    header[5] = DATA_LSB;
+    assert!(matches!(Elf::parse(&header), Err(ElfError::Unsupported)));
+
+    header[4] = CLASS_64;
+    header[16..18].copy_from_slice(&TYPE_EXEC.to_le_bytes());
+    header[18..20].copy_from_slice(&MACHINE_X86_64.to_le_bytes());
+    header[24..32].copy_from_slice(&USER_START.to_le_bytes());
+    let elf = Elf::parse(&header).expect("header without segments");
+    assert_eq!(elf.entry, USER_START);
+    assert_eq!(elf.segments().count(), 0);
+}
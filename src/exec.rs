@@ -0,0 +1,289 @@
+//! Starting user programs stored in the filesystem.
+
+use alloc::{string::String, vec::Vec};
+use x86_64::{
+    instructions::interrupts,
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+use crate::{
+    elf::{Elf, ElfError},
+    elf::{PF_R, PF_W, PF_X, PT_LOAD},
+    filesystem::file_tree::{self, File},
+    ipc::{self, Stdio},
+    memory::address_space::{AddressSpace, MapError, USER_END, USER_START},
+    syscall,
+    thread::{
+        self,
+        context::{InterruptContext, InterruptFrame, SavedRegisters},
+        ThreadId,
+    },
+};
+
+const STACK_PAGES: u64 = 16;
+const STACK_TOP: u64 = USER_END;
+
+/// Page below the user stack, past a guard page, holding the code signal
+/// handlers return into. It calls `sigreturn`, see [`signal`](crate::signal).
+pub const SIGNAL_TRAMPOLINE: u64 = STACK_TOP - (STACK_PAGES + 2) * 4096;
+
+// auxiliary vector entries, see the System V x86_64 ABI
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+
+/// Offset of the code in a [`program_image`]: one ELF and one program header.
+const CODE_OFFSET: u64 = 64 + 56;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError {
+    NotFound,
+    Elf(ElfError),
+    Map(MapError),
+    /// `argv` and `envp` do not fit on the initial stack.
+    ArgumentsTooLong,
+}
+
+impl From<ElfError> for ExecError {
+    fn from(error: ElfError) -> Self {
+        ExecError::Elf(error)
+    }
+}
+
+impl From<MapError> for ExecError {
+    fn from(error: MapError) -> Self {
+        ExecError::Map(error)
+    }
+}
+
+/// Loads the executable `name` from the current directory into a fresh
+/// address space and starts it on a new thread. `argv[0]` is `name`.
+pub fn spawn(name: &str, args: &[&str], envp: &[&str]) -> Result<ThreadId, ExecError> {
+    spawn_with_stdio(name, args, envp, Stdio::default())
+}
+
+/// Like [`spawn`], with standard input and output connected to `stdio`.
+pub fn spawn_with_stdio(
+    name: &str,
+    args: &[&str],
+    envp: &[&str],
+    stdio: Stdio,
+) -> Result<ThreadId, ExecError> {
+    let (space, entry, stack) = load(name, args, envp)?;
+    // the pipes have to be in place before the program can write anything
+    let id = interrupts::without_interrupts(|| {
+        let id = thread::spawn_user(name, space, entry, stack);
+        if stdio.stdin.is_some() || stdio.stdout.is_some() {
+            ipc::set_stdio(id, stdio);
+        }
+        id
+    });
+    log::info!("started {} as thread {}", name, id.as_u64());
+    Ok(id)
+}
+
+/// Replaces the program running on the current thread with the executable
+/// `name`, keeping the thread, its children and its standard streams.
+/// `context` is the system call context the thread returns to user mode
+/// with; it is pointed at the entry of the new program. The old program
+/// keeps running if loading fails.
+pub fn replace_current(
+    name: &str,
+    args: &[&str],
+    envp: &[&str],
+    context: &mut InterruptContext,
+) -> Result<(), ExecError> {
+    let (space, entry, stack) = load(name, args, envp)?;
+    thread::replace_image(name, space);
+    context.regs = SavedRegisters::default();
+    context.frame = InterruptFrame {
+        rip: entry.as_u64(),
+        rsp: stack.as_u64(),
+        // interrupts enabled
+        rflags: 0x202,
+        ..context.frame
+    };
+    log::info!("thread {} now runs {}", thread::current().as_u64(), name);
+    Ok(())
+}
+
+/// Loads the executable `name` from the current directory into a fresh
+/// address space with its stack set up. Returns the address space, the
+/// entry point and the initial stack pointer.
+fn load(
+    name: &str,
+    args: &[&str],
+    envp: &[&str],
+) -> Result<(AddressSpace, VirtAddr, VirtAddr), ExecError> {
+    let image = file_tree::file_contents(name).ok_or(ExecError::NotFound)?;
+    let elf = Elf::parse(&image)?;
+
+    let mut space = AddressSpace::new()?;
+    elf.load(&mut space)?;
+    map_trampoline(&mut space)?;
+
+    let mut argv = Vec::with_capacity(args.len() + 1);
+    argv.push(name);
+    argv.extend_from_slice(args);
+    let stack = setup_stack(&mut space, &elf, &argv, envp)?;
+    Ok((space, VirtAddr::new(elf.entry), stack))
+}
+
+/// Maps the user stack and lays out `argc`, `argv`, `envp` and the auxiliary
+/// vector the way the System V ABI expects them at process entry. Returns the
+/// initial stack pointer.
+fn setup_stack(
+    space: &mut AddressSpace,
+    elf: &Elf,
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<VirtAddr, ExecError> {
+    let bottom = STACK_TOP - STACK_PAGES * 4096;
+    let first = Page::containing_address(VirtAddr::new(bottom));
+    let last = Page::containing_address(VirtAddr::new(STACK_TOP - 1));
+    for page in Page::range_inclusive(first, last) {
+        space.map(page, PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE)?;
+    }
+
+    // strings go at the very top
+    let mut sp = STACK_TOP;
+    let mut push_strings = |strings: &[&str]| -> Result<Vec<u64>, ExecError> {
+        let mut pointers = Vec::with_capacity(strings.len());
+        for s in strings {
+            let len = s.len() as u64 + 1;
+            if sp - bottom < len {
+                return Err(ExecError::ArgumentsTooLong);
+            }
+            sp -= len;
+            space.write(VirtAddr::new(sp), s.as_bytes())?;
+            space.write(VirtAddr::new(sp + len - 1), &[0])?;
+            pointers.push(sp);
+        }
+        Ok(pointers)
+    };
+    let argv_pointers = push_strings(argv)?;
+    let envp_pointers = push_strings(envp)?;
+
+    let mut words = Vec::new();
+    words.push(argv.len() as u64);
+    words.extend(argv_pointers);
+    words.push(0);
+    words.extend(envp_pointers);
+    words.push(0);
+    if let Some(phdr) = elf.program_headers_addr() {
+        words.extend([AT_PHDR, phdr]);
+    }
+    words.extend([
+        AT_PHENT,
+        56,
+        AT_PHNUM,
+        elf.program_headers.len() as u64,
+        AT_PAGESZ,
+        4096,
+        AT_ENTRY,
+        elf.entry,
+        AT_NULL,
+        0,
+    ]);
+
+    // `rsp` points at `argc` and is 16 byte aligned on entry
+    let size = words.len() as u64 * 8;
+    if (sp & !0xf) - bottom < size + 16 {
+        return Err(ExecError::ArgumentsTooLong);
+    }
+    let sp = ((sp & !0xf) - size) & !0xf;
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    space.write(VirtAddr::new(sp), &bytes)?;
+    Ok(VirtAddr::new(sp))
+}
+
+fn map_trampoline(space: &mut AddressSpace) -> Result<(), ExecError> {
+    let mut code = Vec::new();
+    code.push(0xb8); // mov eax, SYS_SIGRETURN
+    code.extend((syscall::SYS_SIGRETURN as u32).to_le_bytes());
+    code.extend([0x0f, 0x05]); // syscall
+    code.extend([0x0f, 0x0b]); // ud2, sigreturn does not return here
+
+    let page = Page::containing_address(VirtAddr::new(SIGNAL_TRAMPOLINE));
+    space.map(page, PageTableFlags::empty())?;
+    space.write(VirtAddr::new(SIGNAL_TRAMPOLINE), &code)?;
+    Ok(())
+}
+
+/// Puts the programs built into the kernel into the current directory.
+pub fn install_builtin_programs() {
+    file_tree::insert_content(File::new(String::from("hello"), hello_image()));
+}
+
+/// A minimal executable printing a greeting with `write` and calling `exit`.
+fn hello_image() -> Vec<u8> {
+    const MESSAGE: &[u8] = b"\nhello from ring 3";
+
+    let mut code = Vec::new();
+    code.push(0xb8); // mov eax, SYS_WRITE
+    code.extend((syscall::SYS_WRITE as u32).to_le_bytes());
+    code.push(0xbf); // mov edi, STDOUT
+    code.extend((syscall::STDOUT as u32).to_le_bytes());
+    code.extend([0x48, 0x8d, 0x35]); // lea rsi, [rip + message]
+    code.extend(16u32.to_le_bytes());
+    code.push(0xba); // mov edx, len
+    code.extend((MESSAGE.len() as u32).to_le_bytes());
+    code.extend([0x0f, 0x05]); // syscall
+    code.push(0xb8); // mov eax, SYS_EXIT
+    code.extend((syscall::SYS_EXIT as u32).to_le_bytes());
+    code.extend([0x31, 0xff]); // xor edi, edi
+    code.extend([0x0f, 0x05]); // syscall
+    code.extend_from_slice(MESSAGE);
+    program_image(&code, false)
+}
+
+/// Wraps position independent machine code in an executable with a single
+/// segment, entered at the first byte of `code`. With `writable` the code
+/// can keep its data next to itself.
+pub fn program_image(code: &[u8], writable: bool) -> Vec<u8> {
+    let size = CODE_OFFSET + code.len() as u64;
+    let mut flags = PF_R | PF_X;
+    if writable {
+        flags |= PF_W;
+    }
+
+    let mut image = Vec::new();
+    image.extend([0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    image.extend([0; 8]);
+    image.extend(2u16.to_le_bytes()); // ET_EXEC
+    image.extend(0x3eu16.to_le_bytes()); // x86_64
+    image.extend(1u32.to_le_bytes());
+    image.extend((USER_START + CODE_OFFSET).to_le_bytes());
+    image.extend(64u64.to_le_bytes()); // program headers
+    image.extend(0u64.to_le_bytes()); // section headers
+    image.extend(0u32.to_le_bytes());
+    image.extend(64u16.to_le_bytes());
+    image.extend(56u16.to_le_bytes());
+    image.extend(1u16.to_le_bytes());
+    image.extend([0; 6]);
+
+    image.extend(PT_LOAD.to_le_bytes());
+    image.extend(flags.to_le_bytes());
+    image.extend(0u64.to_le_bytes());
+    image.extend(USER_START.to_le_bytes());
+    image.extend(USER_START.to_le_bytes());
+    image.extend(size.to_le_bytes());
+    image.extend(size.to_le_bytes());
+    image.extend(4096u64.to_le_bytes());
+
+    image.extend_from_slice(code);
+    image
+}
+
+#[test_case]
+fn test_builtin_image_parses() {
+    let image = hello_image();
+    let elf = Elf::parse(&image).expect("builtin image is valid");
+    assert_eq!(elf.segments().count(), 1);
+    assert_eq!(elf.program_headers_addr(), Some(USER_START + 64));
+    assert_eq!(elf.entry, USER_START + 120);
+}
@@ -1,143 +1,494 @@
 use alloc::{
-    boxed::Box,
+    collections::BTreeMap,
     string::{String, ToString},
-    sync::Arc,
     vec::Vec,
 };
 use lazy_static::lazy_static;
-use spin::Mutex;
 
 use crate::{
-    print, println,
+    println,
+    sync::Mutex,
     vga_buffer::{self, WRITER},
 };
 
+/// A file handed to [`insert_content`], named by its path. Once inserted
+/// it lives on as an [`Inode`].
 #[derive(Clone, Debug)]
 pub struct File {
-    content: String,
+    content: Vec<u8>,
     name: String,
 }
 
-#[derive(Clone, Debug)]
-pub struct Node {
-    pub dir_name: String,
-    pub nodes: Vec<Node>,
-    content: Vec<File>,
-    pub prev_node: Option<Box<Node>>,
+/// Stable name of an inode, valid until the inode is removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct InodeId(u64);
+
+#[derive(Debug)]
+pub enum InodeKind {
+    File(Vec<u8>),
+    /// Children in creation order.
+    Directory(Vec<InodeId>),
+    /// Path to another inode, resolved relative to the directory holding
+    /// the link.
+    Symlink(String),
 }
 
-pub struct FileTree {
-    pub tree_head: Box<Node>,
-    pub cur_node: Arc<Mutex<Node>>,
+#[derive(Debug)]
+pub struct Inode {
+    pub name: String,
+    /// The directory holding this inode. The root is its own parent.
+    pub parent: InodeId,
+    pub kind: InodeKind,
 }
 
-lazy_static! {
-    pub static ref fs_system: Arc<Mutex<FileTree>> = Arc::new(Mutex::new(FileTree::new()));
+impl Inode {
+    pub fn is_dir(&self) -> bool {
+        matches!(self.kind, InodeKind::Directory(_))
+    }
 }
 
-pub fn insert_content(cn: File) {
-    fs_system.lock().cur_node.lock().content.push(cn);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    /// More than [`MAX_SYMLINKS`] links had to be followed, probably a loop.
+    TooManyLinks,
+}
+
+/// Most symbolic links followed while resolving one path.
+pub const MAX_SYMLINKS: usize = 8;
+
+/// The single tree of inodes. Directories refer to their children and
+/// children to their parent by id, and the current directory is just the id
+/// of one of them, so every change is made to the tree itself.
+pub struct FileTree {
+    inodes: BTreeMap<InodeId, Inode>,
+    next_id: u64,
+    root: InodeId,
+    cwd: InodeId,
+}
+
+lazy_static! {
+    pub static ref fs_system: Mutex<FileTree> = Mutex::named("fs_system", FileTree::new());
 }
 
 impl FileTree {
     pub fn new() -> Self {
-        let head = Node {
-            dir_name: "/".to_string(),
-            nodes: Vec::new(),
-            content: Vec::new(),
-            prev_node: None,
-        };
+        let root = InodeId(0);
+        let mut inodes = BTreeMap::new();
+        inodes.insert(
+            root,
+            Inode {
+                name: "/".to_string(),
+                parent: root,
+                kind: InodeKind::Directory(Vec::new()),
+            },
+        );
+        FileTree {
+            inodes,
+            next_id: 1,
+            root,
+            cwd: root,
+        }
+    }
 
-        let nd = Node {
-            dir_name: "/".to_string(),
-            nodes: Vec::new(),
-            content: Vec::new(),
-            prev_node: None,
-        };
+    pub fn root(&self) -> InodeId {
+        self.root
+    }
 
-        FileTree {
-            cur_node: Arc::new(Mutex::new(nd)),
-            tree_head: Box::new(head),
+    /// The current directory.
+    pub fn cwd(&self) -> InodeId {
+        self.cwd
+    }
+
+    pub fn inode(&self, id: InodeId) -> Option<&Inode> {
+        self.inodes.get(&id)
+    }
+
+    /// Children of the directory `dir`, in creation order.
+    pub fn children(&self, dir: InodeId) -> Result<&[InodeId], FsError> {
+        match &self.inodes.get(&dir).ok_or(FsError::NotFound)?.kind {
+            InodeKind::Directory(children) => Ok(children),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    /// The entry `name` of the directory `dir`. `.` is `dir` itself and `..`
+    /// its parent.
+    pub fn lookup(&self, dir: InodeId, name: &str) -> Result<InodeId, FsError> {
+        let children = self.children(dir)?;
+        match name {
+            "." => return Ok(dir),
+            ".." => return Ok(self.inodes[&dir].parent),
+            _ => {}
         }
+        children
+            .iter()
+            .copied()
+            .find(|child| self.inodes[child].name == name)
+            .ok_or(FsError::NotFound)
     }
 
-    pub fn seriliaze(&mut self, tree_head: Vec<Node>, cur_hash: Option<String>) {
-        let mut hash = match cur_hash {
-            Some(it) => it,
-            None => String::new(),
+    fn insert(&mut self, dir: InodeId, name: &str, kind: InodeKind) -> Result<InodeId, FsError> {
+        match self.lookup(dir, name) {
+            Err(FsError::NotFound) => {}
+            Ok(_) => return Err(FsError::AlreadyExists),
+            Err(err) => return Err(err),
+        }
+        let id = InodeId(self.next_id);
+        self.next_id += 1;
+        self.inodes.insert(
+            id,
+            Inode {
+                name: name.to_string(),
+                parent: dir,
+                kind,
+            },
+        );
+        if let InodeKind::Directory(children) = &mut self.inodes.get_mut(&dir).unwrap().kind {
+            children.push(id);
+        }
+        Ok(id)
+    }
+
+    /// Resolves `path`, absolute or relative to `start`, to an inode.
+    /// Symbolic links are followed, the last component only with
+    /// `follow_last` or a trailing slash, which also requires a directory.
+    pub fn resolve(
+        &self,
+        start: InodeId,
+        path: &str,
+        follow_last: bool,
+    ) -> Result<InodeId, FsError> {
+        let mut links = 0;
+        self.resolve_counting(start, path, follow_last, &mut links)
+    }
+
+    fn resolve_counting(
+        &self,
+        start: InodeId,
+        path: &str,
+        follow_last: bool,
+        links: &mut usize,
+    ) -> Result<InodeId, FsError> {
+        if path.is_empty() {
+            return Err(FsError::NotFound);
+        }
+        let mut current = if path.starts_with('/') {
+            self.root
+        } else {
+            start
         };
-        for n in tree_head {
-            if n.clone().nodes.is_empty() {
-                hash.push_str("(");
-                continue;
-            } else {
-                let mut files = String::new();
-                for f in n.content {
-                    let pfs = "{}/".to_string() + &f.name;
-                    files.push_str(&pfs);
-                    hash.push_str(&files);
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        let follow_last = follow_last || path.ends_with('/');
+        for (index, name) in components.iter().enumerate() {
+            let dir = current;
+            current = self.lookup(dir, name)?;
+            let last = index == components.len() - 1;
+            if let InodeKind::Symlink(target) = &self.inodes[&current].kind {
+                if !last || follow_last {
+                    *links += 1;
+                    if *links > MAX_SYMLINKS {
+                        return Err(FsError::TooManyLinks);
+                    }
+                    current = self.resolve_counting(dir, target, true, links)?;
                 }
-                return self.seriliaze(n.nodes, Some(hash));
             }
         }
+        if path.ends_with('/') && !self.inodes[&current].is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        Ok(current)
+    }
 
-        println!("{}", hash);
+    /// Splits `path` into the directory that holds its last component,
+    /// resolved from `start`, and the name of that component. Used to create
+    /// entries; paths ending in `.` or `..` name no new entry.
+    pub fn resolve_parent<'a>(
+        &self,
+        start: InodeId,
+        path: &'a str,
+    ) -> Result<(InodeId, &'a str), FsError> {
+        let trimmed = path.trim_end_matches('/');
+        let (dir, name) = match trimmed.rfind('/') {
+            Some(slash) => {
+                let dir = &trimmed[..slash];
+                let dir = if dir.is_empty() { "/" } else { dir };
+                (self.resolve(start, dir, true)?, &trimmed[slash + 1..])
+            }
+            None if trimmed.is_empty() && path.starts_with('/') => (self.root, ""),
+            None if trimmed.is_empty() => return Err(FsError::NotFound),
+            None => (start, trimmed),
+        };
+        if !self.inodes[&dir].is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        match name {
+            "" | "." | ".." => Err(FsError::AlreadyExists),
+            name => Ok((dir, name)),
+        }
     }
 
-    pub fn change_node(&mut self, location: String) {
+    /// Absolute path of `id`, following parent links up to the root.
+    pub fn path(&self, id: InodeId) -> String {
+        let mut names = Vec::new();
+        let mut current = id;
+        while current != self.root {
+            let inode = &self.inodes[&current];
+            names.push(inode.name.as_str());
+            current = inode.parent;
+        }
+        names.reverse();
+        let mut path = String::new();
+        for name in names {
+            path.push('/');
+            path.push_str(name);
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+        path
+    }
+
+    pub fn mkdir(&mut self, dir: InodeId, name: &str) -> Result<InodeId, FsError> {
+        self.insert(dir, name, InodeKind::Directory(Vec::new()))
+    }
 
-        if location == ".." {
-            let mut cur_node_guard = self.cur_node.lock();
-            if let Some(prev_node) = &cur_node_guard.prev_node {
-                let prev_node_clone = (**prev_node).clone();
-                *cur_node_guard = prev_node_clone;
+    /// Creates the symbolic link `name` in `dir` pointing at `target`, which
+    /// need not exist.
+    pub fn symlink(&mut self, dir: InodeId, name: &str, target: &str) -> Result<InodeId, FsError> {
+        self.insert(dir, name, InodeKind::Symlink(target.to_string()))
+    }
+
+    /// Creates the file `name` in `dir`, or replaces the contents of an
+    /// existing one.
+    pub fn create_file(
+        &mut self,
+        dir: InodeId,
+        name: &str,
+        content: Vec<u8>,
+    ) -> Result<InodeId, FsError> {
+        match self.lookup(dir, name) {
+            Ok(id) => {
+                *self.file_mut(id)? = content;
+                Ok(id)
             }
-            return;
+            Err(FsError::NotFound) => self.insert(dir, name, InodeKind::File(content)),
+            Err(err) => Err(err),
         }
+    }
 
-        let nds = &self.cur_node.lock().nodes.clone();
+    /// Contents of the file `id`. A symbolic link is not followed and, like
+    /// a missing inode, counts as not found.
+    pub fn file(&self, id: InodeId) -> Result<&Vec<u8>, FsError> {
+        match &self.inodes.get(&id).ok_or(FsError::NotFound)?.kind {
+            InodeKind::File(content) => Ok(content),
+            InodeKind::Directory(_) => Err(FsError::IsADirectory),
+            InodeKind::Symlink(_) => Err(FsError::NotFound),
+        }
+    }
 
-        // Iterate over nodes
-        for x in nds {
-            if location == x.dir_name {
-                let mut cur_node_guard = self.cur_node.lock();
-                *cur_node_guard = x.clone();
+    pub fn file_mut(&mut self, id: InodeId) -> Result<&mut Vec<u8>, FsError> {
+        match &mut self.inodes.get_mut(&id).ok_or(FsError::NotFound)?.kind {
+            InodeKind::File(content) => Ok(content),
+            InodeKind::Directory(_) => Err(FsError::IsADirectory),
+            InodeKind::Symlink(_) => Err(FsError::NotFound),
+        }
+    }
 
-                break;
+    /// Creates the file at `path`, relative to the current directory, or
+    /// replaces the contents of the file it names.
+    pub fn create_file_at(&mut self, path: &str, content: Vec<u8>) -> Result<InodeId, FsError> {
+        match self.resolve(self.cwd, path, true) {
+            Ok(id) => {
+                *self.file_mut(id)? = content;
+                Ok(id)
+            }
+            Err(FsError::NotFound) => {
+                let (dir, name) = self.resolve_parent(self.cwd, path)?;
+                self.create_file(dir, name, content)
             }
+            Err(err) => Err(err),
         }
     }
+
+    /// Makes the directory at `location` the current directory.
+    pub fn change_node(&mut self, location: &str) -> Result<(), FsError> {
+        let target = self.resolve(self.cwd, location, true)?;
+        if !self.inodes[&target].is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        self.cwd = target;
+        Ok(())
+    }
+
+    /// The tree below `dir` in one line: every directory is written as its
+    /// name followed by its entries in parentheses.
+    pub fn seriliaze(&self, dir: InodeId) -> String {
+        let inode = &self.inodes[&dir];
+        let mut hash = inode.name.clone();
+        match &inode.kind {
+            InodeKind::Directory(children) => {
+                hash.push('(');
+                let entries: Vec<String> = children.iter().map(|c| self.seriliaze(*c)).collect();
+                hash.push_str(&entries.join(","));
+                hash.push(')');
+            }
+            InodeKind::Symlink(target) => {
+                hash.push_str("->");
+                hash.push_str(target);
+            }
+            InodeKind::File(_) => {}
+        }
+        hash
+    }
+}
+
+impl Default for FileTree {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 impl File {
-    pub fn new(filename: String, content: String) -> Self {
+    pub fn new(filename: String, content: Vec<u8>) -> Self {
         Self {
             content,
             name: filename,
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
 }
 
-impl Node {
-    pub fn new(dir_name: String) -> Self {
-        Node {
-            dir_name,
-            nodes: Vec::new(),
-            content: Vec::new(),
-            prev_node: Some(Box::new(fs_system.lock().cur_node.lock().clone())),
-        }
+/// Stores `file` at the path given as its name, relative to the current
+/// directory, replacing the contents of an existing file.
+pub fn insert_content(file: File) {
+    let mut tree = fs_system.lock();
+    if let Err(err) = tree.create_file_at(&file.name, file.content) {
+        log::warn!("cannot create {}: {:?}", file.name, err);
     }
 }
 
-pub fn list_files() {
-    println!();
+/// Creates an empty file at `path` unless something exists there already.
+pub fn touch(path: &str) -> Result<(), FsError> {
+    let mut tree = fs_system.lock();
+    match tree.resolve(tree.cwd(), path, true) {
+        Ok(_) => Ok(()),
+        Err(FsError::NotFound) => tree.create_file_at(path, Vec::new()).map(|_| ()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Creates the directory `path`.
+pub fn make_dir(path: &str) -> Result<(), FsError> {
+    let mut tree = fs_system.lock();
+    let (dir, name) = tree.resolve_parent(tree.cwd(), path)?;
+    tree.mkdir(dir, name).map(|_| ())
+}
+
+/// Creates a symbolic link at `path` pointing at `target`.
+pub fn make_symlink(target: &str, path: &str) -> Result<(), FsError> {
+    let mut tree = fs_system.lock();
+    let (dir, name) = tree.resolve_parent(tree.cwd(), path)?;
+    tree.symlink(dir, name, target).map(|_| ())
+}
+
+/// Name of the current directory.
+pub fn current_dir_name() -> String {
+    let tree = fs_system.lock();
+    tree.inode(tree.cwd()).unwrap().name.clone()
+}
+
+/// Absolute path of the current directory.
+pub fn current_path() -> String {
+    let tree = fs_system.lock();
+    tree.path(tree.cwd())
+}
+
+/// Copies bytes of the file at `path` starting at `offset` into `buf`.
+/// Returns `None` if there is no such file.
+pub fn read_file(path: &str, offset: usize, buf: &mut [u8]) -> Option<usize> {
+    let tree = fs_system.lock();
+    let content = tree.file(tree.resolve(tree.cwd(), path, true).ok()?).ok()?;
+    let start = offset.min(content.len());
+    let count = buf.len().min(content.len() - start);
+    buf[..count].copy_from_slice(&content[start..start + count]);
+    Some(count)
+}
 
-    for f in &fs_system.lock().cur_node.lock().content {
-        write_blue(f.name.clone())
+/// Writes `data` into the file at `path` at `offset`, growing it as needed.
+pub fn write_file(path: &str, offset: usize, data: &[u8]) -> Option<usize> {
+    let mut tree = fs_system.lock();
+    let id = tree.resolve(tree.cwd(), path, true).ok()?;
+    let content = tree.file_mut(id).ok()?;
+    if content.len() < offset + data.len() {
+        content.resize(offset + data.len(), 0);
     }
+    content[offset..offset + data.len()].copy_from_slice(data);
+    Some(data.len())
+}
+
+/// Returns a copy of the contents of the file at `path`.
+pub fn file_contents(path: &str) -> Option<Vec<u8>> {
+    let tree = fs_system.lock();
+    let id = tree.resolve(tree.cwd(), path, true).ok()?;
+    tree.file(id).ok().cloned()
+}
 
-    for d in &fs_system.lock().cur_node.lock().nodes {
-        write_blue(d.dir_name.clone())
+pub fn file_exists(path: &str) -> bool {
+    let tree = fs_system.lock();
+    let id = tree.resolve(tree.cwd(), path, true);
+    id.map_or(false, |id| tree.file(id).is_ok())
+}
+
+pub fn list_files(path: &str) -> Result<(), FsError> {
+    let names = entry_names(path)?;
+    println!();
+    for name in names {
+        write_blue(name)
     }
+    Ok(())
+}
+
+/// Names of the files and then the directories in the directory at `path`,
+/// or the name of the file at `path`.
+pub fn entry_names(path: &str) -> Result<Vec<String>, FsError> {
+    let tree = fs_system.lock();
+    let id = tree.resolve(tree.cwd(), path, true)?;
+    let children = match tree.children(id) {
+        Ok(children) => children,
+        Err(_) => return Ok(alloc::vec![tree.inode(id).unwrap().name.clone()]),
+    };
+    let (dirs, files): (Vec<&Inode>, Vec<&Inode>) = children
+        .iter()
+        .map(|id| tree.inode(*id).unwrap())
+        .partition(|inode| inode.is_dir());
+    Ok(files
+        .iter()
+        .chain(dirs.iter())
+        .map(|inode| inode.name.clone())
+        .collect())
+}
+
+/// Names of the directories in the current directory.
+pub fn dir_names() -> Vec<String> {
+    let tree = fs_system.lock();
+    let children = tree.children(tree.cwd()).unwrap_or(&[]);
+    children
+        .iter()
+        .map(|id| tree.inode(*id).unwrap())
+        .filter(|inode| inode.is_dir())
+        .map(|inode| inode.name.clone())
+        .collect()
 }
 
 fn write_blue(args: String) {
@@ -148,3 +499,77 @@ fn write_blue(args: String) {
     WRITER.lock().write_string(&ags);
     WRITER.lock().change_color(vga_buffer::Color::White)
 }
+
+#[test_case]
+fn test_changes_below_cwd_persist() {
+    let mut tree = FileTree::new();
+    let root = tree.root();
+    let a = tree.mkdir(root, "a").unwrap();
+    tree.change_node("a").unwrap();
+    let b = tree.mkdir(tree.cwd(), "b").unwrap();
+    tree.change_node("..").unwrap();
+    assert_eq!(tree.cwd(), root);
+    // made while `a` was current, visible from the root
+    assert_eq!(tree.lookup(a, "b"), Ok(b));
+    tree.change_node("a").unwrap();
+    tree.create_file(tree.cwd(), "f", Vec::from(*b"x")).unwrap();
+    assert_eq!(tree.children(a).unwrap().len(), 2);
+    assert_eq!(tree.inode(b).unwrap().parent, a);
+    assert_eq!(tree.seriliaze(root), "/(a(b(),f))");
+}
+
+#[test_case]
+fn test_change_node_errors() {
+    let mut tree = FileTree::new();
+    let root = tree.root();
+    tree.create_file(root, "f", Vec::new()).unwrap();
+    assert_eq!(tree.change_node("f"), Err(FsError::NotADirectory));
+    assert_eq!(tree.change_node("missing"), Err(FsError::NotFound));
+    assert_eq!(tree.mkdir(root, "f"), Err(FsError::AlreadyExists));
+    // the root is its own parent
+    tree.change_node("..").unwrap();
+    assert_eq!(tree.cwd(), root);
+}
+
+#[test_case]
+fn test_resolves_paths() {
+    let mut tree = FileTree::new();
+    let root = tree.root();
+    let a = tree.mkdir(root, "a").unwrap();
+    let b = tree.mkdir(a, "b").unwrap();
+    let f = tree.create_file(b, "f", Vec::new()).unwrap();
+    assert_eq!(tree.resolve(root, "/a/b/f", true), Ok(f));
+    assert_eq!(tree.resolve(b, "../../a/./b//f", true), Ok(f));
+    assert_eq!(tree.resolve(b, "/", true), Ok(root));
+    assert_eq!(tree.resolve(root, "a/b/", true), Ok(b));
+    assert_eq!(
+        tree.resolve(root, "a/b/f/", true),
+        Err(FsError::NotADirectory)
+    );
+    assert_eq!(tree.resolve(root, "a/f/..", true), Err(FsError::NotFound));
+    assert_eq!(tree.resolve_parent(root, "a/b/g/"), Ok((b, "g")));
+    assert_eq!(
+        tree.resolve_parent(root, "/a/.."),
+        Err(FsError::AlreadyExists)
+    );
+    assert_eq!(tree.path(f), "/a/b/f");
+    assert_eq!(tree.path(root), "/");
+}
+
+#[test_case]
+fn test_follows_symlinks() {
+    let mut tree = FileTree::new();
+    let root = tree.root();
+    let a = tree.mkdir(root, "a").unwrap();
+    let f = tree.create_file(a, "f", Vec::new()).unwrap();
+    let link = tree.symlink(root, "l", "a").unwrap();
+    tree.symlink(a, "up", "../l/f").unwrap();
+    assert_eq!(tree.resolve(root, "l/f", true), Ok(f));
+    assert_eq!(tree.resolve(root, "l", false), Ok(link));
+    assert_eq!(tree.resolve(root, "l/", false), Ok(a));
+    assert_eq!(tree.resolve(root, "a/up", true), Ok(f));
+    tree.symlink(root, "loop", "loop").unwrap();
+    assert_eq!(tree.resolve(root, "loop", true), Err(FsError::TooManyLinks));
+    tree.change_node("/l").unwrap();
+    assert_eq!(tree.cwd(), a);
+}
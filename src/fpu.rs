@@ -0,0 +1,191 @@
+//! Hardware floating point for threads, enabled with the `fpu` feature.
+//!
+//! The kernel itself is built soft-float and never touches the x87, SSE or
+//! AVX registers, so their contents only have to follow the threads using
+//! them. They are switched lazily: every switch away from the thread whose
+//! state is loaded sets CR0.TS, and the next FPU instruction raises the
+//! device-not-available exception (#NM). Its handler saves the registers for
+//! the previous owner and loads the ones of the running thread, see
+//! [`thread::claim_fpu`](crate::thread::claim_fpu).
+//!
+//! `xsave` is used where available, which also covers the AVX registers, and
+//! `fxsave` otherwise.
+
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use core::{
+    arch::{asm, x86_64::__cpuid_count},
+    cell::Cell,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use x86_64::registers::{
+    control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+    xcontrol::{XCr0, XCr0Flags},
+};
+
+use crate::thread::ThreadId;
+
+const FXSAVE_SIZE: usize = 512;
+const DEFAULT_FCW: u16 = 0x037F;
+const DEFAULT_MXCSR: u32 = 0x1F80;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static XSAVE: AtomicBool = AtomicBool::new(false);
+static STATE_SIZE: AtomicUsize = AtomicUsize::new(FXSAVE_SIZE);
+
+crate::cpu_local! {
+    /// Thread whose state the FPU registers of each CPU hold.
+    static OWNER: Cell<Option<ThreadId>> = Cell::new(None);
+}
+
+/// Enables the FPU and SSE, plus AVX if the CPU has it, on the calling
+/// processor. Does nothing without the `fpu` feature, FPU instructions then
+/// keep faulting.
+pub fn init() {
+    if !cfg!(feature = "fpu") {
+        return;
+    }
+
+    let features = __cpuid_count(1, 0);
+    let has_xsave = features.ecx & (1 << 26) != 0;
+    let has_avx = features.ecx & (1 << 28) != 0;
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+        if has_xsave {
+            Cr4::update(|flags| flags.insert(Cr4Flags::OSXSAVE));
+            let mut components = XCr0Flags::X87 | XCr0Flags::SSE;
+            if has_avx {
+                components |= XCr0Flags::AVX;
+            }
+            XCr0::write(components);
+            // size of the save area for the components just enabled
+            let size = __cpuid_count(0xD, 0).ebx as usize;
+            STATE_SIZE.store(size.max(FXSAVE_SIZE), Ordering::Relaxed);
+        }
+    }
+    XSAVE.store(has_xsave, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Thread whose registers the calling CPU's FPU holds.
+pub(crate) fn owner() -> Option<ThreadId> {
+    OWNER.get().get()
+}
+
+pub(crate) fn set_owner(owner: Option<ThreadId>) {
+    OWNER.get().set(owner);
+}
+
+/// Called on every thread switch: the FPU is usable right away for its
+/// owner and traps for everyone else.
+pub(crate) fn switch_to(next: ThreadId) {
+    if !is_enabled() {
+        return;
+    }
+    if owner() == Some(next) {
+        clear_task_switched();
+    } else {
+        unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED)) };
+    }
+}
+
+/// Lets the FPU instruction that raised #NM run.
+pub(crate) fn clear_task_switched() {
+    unsafe { asm!("clts", options(nomem, nostack, preserves_flags)) };
+}
+
+/// Saved FPU, SSE and AVX registers of one thread.
+pub struct FpuState {
+    area: NonNull<u8>,
+    layout: Layout,
+}
+
+// only touched by the scheduler on behalf of the owning thread
+unsafe impl Send for FpuState {}
+
+impl FpuState {
+    /// The state after reset: all registers clear, exceptions masked.
+    pub fn new() -> Self {
+        // xsave needs 64 byte alignment, fxsave 16
+        let layout = Layout::from_size_align(STATE_SIZE.load(Ordering::Relaxed), 64)
+            .expect("invalid FPU state layout");
+        let area = NonNull::new(unsafe { alloc_zeroed(layout) })
+            .unwrap_or_else(|| handle_alloc_error(layout));
+        unsafe {
+            // with an all-zero xsave header the x87 and AVX parts load their
+            // initial values, MXCSR is always taken from the legacy area
+            (area.as_ptr() as *mut u16).write(DEFAULT_FCW);
+            (area.as_ptr().add(24) as *mut u32).write(DEFAULT_MXCSR);
+        }
+        FpuState { area, layout }
+    }
+
+    /// Stores the registers of the calling CPU.
+    pub fn save(&mut self) {
+        unsafe {
+            if XSAVE.load(Ordering::Relaxed) {
+                asm!(
+                    "xsave64 [{}]",
+                    in(reg) self.area.as_ptr(),
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack, preserves_flags)
+                );
+            } else {
+                asm!(
+                    "fxsave64 [{}]",
+                    in(reg) self.area.as_ptr(),
+                    options(nostack, preserves_flags)
+                );
+            }
+        }
+    }
+
+    /// Loads the saved registers into the calling CPU.
+    pub fn restore(&self) {
+        unsafe {
+            if XSAVE.load(Ordering::Relaxed) {
+                asm!(
+                    "xrstor64 [{}]",
+                    in(reg) self.area.as_ptr(),
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack, preserves_flags, readonly)
+                );
+            } else {
+                asm!(
+                    "fxrstor64 [{}]",
+                    in(reg) self.area.as_ptr(),
+                    options(nostack, preserves_flags, readonly)
+                );
+            }
+        }
+    }
+
+    /// Overwrites this state with a copy of `other`, e.g. for a forked
+    /// thread.
+    pub fn copy_from(&mut self, other: &FpuState) {
+        let len = self.layout.size().min(other.layout.size());
+        unsafe { ptr::copy_nonoverlapping(other.area.as_ptr(), self.area.as_ptr(), len) };
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.area.as_ptr(), self.layout) };
+    }
+}
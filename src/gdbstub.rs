@@ -0,0 +1,381 @@
+//! GDB remote serial protocol stub on COM2.
+//!
+//! Once [`enable`]d, breakpoint and debug exceptions stop the kernel and hand
+//! control to a GDB connected to the second serial port, e.g. with QEMU's
+//! `-serial stdio -serial pty` and `target remote /dev/pts/N`.
+//!
+//! Supported packets: `?`, `g`, `G`, `m`, `M`, `c`, `s`, `Z0`/`z0`, `D`, `k`
+//! and the `qSupported`/`qAttached` queries. Interrupting a running kernel
+//! with Ctrl+C is not supported; use the `gdb` shell command or an `int3`.
+
+use alloc::{format, string::String, vec::Vec};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use spin::Mutex;
+use x86_64::{
+    registers::control::{Cr0, Cr0Flags},
+    VirtAddr,
+};
+
+use crate::{memory, serial::SERIAL2, thread::context::InterruptContext};
+
+const BREAKPOINT_VECTOR: u8 = 3;
+const INT3: u8 = 0xcc;
+const TRAP_FLAG: u64 = 1 << 8;
+const SIGTRAP: u8 = 5;
+/// Largest packet GDB is told to send and the stub replies with.
+const PACKET_SIZE: usize = 0x1000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Software breakpoints as (address, original byte).
+static BREAKPOINTS: Mutex<Vec<(u64, u8)>> = Mutex::new(Vec::new());
+
+/// Routes breakpoint and debug exceptions to GDB from now on.
+pub fn enable() {
+    if !ENABLED.swap(true, Ordering::SeqCst) {
+        log::info!("gdb stub enabled on COM2");
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Stops in the debugger, waiting for GDB to connect if it has not yet.
+pub fn breakpoint() {
+    enable();
+    x86_64::instructions::interrupts::int3();
+}
+
+/// Entered from the breakpoint and debug exception stubs with interrupts
+/// disabled. Returns once GDB continues or single-steps.
+pub fn handle_exception(vector: u8, context: &mut InterruptContext) {
+    context.frame.rflags &= !TRAP_FLAG;
+    if vector == BREAKPOINT_VECTOR {
+        // report the address of our own int3, not the byte after it
+        let addr = context.frame.rip - 1;
+        if BREAKPOINTS.lock().iter().any(|(bp, _)| *bp == addr) {
+            context.frame.rip = addr;
+        }
+    }
+
+    send_packet(&stop_reply());
+    loop {
+        let packet = receive_packet();
+        match packet.first() {
+            Some(b'?') => send_packet(&stop_reply()),
+            Some(b'g') => send_packet(&read_registers(context)),
+            Some(b'G') => {
+                write_registers(context, &packet[1..]);
+                send_packet("OK");
+            }
+            Some(b'm') => send_packet(&read_memory(&packet[1..]).unwrap_or_else(error)),
+            Some(b'M') => send_packet(write_memory(&packet[1..]).map_or_else(error, |_| "OK".into()).as_str()),
+            Some(b'c') => {
+                resume_at(context, &packet[1..]);
+                return;
+            }
+            Some(b's') => {
+                resume_at(context, &packet[1..]);
+                context.frame.rflags |= TRAP_FLAG;
+                return;
+            }
+            Some(b'Z') if packet.starts_with(b"Z0,") => {
+                let reply = parse_addr_len(&packet[3..])
+                    .and_then(|(addr, _)| insert_breakpoint(addr))
+                    .map_or_else(error, |_| "OK".into());
+                send_packet(&reply);
+            }
+            Some(b'z') if packet.starts_with(b"z0,") => {
+                let reply = parse_addr_len(&packet[3..])
+                    .and_then(|(addr, _)| remove_breakpoint(addr))
+                    .map_or_else(error, |_| "OK".into());
+                send_packet(&reply);
+            }
+            Some(b'D') | Some(b'k') => {
+                remove_all_breakpoints();
+                ENABLED.store(false, Ordering::SeqCst);
+                send_packet("OK");
+                return;
+            }
+            Some(b'q') if packet.starts_with(b"qSupported") => {
+                send_packet(&format!("PacketSize={:x}", PACKET_SIZE))
+            }
+            Some(b'q') if packet.starts_with(b"qAttached") => send_packet("1"),
+            _ => send_packet(""),
+        }
+    }
+}
+
+fn stop_reply() -> String {
+    let mut reply = String::new();
+    let _ = write!(reply, "S{:02x}", SIGTRAP);
+    reply
+}
+
+fn error(code: u8) -> String {
+    let mut reply = String::new();
+    let _ = write!(reply, "E{:02x}", code);
+    reply
+}
+
+fn resume_at(context: &mut InterruptContext, args: &[u8]) {
+    if let Some(addr) = parse_hex(args) {
+        context.frame.rip = addr;
+    }
+}
+
+// Packet framing
+
+fn receive_packet() -> Vec<u8> {
+    let mut serial = SERIAL2.lock();
+    loop {
+        while serial.receive() != b'$' {}
+
+        let mut data = Vec::new();
+        let mut checksum: u8 = 0;
+        loop {
+            match serial.receive() {
+                b'#' => break,
+                byte => {
+                    checksum = checksum.wrapping_add(byte);
+                    // longer packets are cut short and then fail to parse
+                    if data.len() < PACKET_SIZE {
+                        data.push(byte);
+                    }
+                }
+            }
+        }
+        let high = hex_value(serial.receive());
+        let low = hex_value(serial.receive());
+        match (high, low) {
+            (Some(high), Some(low)) if (high << 4 | low) == checksum => {
+                serial.send(b'+');
+                serial.flush();
+                return data;
+            }
+            _ => {
+                serial.send(b'-');
+                serial.flush();
+            }
+        }
+    }
+}
+
+fn send_packet(data: &str) {
+    let mut serial = SERIAL2.lock();
+    loop {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        serial.send(b'$');
+        for byte in data.bytes() {
+            serial.send(byte);
+        }
+        serial.send(b'#');
+        serial.send(HEX_DIGITS[usize::from(checksum >> 4)]);
+        serial.send(HEX_DIGITS[usize::from(checksum & 0xf)]);
+        // interrupts are off, so nothing else drains the transmit queue
+        serial.flush();
+        if serial.receive() == b'+' {
+            return;
+        }
+    }
+}
+
+// Registers
+
+/// Registers in the order of GDB's amd64 `g` packet: 16 general purpose
+/// registers and rip as 64 bit, then eflags and the segment selectors as 32
+/// bit. Floating point registers are left out, GDB shows them as unavailable.
+fn read_registers(context: &InterruptContext) -> String {
+    let regs = &context.regs;
+    let frame = &context.frame;
+    let mut reply = String::new();
+    for value in [
+        regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, frame.rsp, regs.r8,
+        regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, frame.rip,
+    ] {
+        push_le_hex(&mut reply, &value.to_le_bytes());
+    }
+    for value in [frame.rflags as u32, frame.cs as u32, frame.ss as u32, 0, 0, 0, 0] {
+        push_le_hex(&mut reply, &value.to_le_bytes());
+    }
+    reply
+}
+
+fn write_registers(context: &mut InterruptContext, hex: &[u8]) {
+    let bytes = decode_hex(hex).unwrap_or_default();
+    let u64_at = |index: usize| -> Option<u64> {
+        let chunk = bytes.get(index * 8..index * 8 + 8)?;
+        let mut value = [0u8; 8];
+        value.copy_from_slice(chunk);
+        Some(u64::from_le_bytes(value))
+    };
+
+    let regs = &mut context.regs;
+    let frame = &mut context.frame;
+    let targets: [&mut u64; 17] = [
+        &mut regs.rax, &mut regs.rbx, &mut regs.rcx, &mut regs.rdx, &mut regs.rsi,
+        &mut regs.rdi, &mut regs.rbp, &mut frame.rsp, &mut regs.r8, &mut regs.r9,
+        &mut regs.r10, &mut regs.r11, &mut regs.r12, &mut regs.r13, &mut regs.r14,
+        &mut regs.r15, &mut frame.rip,
+    ];
+    for (index, target) in targets.into_iter().enumerate() {
+        if let Some(value) = u64_at(index) {
+            *target = value;
+        }
+    }
+    if let Some(chunk) = bytes.get(17 * 8..17 * 8 + 4) {
+        let mut value = [0u8; 4];
+        value.copy_from_slice(chunk);
+        frame.rflags = u64::from(u32::from_le_bytes(value));
+    }
+}
+
+// Memory
+
+fn is_accessible(addr: u64, len: u64) -> bool {
+    if len == 0 {
+        return true;
+    }
+    let end = match addr.checked_add(len - 1) {
+        Some(end) => end,
+        None => return false,
+    };
+    let mut page = addr & !0xfff;
+    while page <= end {
+        match VirtAddr::try_new(page) {
+            Ok(virt) if memory::translate(virt).is_some() => {}
+            _ => return false,
+        }
+        page = match page.checked_add(4096) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    true
+}
+
+/// Reads at most what fits in one packet; GDB asks again for the rest.
+fn read_memory(args: &[u8]) -> Result<String, u8> {
+    let (addr, len) = parse_addr_len(args)?;
+    let len = len.min(PACKET_SIZE as u64 / 2);
+    if !is_accessible(addr, len) {
+        return Err(0x0e);
+    }
+    let mut reply = String::new();
+    for offset in 0..len {
+        let byte = unsafe { *((addr + offset) as *const u8) };
+        let byte = original_byte(addr + offset).unwrap_or(byte);
+        push_le_hex(&mut reply, &[byte]);
+    }
+    Ok(reply)
+}
+
+fn write_memory(args: &[u8]) -> Result<(), u8> {
+    let colon = args.iter().position(|b| *b == b':').ok_or(0x01)?;
+    let (addr, len) = parse_addr_len(&args[..colon])?;
+    let data = decode_hex(&args[colon + 1..]).ok_or(0x01)?;
+    if data.len() as u64 != len || !is_accessible(addr, len) {
+        return Err(0x0e);
+    }
+    for (offset, byte) in data.into_iter().enumerate() {
+        unsafe { poke(addr + offset as u64, byte) };
+    }
+    Ok(())
+}
+
+/// Writes a byte even if the page is mapped read-only, as kernel code is.
+unsafe fn poke(addr: u64, byte: u8) {
+    let cr0 = Cr0::read();
+    Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+    core::ptr::write_volatile(addr as *mut u8, byte);
+    Cr0::write(cr0);
+}
+
+// Breakpoints
+
+fn original_byte(addr: u64) -> Option<u8> {
+    BREAKPOINTS
+        .lock()
+        .iter()
+        .find(|(bp, _)| *bp == addr)
+        .map(|(_, byte)| *byte)
+}
+
+fn insert_breakpoint(addr: u64) -> Result<(), u8> {
+    if !is_accessible(addr, 1) {
+        return Err(0x0e);
+    }
+    let mut breakpoints = BREAKPOINTS.lock();
+    if breakpoints.iter().all(|(bp, _)| *bp != addr) {
+        let original = unsafe { *(addr as *const u8) };
+        breakpoints.push((addr, original));
+        unsafe { poke(addr, INT3) };
+    }
+    Ok(())
+}
+
+fn remove_breakpoint(addr: u64) -> Result<(), u8> {
+    let mut breakpoints = BREAKPOINTS.lock();
+    let index = breakpoints
+        .iter()
+        .position(|(bp, _)| *bp == addr)
+        .ok_or(0x01)?;
+    let (addr, original) = breakpoints.remove(index);
+    unsafe { poke(addr, original) };
+    Ok(())
+}
+
+fn remove_all_breakpoints() {
+    for (addr, original) in BREAKPOINTS.lock().drain(..) {
+        unsafe { poke(addr, original) };
+    }
+}
+
+// Hex helpers
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn parse_hex(hex: &[u8]) -> Option<u64> {
+    if hex.is_empty() || hex.len() > 16 {
+        return None;
+    }
+    hex.iter()
+        .try_fold(0u64, |value, byte| Some(value << 4 | u64::from(hex_value(*byte)?)))
+}
+
+/// Parses `addr,len`.
+fn parse_addr_len(args: &[u8]) -> Result<(u64, u64), u8> {
+    let comma = args.iter().position(|b| *b == b',').ok_or(0x01)?;
+    let addr = parse_hex(&args[..comma]).ok_or(0x01)?;
+    let len = parse_hex(&args[comma + 1..]).ok_or(0x01)?;
+    Ok((addr, len))
+}
+
+fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| Some(hex_value(pair[0])? << 4 | hex_value(pair[1])?))
+        .collect()
+}
+
+fn push_le_hex(out: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        out.push(char::from(HEX_DIGITS[usize::from(byte >> 4)]));
+        out.push(char::from(HEX_DIGITS[usize::from(byte & 0xf)]));
+    }
+}
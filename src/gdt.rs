@@ -1,3 +1,7 @@
+use alloc::{boxed::Box, vec};
+use core::arch::asm;
+use core::ops::Range;
+use core::ptr::addr_of;
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
@@ -5,49 +9,158 @@ use x86_64::VirtAddr;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
-    };
+const STACK_SIZE: usize = 4096 * 5;
+
+/// Mutable because the ring 0 stack in `privilege_stack_table[0]` changes
+/// with every switch to a thread that can run in user mode.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+fn init_tss() {
+    static mut DOUBLE_FAULT_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+    // used for interrupts and syscalls from ring 3 until a thread sets its own
+    static mut PRIVILEGE_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+    unsafe {
+        let double_fault_start = VirtAddr::from_ptr(addr_of!(DOUBLE_FAULT_STACK));
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            double_fault_start + STACK_SIZE;
+
+        let privilege_start = VirtAddr::from_ptr(addr_of!(PRIVILEGE_STACK));
+        TSS.privilege_stack_table[0] = privilege_start + STACK_SIZE;
+    }
 }
 
 lazy_static! {
+    /// The order of the data and code segments is dictated by `syscall` and
+    /// `sysret`, which derive them from fixed offsets.
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*addr_of!(TSS) }));
         (
             gdt,
             Selectors {
                 code_selector,
+                data_selector,
+                user_data_selector,
+                user_code_selector,
                 tss_selector,
             },
         )
     };
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+pub struct Selectors {
+    pub code_selector: SegmentSelector,
+    pub data_selector: SegmentSelector,
+    pub user_data_selector: SegmentSelector,
+    pub user_code_selector: SegmentSelector,
+    pub tss_selector: SegmentSelector,
+}
+
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
 }
 
 pub fn init() {
-    use x86_64::instructions::segmentation::{Segment, CS};
+    use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
     use x86_64::instructions::tables::load_tss;
 
+    init_tss();
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
+        DS::set_reg(GDT.1.data_selector);
+        ES::set_reg(GDT.1.data_selector);
+        SS::set_reg(GDT.1.data_selector);
         load_tss(GDT.1.tss_selector);
     }
 }
 
+/// Builds and loads a GDT and TSS for an application processor. The
+/// segments are in the same order as on the bootstrap processor, so
+/// [`selectors`] holds on every CPU.
+pub fn init_ap() {
+    use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+    use x86_64::instructions::tables::load_tss;
+
+    // application processors never run user code, so no privilege stack
+    let double_fault_stack = Box::leak(vec![0u8; STACK_SIZE].into_boxed_slice());
+    let tss = Box::leak(Box::new(TaskStateSegment::new()));
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+        VirtAddr::from_ptr(double_fault_stack.as_ptr()) + STACK_SIZE;
+    let tss: &'static TaskStateSegment = tss;
+
+    let gdt = Box::leak(Box::new(GlobalDescriptorTable::new()));
+    gdt.add_entry(Descriptor::kernel_code_segment());
+    gdt.add_entry(Descriptor::kernel_data_segment());
+    gdt.add_entry(Descriptor::user_data_segment());
+    gdt.add_entry(Descriptor::user_code_segment());
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+    assert_eq!(tss_selector, GDT.1.tss_selector);
+    let gdt: &'static GlobalDescriptorTable = gdt;
+
+    gdt.load();
+    unsafe {
+        CS::set_reg(GDT.1.code_selector);
+        DS::set_reg(GDT.1.data_selector);
+        ES::set_reg(GDT.1.data_selector);
+        SS::set_reg(GDT.1.data_selector);
+        load_tss(tss_selector);
+    }
+}
+
+/// Sets the stack the CPU switches to when an interrupt, exception or system
+/// call arrives while running in ring 3.
+///
+/// # Safety
+///
+/// `stack_top` must be the top of a valid, otherwise unused kernel stack.
+pub unsafe fn set_kernel_stack(stack_top: VirtAddr) {
+    TSS.privilege_stack_table[0] = stack_top;
+    crate::syscall::set_kernel_stack(stack_top);
+}
+
+pub fn kernel_stack() -> VirtAddr {
+    unsafe { TSS.privilege_stack_table[0] }
+}
+
+/// Addresses of the stack the bootstrap processor handles double faults on.
+pub fn double_fault_stack() -> Range<u64> {
+    let top = unsafe { TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] }.as_u64();
+    top.saturating_sub(STACK_SIZE as u64)..top
+}
+
+/// Drops to ring 3 and starts executing at `entry` on `user_stack` with
+/// interrupts enabled. Swaps GS like every return to ring 3, which parks
+/// the per-CPU pointer in the kernel GS base until the next kernel entry.
+///
+/// # Safety
+///
+/// Both addresses must be mapped user accessible in the active page table,
+/// and the TSS must hold a kernel stack for the return to ring 0.
+pub unsafe fn enter_user_mode(entry: VirtAddr, user_stack: VirtAddr) -> ! {
+    let code = u64::from(GDT.1.user_code_selector.0);
+    let data = u64::from(GDT.1.user_data_selector.0);
+    asm!(
+        "mov ds, {data:x}",
+        "mov es, {data:x}",
+        "push {data}",
+        "push {stack}",
+        "push {rflags}",
+        "push {code}",
+        "push {entry}",
+        "swapgs",
+        "iretq",
+        data = in(reg) data,
+        stack = in(reg) user_stack.as_u64(),
+        // interrupts enabled
+        rflags = in(reg) 0x202u64,
+        code = in(reg) code,
+        entry = in(reg) entry.as_u64(),
+        options(noreturn)
+    );
+}
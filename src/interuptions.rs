@@ -1,23 +1,32 @@
 
-use crate::{cmd_handler, filesystem::file_tree, gdt, hlt_loop, print, println, vga_buffer};
-use alloc::{
-    fmt, str,
-    string::{String, ToString},
-};
+use crate::{apic, fpu, gdbstub, gdt, hlt_loop, keyboard, println, serial, smp, syscall, task, thread};
+use crate::sync::IrqSpinlock;
+use crate::thread::context::InterruptContext;
+use core::cell::Cell;
 use lazy_static::lazy_static;
-use pc_keyboard::KeyCode;
 use pic8259::ChainedPics;
-use spin::Mutex;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::{PrivilegeLevel, VirtAddr};
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
+/// Software interrupt used by `thread::yield_now` to enter the scheduler.
+pub const YIELD_VECTOR: u8 = 0x81;
+
+/// Inter-processor interrupts, acknowledged at the local APIC.
+pub const TLB_SHOOTDOWN_VECTOR: u8 = 0xF0;
+pub const RESCHEDULE_VECTOR: u8 = 0xF1;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InteruptIndex {
     TIMER = PIC_1_OFFSET,
     Keyboard,
+    /// shared by COM2 and COM4
+    Com2 = PIC_1_OFFSET + 3,
+    /// shared by COM1 and COM3
+    Com1 = PIC_1_OFFSET + 4,
 }
 
 impl InteruptIndex {
@@ -29,53 +38,72 @@ impl InteruptIndex {
         usize::from(self.as_u8())
     }
 }
-pub const PROMPT: &str = " -> ";
-
-#[derive(Debug)]
-enum Prefix {
-    Ctrl,
-    CapsLock,
-    None,
+crate::cpu_local! {
+    /// Nesting depth of hardware interrupt handlers on each CPU.
+    static IRQ_DEPTH: Cell<usize> = Cell::new(0);
 }
 
-impl Prefix {
-    pub fn new() -> Self {
-        Prefix::None
-    }
-    pub fn from(keycode: KeyCode) -> Self {
-        match keycode {
-            KeyCode::LControl => Prefix::Ctrl,
-            KeyCode::RControl => Prefix::Ctrl,
-            KeyCode::CapsLock => Prefix::CapsLock,
-            _default => Prefix::None,
-        }
-    }
+/// Whether the caller runs inside a hardware interrupt handler.
+pub fn in_interrupt() -> bool {
+    IRQ_DEPTH.get().get() > 0
 }
 
-impl fmt::Display for Prefix {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-        // or, alternatively:
-        // fmt::Debug::fmt(self, f)
+/// Held for the duration of a hardware interrupt handler. The handlers use
+/// the context switch stubs, which make the per-CPU data reachable even when
+/// the interrupt arrives in ring 3.
+struct IrqScope;
+
+impl IrqScope {
+    fn enter() -> Self {
+        let depth = IRQ_DEPTH.get();
+        depth.set(depth.get() + 1);
+        IrqScope
     }
 }
 
-lazy_static! {
-    static ref cmd: Mutex<String> = Mutex::new(String::new());
-    static ref prefix: Mutex<String> = Mutex::new(String::new());
+impl Drop for IrqScope {
+    fn drop(&mut self) {
+        let depth = IRQ_DEPTH.get();
+        depth.set(depth.get() - 1);
+    }
 }
 
-pub static PIC: spin::Mutex<ChainedPics> =
-    spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+pub static PIC: IrqSpinlock<ChainedPics> = IrqSpinlock::named("interuptions::PIC", unsafe {
+    ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET)
+});
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
         unsafe {
+            idt.breakpoint
+                .set_handler_addr(VirtAddr::from_ptr(breakpoint_stub as *const ()));
+            idt.debug.set_handler_addr(VirtAddr::from_ptr(debug_stub as *const ()));
+            idt.non_maskable_interrupt.set_handler_fn(nmi_handler);
             idt.page_fault.set_handler_fn(page_interupt_handler);
-            idt[InteruptIndex::TIMER.as_usize()].set_handler_fn(timer_interrupt_handler);
-            idt[InteruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+            idt.general_protection_fault
+                .set_handler_fn(general_protection_handler);
+            idt.device_not_available
+                .set_handler_addr(VirtAddr::from_ptr(device_not_available_stub as *const ()));
+            idt[InteruptIndex::TIMER.as_usize()]
+                .set_handler_addr(VirtAddr::from_ptr(timer_interrupt_stub as *const ()));
+            idt[usize::from(YIELD_VECTOR)]
+                .set_handler_addr(VirtAddr::from_ptr(yield_interrupt_stub as *const ()));
+            // reachable from ring 3 for CPUs or programs without `syscall`
+            idt[usize::from(syscall::INT80_VECTOR)]
+                .set_handler_addr(syscall::int80_handler())
+                .set_privilege_level(PrivilegeLevel::Ring3);
+            idt[usize::from(TLB_SHOOTDOWN_VECTOR)]
+                .set_handler_addr(VirtAddr::from_ptr(tlb_shootdown_stub as *const ()));
+            idt[usize::from(RESCHEDULE_VECTOR)]
+                .set_handler_addr(VirtAddr::from_ptr(reschedule_stub as *const ()));
+            idt[InteruptIndex::Keyboard.as_usize()]
+                .set_handler_addr(VirtAddr::from_ptr(keyboard_interrupt_stub as *const ()));
+            idt[InteruptIndex::Com1.as_usize()]
+                .set_handler_addr(VirtAddr::from_ptr(com1_interrupt_stub as *const ()));
+            idt[InteruptIndex::Com2.as_usize()]
+                .set_handler_addr(VirtAddr::from_ptr(com2_interrupt_stub as *const ()));
+            idt[usize::from(apic::SPURIOUS_VECTOR)].set_handler_fn(spurious_interrupt_handler);
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
@@ -88,7 +116,22 @@ extern "x86-interrupt" fn page_interupt_handler(
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
-    use x86_64::registers::control::Cr2;
+    use x86_64::{instructions::segmentation::GS, registers::control::Cr2};
+
+    if error_code.contains(PageFaultErrorCode::USER_MODE) {
+        // unlike the stubs, this entry does not swap to the per-CPU GS base
+        unsafe { GS::swap() };
+        let addr = Cr2::read();
+        let write = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+        if error_code.contains(write) && thread::break_cow(addr) {
+            unsafe { GS::swap() };
+            return;
+        }
+        kill_faulting_thread(format_args!(
+            "faulted at {:?} ({:?}), rip {:?}",
+            addr, error_code, stack_frame.instruction_pointer
+        ));
+    }
 
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
@@ -97,101 +140,169 @@ extern "x86-interrupt" fn page_interupt_handler(
     hlt_loop();
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-    use spin::Mutex;
-    use x86_64::instructions::port::Port;
+extern "x86-interrupt" fn general_protection_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    use x86_64::instructions::segmentation::GS;
 
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Dvorak104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(
-                ScancodeSet1::new(),
-                layouts::Dvorak104Key,
-                HandleControl::Ignore
-            ));
+    if stack_frame.code_segment & 3 == 3 {
+        unsafe { GS::swap() };
+        kill_faulting_thread(format_args!(
+            "caused a general protection fault ({:#x}), rip {:?}",
+            error_code, stack_frame.instruction_pointer
+        ));
     }
 
-    let mut keyboard = KEYBOARD.lock();
-    let mut port = Port::new(0x60);
+    println!("EXCEPTION: GENERAL PROTECTION FAULT");
+    println!("Error Code: {:#x}", error_code);
+    println!("{:#?}", stack_frame);
+    hlt_loop();
+}
+
+/// Ends the user program whose fault brought the CPU into the kernel with
+/// SIGSEGV. The caller has already swapped to the kernel GS base.
+fn kill_faulting_thread(fault: core::fmt::Arguments) -> ! {
+    log::warn!("thread {} {}", thread::current().as_u64(), fault);
+    thread::exit_with(thread::ExitStatus::Signaled(crate::signal::Signal::SIGSEGV));
+}
+
+crate::context_switch_stub!(keyboard_interrupt_stub, keyboard_interrupt_dispatch);
+crate::context_switch_stub!(com1_interrupt_stub, com1_interrupt_dispatch);
+crate::context_switch_stub!(com2_interrupt_stub, com2_interrupt_dispatch);
 
+extern "C" fn keyboard_interrupt_dispatch(context: *mut InterruptContext) -> *mut InterruptContext {
+    use x86_64::instructions::port::Port;
+
+    let _scope = IrqScope::enter();
+    let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode('\u{0008}') => {
-                    vga_buffer::WRITER.lock().write_byte(0x0E);
-                    cmd.lock().pop();
-                }
-                DecodedKey::Unicode(character) => match character {
-                    'a'..='z' => {
-                        if prefix.lock().as_str() == "None" || prefix.lock().is_empty() {
-                            cmd.lock().push(character);
-                            print!("{}", character)
-                        } else {
-                            cmd_handler::handle_prefix_action(character.to_string().as_str());
-                            prefix.lock().clear();
-                        }
-                    }
-                    '1'..'9' => {
-                        if prefix.lock().as_str() == "None" || prefix.lock().is_empty() {
-                            cmd.lock().push(character);
-                            print!("{}", character)
-                        } else {
-                            cmd_handler::handle_prefix_action(character.to_string().as_str());
-                            prefix.lock().clear();
-                        }
-                    }
-                    ' ' => {
-                        cmd.lock().push(character);
-                        print!(" ")
-                    }
-                    '.' => {
-                        cmd.lock().push_str(".");
-                        print!(".")
-
-                    }
-                    '\n' => {
-                        if !cmd.lock().is_empty() {
-                            crate::cmd_handler::handle_cmd(&mut cmd.lock());
-                        }
-                        print!("\n");
-                        let dir = file_tree::fs_system.lock().cur_node.lock().dir_name.clone();
-                        print!("{} {}",dir, PROMPT);
-                        cmd.lock().clear();
-                        prefix.lock().clear();
-                    }
-                    '\u{27}' => {
-                        prefix.lock().clear();
-                    }
-                    default => print!("{}", default),
-                },
-                DecodedKey::RawKey(key) => {
-                    let prefx = Prefix::from(key);
-                    prefix.lock().push_str(&prefx.to_string());
-                }
-            }
-        }
-    }
+    keyboard::add_scancode(scancode);
 
     unsafe {
         PIC.lock()
             .notify_end_of_interrupt(InteruptIndex::Keyboard.as_u8());
     }
+    thread::input_arrived(context)
+}
+
+extern "C" fn com1_interrupt_dispatch(context: *mut InterruptContext) -> *mut InterruptContext {
+    let _scope = IrqScope::enter();
+    let received = serial::handle_interrupt(4);
+
+    unsafe {
+        PIC.lock()
+            .notify_end_of_interrupt(InteruptIndex::Com1.as_u8());
+    }
+    if received {
+        thread::input_arrived(context)
+    } else {
+        context
+    }
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+extern "C" fn com2_interrupt_dispatch(context: *mut InterruptContext) -> *mut InterruptContext {
+    let _scope = IrqScope::enter();
+    serial::handle_interrupt(3);
+
+    unsafe {
+        PIC.lock()
+            .notify_end_of_interrupt(InteruptIndex::Com2.as_u8());
+    }
+    context
+}
+
+crate::context_switch_stub!(timer_interrupt_stub, timer_interrupt_dispatch);
+crate::context_switch_stub!(yield_interrupt_stub, yield_interrupt_dispatch);
+
+extern "C" fn timer_interrupt_dispatch(context: *mut InterruptContext) -> *mut InterruptContext {
+    let _scope = IrqScope::enter();
+    task::timer::tick();
+    thread::account_tick();
+
     unsafe {
         PIC.lock()
             .notify_end_of_interrupt(InteruptIndex::TIMER.as_u8());
     }
+    thread::schedule(context)
+}
+
+extern "C" fn yield_interrupt_dispatch(context: *mut InterruptContext) -> *mut InterruptContext {
+    thread::schedule_yield(context)
+}
+
+crate::context_switch_stub!(tlb_shootdown_stub, tlb_shootdown_dispatch);
+crate::context_switch_stub!(reschedule_stub, reschedule_dispatch);
+
+extern "C" fn tlb_shootdown_dispatch(context: *mut InterruptContext) -> *mut InterruptContext {
+    smp::handle_tlb_shootdown();
+    context
+}
+
+extern "C" fn reschedule_dispatch(context: *mut InterruptContext) -> *mut InterruptContext {
+    smp::handle_reschedule();
+    thread::schedule(context)
+}
+
+/// Only sent by [`smp::halt_others`].
+extern "x86-interrupt" fn nmi_handler(_stack_frame: InterruptStackFrame) {
+    smp::handle_nmi();
 }
 
+/// Needs no end of interrupt.
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
 pub fn init_idt() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+/// Clears the mask bit of a legacy IRQ line in the PIC.
+pub fn unmask_irq(irq: u8) {
+    use x86_64::instructions::port::Port;
+
+    let (port, bit) = if irq < 8 { (0x21, irq) } else { (0xA1, irq - 8) };
+    let mut data: Port<u8> = Port::new(port);
+    unsafe {
+        let mask = data.read();
+        data.write(mask & !(1 << bit));
+    }
+}
+
+crate::context_switch_stub!(breakpoint_stub, breakpoint_dispatch);
+crate::context_switch_stub!(debug_stub, debug_dispatch);
+
+extern "C" fn breakpoint_dispatch(context: *mut InterruptContext) -> *mut InterruptContext {
+    let context_ref = unsafe { &mut *context };
+    if gdbstub::is_enabled() {
+        gdbstub::handle_exception(3, context_ref);
+    } else {
+        println!("EXCEPTION: BREAKPOINT\n{:#?}", context_ref.frame);
+    }
+    context
+}
+
+extern "C" fn debug_dispatch(context: *mut InterruptContext) -> *mut InterruptContext {
+    let context_ref = unsafe { &mut *context };
+    if gdbstub::is_enabled() {
+        gdbstub::handle_exception(1, context_ref);
+    } else {
+        println!("EXCEPTION: DEBUG\n{:#?}", context_ref.frame);
+    }
+    context
+}
+
+crate::context_switch_stub!(device_not_available_stub, device_not_available_dispatch);
+
+extern "C" fn device_not_available_dispatch(
+    context: *mut InterruptContext,
+) -> *mut InterruptContext {
+    if fpu::is_enabled() {
+        thread::claim_fpu();
+        return context;
+    }
+    println!("EXCEPTION: DEVICE NOT AVAILABLE");
+    println!("{:#?}", unsafe { &(*context).frame });
+    hlt_loop();
 }
 
 extern "x86-interrupt" fn double_fault_handler(
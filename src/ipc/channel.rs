@@ -0,0 +1,175 @@
+use alloc::{collections::VecDeque, sync::Arc};
+
+use super::IpcError;
+use crate::{
+    sync::{IrqSpinlock, WaitQueue},
+    thread,
+};
+
+struct State<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    senders: usize,
+    receiver: bool,
+}
+
+struct Shared<T> {
+    state: IrqSpinlock<State<T>>,
+    /// Receivers waiting for a message.
+    not_empty: WaitQueue,
+    /// Senders waiting for room in the queue.
+    not_full: WaitQueue,
+}
+
+/// Sending half of a channel, cloned for every additional producer.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Receiving half of a channel. There is only ever one.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Creates a channel holding at most `capacity` messages.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must not be zero");
+    let shared = Arc::new(Shared {
+        state: IrqSpinlock::named(
+            "ipc::channel",
+            State {
+                queue: VecDeque::with_capacity(capacity),
+                capacity,
+                senders: 1,
+                receiver: true,
+            },
+        ),
+        not_empty: WaitQueue::new(),
+        not_full: WaitQueue::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+/// Whether a blocked call should give up because a signal arrived.
+fn interrupted() -> bool {
+    thread::is_running() && thread::signal_pending()
+}
+
+impl<T> Sender<T> {
+    /// Queues `message`, waiting for room if the channel is full.
+    pub fn send(&self, message: T) -> Result<(), IpcError> {
+        let mut message = Some(message);
+        loop {
+            match self.push(&mut message) {
+                Err(IpcError::WouldBlock) => {}
+                result => return result,
+            }
+            self.shared.not_full.wait_until(|| {
+                let state = self.shared.state.lock();
+                state.queue.len() < state.capacity || !state.receiver || interrupted()
+            });
+            if interrupted() {
+                return Err(IpcError::Interrupted);
+            }
+        }
+    }
+
+    /// Queues `message` or fails with [`IpcError::WouldBlock`] if the
+    /// channel is full.
+    pub fn try_send(&self, message: T) -> Result<(), IpcError> {
+        self.push(&mut Some(message))
+    }
+
+    /// Takes the message out of `message` only if it can be queued.
+    fn push(&self, message: &mut Option<T>) -> Result<(), IpcError> {
+        {
+            let mut state = self.shared.state.lock();
+            if !state.receiver {
+                return Err(IpcError::Disconnected);
+            }
+            if state.queue.len() >= state.capacity {
+                return Err(IpcError::WouldBlock);
+            }
+            state.queue.extend(message.take());
+        }
+        self.shared.not_empty.wake_one();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let last = {
+            let mut state = self.shared.state.lock();
+            state.senders -= 1;
+            state.senders == 0
+        };
+        if last {
+            self.shared.not_empty.wake_all();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Takes the oldest message, waiting for one if the channel is empty.
+    /// Fails with [`IpcError::Disconnected`] once it is empty and every
+    /// sender is gone.
+    pub fn recv(&self) -> Result<T, IpcError> {
+        loop {
+            match self.try_recv() {
+                Err(IpcError::WouldBlock) => {}
+                result => return result,
+            }
+            self.shared.not_empty.wait_until(|| {
+                let state = self.shared.state.lock();
+                !state.queue.is_empty() || state.senders == 0 || interrupted()
+            });
+            if interrupted() {
+                return Err(IpcError::Interrupted);
+            }
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<T, IpcError> {
+        let message = {
+            let mut state = self.shared.state.lock();
+            match state.queue.pop_front() {
+                Some(message) => message,
+                None if state.senders == 0 => return Err(IpcError::Disconnected),
+                None => return Err(IpcError::WouldBlock),
+            }
+        };
+        self.shared.not_full.wake_one();
+        Ok(message)
+    }
+
+    /// Number of queued messages.
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().receiver = false;
+        self.shared.not_full.wake_all();
+    }
+}
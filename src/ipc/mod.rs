@@ -0,0 +1,217 @@
+//! Message passing between threads.
+//!
+//! [`channel`] creates a bounded queue for kernel code, e.g. a driver handing
+//! events to a thread. User programs reach channels of byte messages through
+//! handles: small numbers owned by one thread, which only that thread can
+//! use until it closes them or [`transfer`]s them to another thread. The
+//! handles of a thread are closed when it exits.
+//!
+//! [`pipe`]s are byte streams instead, used to connect the output of one
+//! shell command to the input of the next. A program started with [`Stdio`]
+//! reads and writes its pipes through the `STDIN` and `STDOUT` descriptors.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    sync::{IrqSpinlock, Mutex},
+    thread::ThreadId,
+};
+
+mod channel;
+mod pipe;
+
+pub use channel::{channel, Receiver, Sender};
+pub use pipe::{pipe, PipeReader, PipeWriter, PIPE_CAPACITY};
+
+/// Largest message a user program can send.
+pub const MAX_MESSAGE: usize = 4096;
+/// Largest number of messages a channel created through a handle can hold.
+pub const MAX_CAPACITY: usize = 64;
+/// Open handles across all threads.
+pub const MAX_HANDLES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcError {
+    /// The channel is full on send or empty on receive.
+    WouldBlock,
+    /// The other end is gone.
+    Disconnected,
+    /// A signal arrived while the thread was blocked.
+    Interrupted,
+    /// No such handle, or it belongs to another thread.
+    BadHandle,
+    /// The handle is the wrong end for the operation.
+    WrongEnd,
+    TooLarge,
+    InvalidCapacity,
+    TooManyHandles,
+}
+
+enum Endpoint {
+    Sender(Arc<Sender<Vec<u8>>>),
+    Receiver(Arc<Receiver<Vec<u8>>>),
+}
+
+struct Handle {
+    owner: ThreadId,
+    endpoint: Endpoint,
+}
+
+static HANDLES: Mutex<BTreeMap<u64, Handle>> = Mutex::named("ipc::HANDLES", BTreeMap::new());
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+/// Pipes replacing the console for a thread. Missing ends stay the console.
+#[derive(Default)]
+pub struct Stdio {
+    pub stdin: Option<PipeReader>,
+    pub stdout: Option<PipeWriter>,
+}
+
+/// Set before a program first runs, hence a lock usable with interrupts off.
+static STDIO: IrqSpinlock<BTreeMap<ThreadId, Stdio>> =
+    IrqSpinlock::named("ipc::STDIO", BTreeMap::new());
+
+/// Creates a channel of byte messages owned by `owner` and returns the
+/// handles of its sending and receiving end.
+pub fn create_channel(owner: ThreadId, capacity: usize) -> Result<(u64, u64), IpcError> {
+    if capacity == 0 || capacity > MAX_CAPACITY {
+        return Err(IpcError::InvalidCapacity);
+    }
+    let mut handles = HANDLES.lock();
+    if handles.len() + 2 > MAX_HANDLES {
+        return Err(IpcError::TooManyHandles);
+    }
+    let (sender, receiver) = channel(capacity);
+    let send_handle = NEXT_HANDLE.fetch_add(2, Ordering::Relaxed);
+    let recv_handle = send_handle + 1;
+    handles.insert(
+        send_handle,
+        Handle {
+            owner,
+            endpoint: Endpoint::Sender(Arc::new(sender)),
+        },
+    );
+    handles.insert(
+        recv_handle,
+        Handle {
+            owner,
+            endpoint: Endpoint::Receiver(Arc::new(receiver)),
+        },
+    );
+    Ok((send_handle, recv_handle))
+}
+
+/// Sends `message` through the sending end `handle`. Without `blocking` a
+/// full channel fails with [`IpcError::WouldBlock`].
+pub fn send(
+    owner: ThreadId,
+    handle: u64,
+    message: Vec<u8>,
+    blocking: bool,
+) -> Result<(), IpcError> {
+    if message.len() > MAX_MESSAGE {
+        return Err(IpcError::TooLarge);
+    }
+    // the table lock is not held while blocked
+    let sender = match lookup(owner, handle)? {
+        Endpoint::Sender(sender) => sender,
+        Endpoint::Receiver(_) => return Err(IpcError::WrongEnd),
+    };
+    if blocking {
+        sender.send(message)
+    } else {
+        sender.try_send(message)
+    }
+}
+
+/// Takes the oldest message from the receiving end `handle`.
+pub fn recv(owner: ThreadId, handle: u64, blocking: bool) -> Result<Vec<u8>, IpcError> {
+    let receiver = match lookup(owner, handle)? {
+        Endpoint::Receiver(receiver) => receiver,
+        Endpoint::Sender(_) => return Err(IpcError::WrongEnd),
+    };
+    if blocking {
+        receiver.recv()
+    } else {
+        receiver.try_recv()
+    }
+}
+
+/// Closes `handle`. Closing the last sending end wakes the receiver, closing
+/// the receiving end fails every further send.
+pub fn close(owner: ThreadId, handle: u64) -> Result<(), IpcError> {
+    let endpoint = {
+        let mut handles = HANDLES.lock();
+        match handles.get(&handle) {
+            Some(entry) if entry.owner == owner => handles.remove(&handle).unwrap(),
+            _ => return Err(IpcError::BadHandle),
+        }
+    };
+    // waking the other end happens outside the table lock
+    drop(endpoint);
+    Ok(())
+}
+
+/// Hands `handle` from `from` to `to`, e.g. to a program the shell starts.
+pub fn transfer(handle: u64, from: ThreadId, to: ThreadId) -> Result<(), IpcError> {
+    match HANDLES.lock().get_mut(&handle) {
+        Some(entry) if entry.owner == from => {
+            entry.owner = to;
+            Ok(())
+        }
+        _ => Err(IpcError::BadHandle),
+    }
+}
+
+/// Connects the standard input and output of thread `id` to pipes.
+pub fn set_stdio(id: ThreadId, stdio: Stdio) {
+    let old = STDIO.lock().insert(id, stdio);
+    drop(old);
+}
+
+/// The pipe thread `id` reads as standard input, if any.
+pub fn stdin(id: ThreadId) -> Option<PipeReader> {
+    STDIO.lock().get(&id)?.stdin.clone()
+}
+
+/// The pipe thread `id` writes as standard output, if any.
+pub fn stdout(id: ThreadId) -> Option<PipeWriter> {
+    STDIO.lock().get(&id)?.stdout.clone()
+}
+
+/// Closes every handle and pipe of `owner`. Called once the thread has
+/// exited.
+pub fn release_all(owner: ThreadId) {
+    let stdio = STDIO.lock().remove(&owner);
+    drop(stdio);
+    let released: Vec<Handle> = {
+        let mut handles = HANDLES.lock();
+        let ids: Vec<u64> = handles
+            .iter()
+            .filter(|(_, entry)| entry.owner == owner)
+            .map(|(&id, _)| id)
+            .collect();
+        ids.iter().filter_map(|id| handles.remove(id)).collect()
+    };
+    drop(released);
+}
+
+/// Number of handles `owner` has open.
+pub fn handle_count(owner: ThreadId) -> usize {
+    HANDLES
+        .lock()
+        .values()
+        .filter(|entry| entry.owner == owner)
+        .count()
+}
+
+fn lookup(owner: ThreadId, handle: u64) -> Result<Endpoint, IpcError> {
+    match HANDLES.lock().get(&handle) {
+        Some(entry) if entry.owner == owner => Ok(match &entry.endpoint {
+            Endpoint::Sender(sender) => Endpoint::Sender(sender.clone()),
+            Endpoint::Receiver(receiver) => Endpoint::Receiver(receiver.clone()),
+        }),
+        _ => Err(IpcError::BadHandle),
+    }
+}
@@ -0,0 +1,172 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use core::fmt;
+
+use super::IpcError;
+use crate::{
+    sync::{IrqSpinlock, WaitQueue},
+    thread,
+};
+
+/// Bytes a pipe buffers before writers block.
+pub const PIPE_CAPACITY: usize = 4096;
+
+struct State {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+struct Shared {
+    state: IrqSpinlock<State>,
+    /// Readers waiting for data.
+    not_empty: WaitQueue,
+    /// Writers waiting for room.
+    not_full: WaitQueue,
+}
+
+/// Reading end of a pipe. Clones share the same stream.
+pub struct PipeReader {
+    shared: Arc<Shared>,
+}
+
+/// Writing end of a pipe. Readers see end of file once every clone is gone.
+pub struct PipeWriter {
+    shared: Arc<Shared>,
+}
+
+/// Creates a byte stream from the returned writer to the reader.
+pub fn pipe() -> (PipeWriter, PipeReader) {
+    let shared = Arc::new(Shared {
+        state: IrqSpinlock::named(
+            "ipc::pipe",
+            State {
+                buffer: VecDeque::with_capacity(PIPE_CAPACITY),
+                readers: 1,
+                writers: 1,
+            },
+        ),
+        not_empty: WaitQueue::new(),
+        not_full: WaitQueue::new(),
+    });
+    (
+        PipeWriter {
+            shared: shared.clone(),
+        },
+        PipeReader { shared },
+    )
+}
+
+fn interrupted() -> bool {
+    thread::is_running() && thread::signal_pending()
+}
+
+impl PipeReader {
+    /// Waits for data and copies up to `buf.len()` bytes of it. Returns 0 at
+    /// end of file, i.e. once the pipe is empty and has no writers left.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, IpcError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.shared.not_empty.wait_until(|| {
+            let state = self.shared.state.lock();
+            !state.buffer.is_empty() || state.writers == 0 || interrupted()
+        });
+        let count = {
+            let mut state = self.shared.state.lock();
+            if state.buffer.is_empty() && interrupted() {
+                return Err(IpcError::Interrupted);
+            }
+            let count = buf.len().min(state.buffer.len());
+            for (dst, src) in buf.iter_mut().zip(state.buffer.drain(..count)) {
+                *dst = src;
+            }
+            count
+        };
+        self.shared.not_full.wake_all();
+        Ok(count)
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().readers += 1;
+        PipeReader {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let last = {
+            let mut state = self.shared.state.lock();
+            state.readers -= 1;
+            state.readers == 0
+        };
+        if last {
+            self.shared.not_full.wake_all();
+        }
+    }
+}
+
+impl PipeWriter {
+    /// Writes all of `data`, waiting for room as needed. Fails with
+    /// [`IpcError::Disconnected`] if every reader is gone before anything
+    /// could be written; otherwise returns how much was written.
+    pub fn write(&self, data: &[u8]) -> Result<usize, IpcError> {
+        let mut written = 0;
+        while written < data.len() {
+            self.shared.not_full.wait_until(|| {
+                let state = self.shared.state.lock();
+                state.buffer.len() < PIPE_CAPACITY || state.readers == 0 || interrupted()
+            });
+            {
+                let mut state = self.shared.state.lock();
+                if state.readers == 0 || interrupted() {
+                    let error = if state.readers == 0 {
+                        IpcError::Disconnected
+                    } else {
+                        IpcError::Interrupted
+                    };
+                    return if written > 0 { Ok(written) } else { Err(error) };
+                }
+                let count = (PIPE_CAPACITY - state.buffer.len()).min(data.len() - written);
+                state.buffer.extend(&data[written..written + count]);
+                written += count;
+            }
+            self.shared.not_empty.wake_all();
+        }
+        Ok(written)
+    }
+}
+
+impl fmt::Write for PipeWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.write(s.as_bytes()) {
+            Ok(count) if count == s.len() => Ok(()),
+            _ => Err(fmt::Error),
+        }
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().writers += 1;
+        PipeWriter {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        let last = {
+            let mut state = self.shared.state.lock();
+            state.writers -= 1;
+            state.writers == 0
+        };
+        if last {
+            self.shared.not_empty.wake_all();
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use core::cell::RefCell;
+use lazy_static::lazy_static;
+use pc_keyboard::{
+    layouts, DecodedKey, HandleControl, KeyCode, Keyboard, KeyboardLayout, Modifiers,
+    ScancodeSet1,
+};
+
+use crate::{byte_queue::ByteQueue, config::CONFIG, signal, sync::IrqSpinlock};
+
+/// Keyboard layouts that can be selected at build time (`keymap-*` features)
+/// or at runtime with the `keymap` shell command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Keymap {
+    Us104 = 0,
+    Uk105,
+    De105,
+    Azerty,
+    Dvorak104,
+    Jis109,
+}
+
+impl Keymap {
+    pub const ALL: [Keymap; 6] = [
+        Keymap::Us104,
+        Keymap::Uk105,
+        Keymap::De105,
+        Keymap::Azerty,
+        Keymap::Dvorak104,
+        Keymap::Jis109,
+    ];
+
+    /// Layout selected by the enabled `keymap-*` feature. Non-default features
+    /// take precedence so `--features keymap-us104` works without
+    /// `--no-default-features`.
+    #[cfg(feature = "keymap-us104")]
+    pub const DEFAULT: Keymap = Keymap::Us104;
+    #[cfg(all(not(feature = "keymap-us104"), feature = "keymap-uk105"))]
+    pub const DEFAULT: Keymap = Keymap::Uk105;
+    #[cfg(all(
+        not(any(feature = "keymap-us104", feature = "keymap-uk105")),
+        feature = "keymap-de105"
+    ))]
+    pub const DEFAULT: Keymap = Keymap::De105;
+    #[cfg(all(
+        not(any(
+            feature = "keymap-us104",
+            feature = "keymap-uk105",
+            feature = "keymap-de105"
+        )),
+        feature = "keymap-azerty"
+    ))]
+    pub const DEFAULT: Keymap = Keymap::Azerty;
+    #[cfg(all(
+        not(any(
+            feature = "keymap-us104",
+            feature = "keymap-uk105",
+            feature = "keymap-de105",
+            feature = "keymap-azerty"
+        )),
+        feature = "keymap-jis109"
+    ))]
+    pub const DEFAULT: Keymap = Keymap::Jis109;
+    #[cfg(not(any(
+        feature = "keymap-us104",
+        feature = "keymap-uk105",
+        feature = "keymap-de105",
+        feature = "keymap-azerty",
+        feature = "keymap-jis109"
+    )))]
+    pub const DEFAULT: Keymap = Keymap::Dvorak104;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Keymap::Us104 => "us104",
+            Keymap::Uk105 => "uk105",
+            Keymap::De105 => "de105",
+            Keymap::Azerty => "azerty",
+            Keymap::Dvorak104 => "dvorak104",
+            Keymap::Jis109 => "jis109",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Keymap> {
+        Keymap::ALL.iter().copied().find(|k| k.name() == name)
+    }
+
+    pub fn from_u8(value: u8) -> Option<Keymap> {
+        Keymap::ALL.iter().copied().find(|k| *k as u8 == value)
+    }
+}
+
+/// Layout handed to `pc_keyboard`. It looks up the active keymap on every key
+/// so a `keymap` change takes effect without rebuilding the decoder state.
+pub struct ConfiguredLayout;
+
+impl KeyboardLayout for ConfiguredLayout {
+    fn map_keycode(
+        &self,
+        keycode: KeyCode,
+        modifiers: &Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        match CONFIG.keymap() {
+            Keymap::Us104 => layouts::Us104Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Keymap::Uk105 => layouts::Uk105Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Keymap::De105 => layouts::De105Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Keymap::Azerty => layouts::Azerty.map_keycode(keycode, modifiers, handle_ctrl),
+            Keymap::Dvorak104 => {
+                layouts::Dvorak104Key.map_keycode(keycode, modifiers, handle_ctrl)
+            }
+            Keymap::Jis109 => layouts::Jis109Key.map_keycode(keycode, modifiers, handle_ctrl),
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref KEYBOARD: IrqSpinlock<Keyboard<ConfiguredLayout, ScancodeSet1>> =
+        IrqSpinlock::named(
+            "keyboard::KEYBOARD",
+            Keyboard::new(ScancodeSet1::new(), ConfiguredLayout, HandleControl::Ignore),
+        );
+}
+
+crate::cpu_local! {
+    /// Second decoder fed by the interrupt handler itself, which spots Ctrl+C
+    /// even while the shell waits for a program and nothing reads the queue.
+    /// Created by the first key press a CPU handles.
+    static INTERRUPT_KEYS: RefCell<Option<Keyboard<ConfiguredLayout, ScancodeSet1>>> =
+        RefCell::new(None);
+}
+
+/// Ctrl+C with letters mapped to control characters.
+const END_OF_TEXT: char = '\u{3}';
+
+/// Scancodes queued by the interrupt handler for
+/// [`ScancodeStream`](crate::task::keyboard::ScancodeStream).
+pub static SCANCODE_QUEUE: ByteQueue = ByteQueue::new();
+
+/// Called by the keyboard interrupt handler. Must not block or allocate.
+/// Ctrl+C interrupts the foreground job instead of being typed, if there
+/// is one.
+pub(crate) fn add_scancode(scancode: u8) {
+    let key = {
+        // only ever used here, with interrupts off
+        let mut keyboard = INTERRUPT_KEYS.get().borrow_mut();
+        let keyboard = keyboard.get_or_insert_with(|| {
+            Keyboard::new(
+                ScancodeSet1::new(),
+                ConfiguredLayout,
+                HandleControl::MapLettersToUnicode,
+            )
+        });
+        match keyboard.add_byte(scancode) {
+            Ok(Some(event)) => keyboard.process_keyevent(event),
+            _ => None,
+        }
+    };
+    if key == Some(DecodedKey::Unicode(END_OF_TEXT)) && signal::interrupt_foreground() {
+        return;
+    }
+    if SCANCODE_QUEUE.push(scancode) {
+        crate::task::keyboard::wake();
+    }
+}
+
+#[test_case]
+fn test_keymap_names_round_trip() {
+    for keymap in Keymap::ALL.iter() {
+        assert_eq!(Keymap::from_name(keymap.name()), Some(*keymap));
+        assert_eq!(Keymap::from_u8(*keymap as u8), Some(*keymap));
+    }
+    assert_eq!(Keymap::from_name("qwerty"), None);
+}
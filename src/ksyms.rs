@@ -0,0 +1,91 @@
+//! Kernel symbol table used to symbolise backtraces.
+//!
+//! The table is a zero-filled blob in its own `.ksyms` section. The cargo
+//! runner, `tools/runner.py`, has `tools/ksyms.py` write the kernel's
+//! function symbols into it before building the boot image:
+//!
+//! ```text
+//! magic "KSYM" | count: u32 | count * (addr: u64, name_off: u32, name_len: u32) | names
+//! ```
+//!
+//! Entries are sorted by address. An unpatched kernel simply has no symbols.
+//! The placeholder starts with a non-zero marker so the linker emits real
+//! file contents for the section instead of treating it like `.bss`.
+
+use core::{convert::TryInto, slice, str};
+
+const KSYMS_SIZE: usize = 256 * 1024;
+const MAGIC: &[u8; 4] = b"KSYM";
+const HEADER_SIZE: usize = 8;
+const ENTRY_SIZE: usize = 16;
+
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: [u8; KSYMS_SIZE] = {
+    let mut table = [0; KSYMS_SIZE];
+    table[0] = b'n';
+    table[1] = b'o';
+    table[2] = b'n';
+    table[3] = b'e';
+    table
+};
+
+fn table() -> &'static [u8] {
+    // the contents are patched after compilation, so keep the compiler from
+    // folding reads of the placeholder initializer
+    let ptr = core::hint::black_box(KSYMS.as_ptr());
+    unsafe { slice::from_raw_parts(ptr, KSYMS_SIZE) }
+}
+
+fn read_u32(table: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(table[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(table: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(table[offset..offset + 8].try_into().unwrap())
+}
+
+fn symbol_count(table: &[u8]) -> usize {
+    if &table[0..4] != MAGIC {
+        return 0;
+    }
+    let count = read_u32(table, 4) as usize;
+    count.min((KSYMS_SIZE - HEADER_SIZE) / ENTRY_SIZE)
+}
+
+fn entry(table: &[u8], index: usize) -> (u64, &str) {
+    let offset = HEADER_SIZE + index * ENTRY_SIZE;
+    let addr = read_u64(table, offset);
+    let name_off = read_u32(table, offset + 8) as usize;
+    let name_len = read_u32(table, offset + 12) as usize;
+    let name = table
+        .get(name_off..name_off + name_len)
+        .and_then(|bytes| str::from_utf8(bytes).ok())
+        .unwrap_or("<invalid>");
+    (addr, name)
+}
+
+/// Returns the symbol containing `addr` and the offset into it.
+pub fn lookup(addr: u64) -> Option<(&'static str, u64)> {
+    let table = table();
+    let count = symbol_count(table);
+    if count == 0 {
+        return None;
+    }
+
+    // last entry with entry.addr <= addr
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        if entry(table, mid).0 <= addr {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low == 0 {
+        return None;
+    }
+    let (start, name) = entry(table, low - 1);
+    Some((name, addr - start))
+}
@@ -6,22 +6,48 @@
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+use bootloader::BootInfo;
 use core::panic::PanicInfo;
 extern crate alloc;
 
+pub mod acpi;
 pub mod alocator;
+pub mod apic;
+pub mod byte_queue;
 pub mod cmd_handler;
+pub mod config;
+pub mod elf;
+pub mod exec;
+pub mod fpu;
+pub mod gdbstub;
 pub mod gdt;
 pub mod interuptions;
+pub mod ipc;
+pub mod keyboard;
+pub mod ksyms;
+pub mod line_editor;
+pub mod logger;
 pub mod memory;
+pub mod panic_screen;
 pub mod serial;
+pub mod signal;
+pub mod smp;
+pub mod sync;
+pub mod syscall;
+pub mod task;
+pub mod thread;
 pub mod vga_buffer;
 pub mod filesystem;
 
 pub fn init() {
+    smp::init_bsp();
     gdt::init();
+    fpu::init();
+    syscall::init();
     interuptions::init_idt();
     unsafe { interuptions::PIC.lock().initialize() };
+    task::timer::init();
+    serial::init();
     x86_64::instructions::interrupts::enable();
 }
 pub trait Testable {
@@ -54,6 +80,45 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
     hlt_loop();
 }
 
+/// Boot sequence shared by the integration tests: [`init`], paging, the heap
+/// and the frame allocator. Threads and the other processors are left to the
+/// tests that need them.
+pub fn test_init(boot_info: &'static BootInfo) {
+    use memory::BootInfoFrameAllocator;
+    use x86_64::VirtAddr;
+
+    init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    alocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_frame_allocator(frame_allocator);
+}
+
+/// Entry point and panic handler of an integration test binary. `$setup` runs
+/// after [`test_init`], right before the tests.
+#[macro_export]
+macro_rules! test_harness {
+    () => {
+        $crate::test_harness!(|| {});
+    };
+    ($setup:expr) => {
+        bootloader::entry_point!(test_kernel_main);
+
+        fn test_kernel_main(boot_info: &'static bootloader::BootInfo) -> ! {
+            $crate::test_init(boot_info);
+            ($setup)();
+            test_main();
+            $crate::hlt_loop();
+        }
+
+        #[panic_handler]
+        fn panic(info: &core::panic::PanicInfo) -> ! {
+            $crate::test_panic_handler(info)
+        }
+    };
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
@@ -64,13 +129,15 @@ pub enum QemuExitCode {
 pub fn exit_qemu(exit_code: QemuExitCode) {
     use x86_64::instructions::port::Port;
 
+    // test results and crash reports have to reach the host first
+    serial::uart::drain(0);
     unsafe {
         let mut port = Port::new(0xf4);
         port.write(exit_code as u32);
     }
 }
 #[cfg(test)]
-use bootloader::{entry_point, BootInfo};
+use bootloader::entry_point;
 
 #[cfg(test)]
 entry_point!(test_kernel_main);
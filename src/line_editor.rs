@@ -0,0 +1,182 @@
+use alloc::{
+    collections::VecDeque,
+    string::{String, ToString},
+};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::{
+    cmd_handler,
+    config::CONFIG,
+    filesystem::file_tree,
+    print, serial_print,
+    sync::{IrqSpinlock, Mutex, WaitQueue},
+    thread, vga_buffer,
+};
+
+pub const PROMPT: &str = " -> ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prefix {
+    Ctrl,
+    CapsLock,
+    None,
+}
+
+impl Prefix {
+    pub fn from(keycode: KeyCode) -> Self {
+        match keycode {
+            KeyCode::LControl => Prefix::Ctrl,
+            KeyCode::RControl => Prefix::Ctrl,
+            KeyCode::CapsLock => Prefix::CapsLock,
+            _default => Prefix::None,
+        }
+    }
+}
+
+/// Collects decoded keys into a command line and hands finished lines to
+/// `cmd_handler`. Runs outside interrupt context.
+pub struct LineEditor {
+    line: String,
+    prefix: Prefix,
+}
+
+lazy_static! {
+    /// Held while a finished line runs, so a sleeping lock.
+    pub static ref LINE_EDITOR: Mutex<LineEditor> =
+        Mutex::named("line_editor::LINE_EDITOR", LineEditor::new());
+}
+
+/// Lines handed to user programs instead of the shell while any of them is
+/// blocked in [`read_stdin`].
+static STDIN: IrqSpinlock<VecDeque<u8>> = IrqSpinlock::named("stdin", VecDeque::new());
+static STDIN_READERS: AtomicUsize = AtomicUsize::new(0);
+static STDIN_WAITERS: WaitQueue = WaitQueue::new();
+/// A command the shell waits for runs on its own thread, see
+/// [`start_job`].
+static JOB_RUNNING: AtomicBool = AtomicBool::new(false);
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        LineEditor {
+            line: String::new(),
+            prefix: Prefix::None,
+        }
+    }
+
+    pub fn handle_key(&mut self, key: DecodedKey) {
+        // the console belongs to the foreground job, unless a program reads it
+        if JOB_RUNNING.load(Ordering::Acquire) && STDIN_READERS.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        match key {
+            DecodedKey::Unicode('\u{0008}') => {
+                vga_buffer::WRITER.lock().write_byte(0x0E);
+                if CONFIG.serial_console() {
+                    serial_print!("\u{8} \u{8}");
+                }
+                self.line.pop();
+            }
+            DecodedKey::Unicode(character) => match character {
+                'a'..='z' | '0'..='9' => {
+                    if self.prefix == Prefix::None {
+                        self.line.push(character);
+                        print!("{}", character)
+                    } else {
+                        cmd_handler::handle_prefix_action(character.to_string().as_str());
+                        self.prefix = Prefix::None;
+                    }
+                }
+                '\n' => self.submit(),
+                '\u{27}' => self.prefix = Prefix::None,
+                ' '..='~' => {
+                    self.line.push(character);
+                    print!("{}", character)
+                }
+                default => print!("{}", default),
+            },
+            DecodedKey::RawKey(key) => self.prefix = Prefix::from(key),
+        }
+    }
+
+    /// Feeds a byte received on a serial line, translating terminal
+    /// conventions to the keys the keyboard path produces.
+    pub fn handle_byte(&mut self, byte: u8) {
+        match byte {
+            b'\r' | b'\n' => self.handle_key(DecodedKey::Unicode('\n')),
+            0x08 | 0x7f => self.handle_key(DecodedKey::Unicode('\u{0008}')),
+            // Ctrl+L
+            0x0c => cmd_handler::handle_prefix_action("l"),
+            0x20..=0x7e => self.handle_key(DecodedKey::Unicode(char::from(byte))),
+            _ => {}
+        }
+    }
+
+    fn submit(&mut self) {
+        if STDIN_READERS.load(Ordering::Acquire) > 0 {
+            print!("\n");
+            {
+                let mut stdin = STDIN.lock();
+                stdin.extend(self.line.bytes());
+                stdin.push_back(b'\n');
+            }
+            STDIN_WAITERS.wake_all_interactive();
+            self.line.clear();
+            self.prefix = Prefix::None;
+            return;
+        }
+        // a foreground job prints the prompt once it is done
+        if self.line.is_empty() || cmd_handler::handle_cmd(&mut self.line) {
+            print!("\n");
+            print_prompt();
+        }
+        self.line.clear();
+        self.prefix = Prefix::None;
+    }
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn print_prompt() {
+    let dir = file_tree::current_dir_name();
+    print!("{} {}", dir, PROMPT);
+}
+
+/// Hands the console to a command running on a thread of its own: typed
+/// keys are dropped until [`finish_job`], except for programs reading
+/// standard input. Ctrl+C still reaches the job.
+pub fn start_job() {
+    JOB_RUNNING.store(true, Ordering::Release);
+}
+
+/// Gives the console back to the shell and prints the prompt.
+pub fn finish_job() {
+    JOB_RUNNING.store(false, Ordering::Release);
+    print!("\n");
+    print_prompt();
+}
+
+/// Blocks the calling thread until a line has been typed and copies up to
+/// `buf.len()` bytes of it. Returns 0 if a signal arrives while waiting.
+/// Must not be called from the executor thread, which is the one feeding the
+/// line editor.
+pub fn read_stdin(buf: &mut [u8]) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+    STDIN_READERS.fetch_add(1, Ordering::AcqRel);
+    STDIN_WAITERS.wait_until(|| !STDIN.lock().is_empty() || thread::signal_pending());
+    STDIN_READERS.fetch_sub(1, Ordering::AcqRel);
+
+    let mut stdin = STDIN.lock();
+    let count = buf.len().min(stdin.len());
+    for (dst, src) in buf.iter_mut().zip(stdin.drain(..count)) {
+        *dst = src;
+    }
+    count
+}
@@ -0,0 +1,156 @@
+use alloc::{collections::VecDeque, format, string::String, vec::Vec};
+use lazy_static::lazy_static;
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::{
+    print, serial_println,
+    sync::IrqSpinlock,
+    task::timer::{self, TICKS_PER_SECOND},
+};
+
+/// Number of formatted lines kept for `dmesg`.
+const RING_CAPACITY: usize = 256;
+
+/// Where log lines are printed besides the ring buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    None,
+    Vga,
+    Serial,
+    Both,
+}
+
+impl Sink {
+    pub fn from_name(name: &str) -> Option<Sink> {
+        match name {
+            "none" => Some(Sink::None),
+            "vga" => Some(Sink::Vga),
+            "serial" => Some(Sink::Serial),
+            "both" => Some(Sink::Both),
+            _ => None,
+        }
+    }
+}
+
+struct LoggerState {
+    default_level: LevelFilter,
+    /// Per-module overrides as (module path prefix, level); longest prefix wins.
+    module_levels: Vec<(String, LevelFilter)>,
+    sink: Sink,
+    ring: VecDeque<String>,
+}
+
+impl LoggerState {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.module_levels
+            .iter()
+            .filter(|(module, _)| target.starts_with(module.as_str()))
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default_level)
+    }
+}
+
+pub struct KernelLogger;
+
+static LOGGER: KernelLogger = KernelLogger;
+
+lazy_static! {
+    static ref STATE: IrqSpinlock<LoggerState> = IrqSpinlock::named(
+        "logger",
+        LoggerState {
+            default_level: LevelFilter::Info,
+            module_levels: Vec::new(),
+            sink: Sink::Serial,
+            ring: VecDeque::with_capacity(RING_CAPACITY),
+        }
+    );
+}
+
+/// Installs the kernel logger. Needs the heap for the ring buffer.
+pub fn init() {
+    log::set_logger(&LOGGER).expect("logger already initialized");
+    // filtering happens per module in `enabled`
+    log::set_max_level(LevelFilter::Trace);
+}
+
+fn with_state<R>(f: impl FnOnce(&mut LoggerState) -> R) -> R {
+    f(&mut STATE.lock())
+}
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        with_state(|state| metadata.level() <= state.level_for(metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let ticks = timer::ticks();
+        let line = format!(
+            "[{:>5}.{:03}] {:<5} {}: {}",
+            ticks / TICKS_PER_SECOND,
+            (ticks % TICKS_PER_SECOND) * 1000 / TICKS_PER_SECOND,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        let sink = with_state(|state| {
+            if state.ring.len() == RING_CAPACITY {
+                state.ring.pop_front();
+            }
+            state.ring.push_back(line.clone());
+            state.sink
+        });
+
+        match sink {
+            Sink::None => {}
+            Sink::Vga => print!("\n{}", line),
+            Sink::Serial => {
+                serial_println!("{}", line);
+            }
+            Sink::Both => {
+                print!("\n{}", line);
+                serial_println!("{}", line);
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Copies the retained log lines, oldest first.
+pub fn dmesg() -> Vec<String> {
+    with_state(|state| state.ring.iter().cloned().collect())
+}
+
+pub fn set_sink(sink: Sink) {
+    with_state(|state| state.sink = sink);
+}
+
+/// Sets the level for `module` and everything below it, or the default level
+/// if `module` is `None`.
+pub fn set_level(module: Option<&str>, level: LevelFilter) {
+    with_state(|state| match module {
+        Some(module) => {
+            state.module_levels.retain(|(m, _)| m != module);
+            state.module_levels.push((String::from(module), level));
+        }
+        None => state.default_level = level,
+    });
+}
+
+pub fn parse_level(name: &str) -> Option<LevelFilter> {
+    match name {
+        "off" => Some(LevelFilter::Off),
+        "error" => Some(LevelFilter::Error),
+        "warn" => Some(LevelFilter::Warn),
+        "info" => Some(LevelFilter::Info),
+        "debug" => Some(LevelFilter::Debug),
+        "trace" => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
@@ -5,13 +5,12 @@
 #![test_runner(kernel::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
-pub mod serial;
-
 use core::panic::PanicInfo;
 
 use bootloader::{entry_point, BootInfo};
 use kernel::{
-    interuptions, memory::{self, BootInfoFrameAllocator}, print
+    line_editor, memory::{self, BootInfoFrameAllocator}, print,
+    task::{executor::Executor, keyboard, serial as serial_input, Task},
 };
 use x86_64::{structures::paging::Page, VirtAddr};
 extern crate alloc;
@@ -34,8 +33,6 @@ pub fn kernel_main(boot_info: &'static BootInfo) -> ! {
 
     print!("{}", logo);
 
-    print!("{}", interuptions::PROMPT);
-
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
@@ -46,16 +43,29 @@ pub fn kernel_main(boot_info: &'static BootInfo) -> ! {
 
     // alloc some kernel heap size defined in the alocator.rs
     kernel::alocator::init_heap(&mut mapper, &mut frame_allocator).expect("allocation failed");
-    kernel::hlt_loop()
+    memory::init_frame_allocator(frame_allocator);
+    kernel::logger::init();
+    log::info!("heap initialized");
+    kernel::thread::init();
+    log::info!("scheduler started");
+    kernel::smp::init();
+    kernel::exec::install_builtin_programs();
+
+    line_editor::print_prompt();
+    // the executor runs the shell on this thread
+    kernel::thread::mark_interactive();
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(keyboard::handle_keypresses()));
+    executor.spawn(Task::new(serial_input::handle_serial_input()));
+    executor.run()
 }
 
 /// This function is called on panic.
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    use kernel::println;
-    println!("{}", info);
-    kernel::hlt_loop();
+    kernel::panic_screen::kernel_panic(info)
 }
 
 #[cfg(test)]
@@ -1,11 +1,36 @@
+use alloc::{collections::BTreeMap, vec::Vec};
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64::{
     structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+        mapper::{MapToError, TranslateResult, UnmapError},
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+        Size4KiB, Translate,
     },
     PhysAddr, VirtAddr,
 };
 
+use crate::sync::IrqSpinlock;
+
+pub mod address_space;
+
+/// Offset of the bootloader's complete physical memory mapping, set by [`init`].
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Frames below 1 MiB are never handed out, so real mode code such as the
+/// SMP trampoline can be placed there.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
+/// Device registers are mapped from here on. Shares the level 4 entry of the
+/// heap, so the mappings show up in every address space.
+const MMIO_START: u64 = 0x_4444_8000_0000;
+static MMIO_NEXT: IrqSpinlock<u64> = IrqSpinlock::named("memory::MMIO_NEXT", MMIO_START);
+
+/// Stack `kernel_main` starts on, placed by the bootloader as configured in
+/// `Cargo.toml`. Its lowest page is left unmapped as a guard page.
+pub const BOOT_STACK_SIZE: u64 = 512 * 4096;
+pub const BOOT_STACK_TOP: u64 = 0x_4444_c000_0000 + 4096 + BOOT_STACK_SIZE;
+
 /// Returns a mutable reference to the active level 4 table.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -14,7 +39,12 @@ use x86_64::{
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
     let level_4_table = active_level_4_table(physical_memory_offset);
+    assert!(
+        level_4_table[address_space::USER_P4_INDEX].is_unused(),
+        "the bootloader mapped memory into the user program slot"
+    );
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
@@ -30,6 +60,35 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     &mut *page_table_ptr // unsafe
 }
 
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+/// Translates `addr` through the active page table, following huge pages.
+/// Returns `None` before [`init`] has run.
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    let offset = physical_memory_offset();
+    if offset.is_null() {
+        return None;
+    }
+    // only used for reading, so the aliasing `&mut` is never written through
+    let table = unsafe { OffsetPageTable::new(active_level_4_table(offset), offset) };
+    table.translate_addr(addr)
+}
+
+/// Returns the flags of the lowest level page table entry mapping `addr`.
+pub fn page_flags(addr: VirtAddr) -> Option<PageTableFlags> {
+    let offset = physical_memory_offset();
+    if offset.is_null() {
+        return None;
+    }
+    let table = unsafe { OffsetPageTable::new(active_level_4_table(offset), offset) };
+    match table.translate(addr) {
+        TranslateResult::Mapped { flags, .. } => Some(flags),
+        _ => None,
+    }
+}
+
 pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
     translate_addr_inner(addr, physical_memory_offset)
 }
@@ -120,7 +179,9 @@ impl BootInfoFrameAllocator {
 
         let addr_ranges = usable_regions.map(|x| x.range.start_addr()..x.range.end_addr());
 
-        let frame_addres = addr_ranges.flat_map(|r| r.step_by(4096));
+        let frame_addres = addr_ranges
+            .flat_map(|r| r.step_by(4096))
+            .filter(|addr| *addr >= LOW_MEMORY_END);
 
         frame_addres.map(|x| PhysFrame::containing_address(PhysAddr::new(x)))
     }
@@ -133,3 +194,120 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
         frame
     }
 }
+
+/// Frame allocator shared by everything that maps memory after boot, set by
+/// [`init_frame_allocator`]. Freed frames are reused before new ones are
+/// taken from the memory map.
+struct FrameStore {
+    boot: BootInfoFrameAllocator,
+    free: Vec<PhysFrame>,
+    /// Owners of frames mapped copy-on-write into several address spaces.
+    /// Frames missing here have a single owner.
+    shared: BTreeMap<PhysFrame, usize>,
+}
+
+static FRAMES: IrqSpinlock<Option<FrameStore>> = IrqSpinlock::named("memory::FRAMES", None);
+
+/// Hands the boot frame allocator over to the kernel once the heap exists.
+pub fn init_frame_allocator(allocator: BootInfoFrameAllocator) {
+    *FRAMES.lock() = Some(FrameStore {
+        boot: allocator,
+        free: Vec::new(),
+        shared: BTreeMap::new(),
+    });
+}
+
+pub fn allocate_frame() -> Option<PhysFrame> {
+    let mut frames = FRAMES.lock();
+    let store = frames.as_mut()?;
+    store.free.pop().or_else(|| store.boot.allocate_frame())
+}
+
+/// Gives up one ownership of `frame`. Once the last owner is gone it
+/// returns to the allocator and must not be mapped anywhere.
+pub fn free_frame(frame: PhysFrame) {
+    if let Some(store) = FRAMES.lock().as_mut() {
+        match store.shared.get_mut(&frame) {
+            Some(owners) if *owners > 2 => *owners -= 1,
+            Some(_) => {
+                store.shared.remove(&frame);
+            }
+            None => store.free.push(frame),
+        }
+    }
+}
+
+/// Adds an owner to `frame`, e.g. an address space it is shared with.
+/// Every owner calls [`free_frame`] when done with it.
+pub fn share_frame(frame: PhysFrame) {
+    if let Some(store) = FRAMES.lock().as_mut() {
+        *store.shared.entry(frame).or_insert(1) += 1;
+    }
+}
+
+/// Number of owners of an allocated `frame`.
+pub fn frame_owners(frame: PhysFrame) -> usize {
+    FRAMES
+        .lock()
+        .as_ref()
+        .and_then(|store| store.shared.get(&frame).copied())
+        .unwrap_or(1)
+}
+
+/// Hands out frames of the global allocator to the `Mapper` API.
+struct GlobalFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for GlobalFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        allocate_frame()
+    }
+}
+
+/// Maps `size` bytes of device memory at `phys` uncached into the kernel's
+/// part of the address space.
+pub fn map_mmio(phys: PhysAddr, size: usize) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last = PhysFrame::<Size4KiB>::containing_address(phys + (size.max(1) - 1));
+    let frames = PhysFrame::range_inclusive(first, last);
+
+    let mut next = MMIO_NEXT.lock();
+    let start = VirtAddr::new(*next);
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_EXECUTE;
+    let offset = physical_memory_offset();
+    let mut mapper = unsafe { OffsetPageTable::new(active_level_4_table(offset), offset) };
+    for (index, frame) in frames.enumerate() {
+        let page = Page::containing_address(start + index as u64 * 4096);
+        unsafe { mapper.map_to(page, frame, flags, &mut GlobalFrameAllocator)? }.flush();
+    }
+    *next += (last.start_address() - first.start_address()) + 4096;
+    Ok(start + phys.as_u64() % 4096)
+}
+
+/// Maps `frame` at the virtual address equal to its physical address, for
+/// code that runs while paging is being switched on. Returns false if it
+/// was mapped there already.
+pub fn identity_map(frame: PhysFrame, flags: PageTableFlags) -> Result<bool, MapToError<Size4KiB>> {
+    let page = Page::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+    if translate(page.start_address()) == Some(frame.start_address()) {
+        return Ok(false);
+    }
+    let offset = physical_memory_offset();
+    let mut mapper = unsafe { OffsetPageTable::new(active_level_4_table(offset), offset) };
+    unsafe { mapper.map_to(page, frame, flags, &mut GlobalFrameAllocator)? }.flush();
+    Ok(true)
+}
+
+/// Removes a mapping added by [`identity_map`]. Only the calling CPU's TLB
+/// is flushed.
+pub fn identity_unmap(frame: PhysFrame) -> Result<(), UnmapError> {
+    let page: Page = Page::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+    let offset = physical_memory_offset();
+    let mut mapper = unsafe { OffsetPageTable::new(active_level_4_table(offset), offset) };
+    let (_, flush) = mapper.unmap(page)?;
+    flush.flush();
+    Ok(())
+}
@@ -0,0 +1,292 @@
+use alloc::vec::Vec;
+use x86_64::{
+    instructions::tlb,
+    registers::control::Cr3,
+    structures::paging::{
+        mapper::{MapToError, TranslateResult},
+        page_table::PageTableEntry,
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PageTableIndex,
+        PhysFrame, Size4KiB, Translate,
+    },
+    VirtAddr,
+};
+
+use super::{allocate_frame, frame_owners, free_frame, physical_memory_offset, share_frame};
+
+/// Level 4 slot holding user programs. The bootloader and the kernel never
+/// map anything here, so it is the only entry that differs between address
+/// spaces. Programs have to be linked to run inside it.
+pub const USER_P4_INDEX: usize = 16;
+pub const USER_START: u64 = (USER_P4_INDEX as u64) << 39;
+pub const USER_END: u64 = USER_START + (1 << 39);
+
+/// Marks a writable page shared with another address space by
+/// [`AddressSpace::fork`]. It is mapped read-only until the first write
+/// gives the writer its own copy.
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    OutOfMemory,
+    AlreadyMapped,
+    NotMapped,
+    /// The address lies outside `USER_START..USER_END`.
+    OutsideUserRegion,
+}
+
+/// Page tables of one user program: the kernel half is shared with every
+/// other address space, the user slot is private. User pages may be shared
+/// copy-on-write after a fork.
+///
+/// Kernel mappings added to previously empty level 4 slots after an address
+/// space was created are not visible in it.
+pub struct AddressSpace {
+    p4: PhysFrame,
+    /// Frames backing user pages and the page tables below the user slot.
+    /// Each entry is one ownership, see [`free_frame`].
+    frames: Vec<PhysFrame>,
+}
+
+/// Remembers every page table frame the mapper allocates so it can be freed.
+struct RecordingAllocator<'a>(&'a mut Vec<PhysFrame>);
+
+unsafe impl FrameAllocator<Size4KiB> for RecordingAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = allocate_frame()?;
+        self.0.push(frame);
+        Some(frame)
+    }
+}
+
+unsafe fn table_at(frame: PhysFrame) -> &'static mut PageTable {
+    let virt = physical_memory_offset() + frame.start_address().as_u64();
+    &mut *virt.as_mut_ptr()
+}
+
+fn zero_frame(frame: PhysFrame) {
+    let virt = physical_memory_offset() + frame.start_address().as_u64();
+    unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, 4096) };
+}
+
+impl AddressSpace {
+    /// Creates an address space sharing the kernel mappings of the active
+    /// page table, with an empty user slot.
+    pub fn new() -> Result<Self, MapError> {
+        let (active, _) = Cr3::read();
+        let p4 = allocate_frame().ok_or(MapError::OutOfMemory)?;
+        let space = AddressSpace {
+            p4,
+            frames: Vec::new(),
+        };
+
+        let (table, kernel) = unsafe { (table_at(p4), table_at(active)) };
+        table.zero();
+        for (index, entry) in kernel.iter().enumerate() {
+            if index != USER_P4_INDEX {
+                table[index] = entry.clone();
+            }
+        }
+        Ok(space)
+    }
+
+    pub fn page_table(&self) -> PhysFrame {
+        self.p4
+    }
+
+    /// Bytes of physical memory owned by this address space, page tables
+    /// included.
+    pub fn memory_usage(&self) -> usize {
+        (self.frames.len() + 1) * 4096
+    }
+
+    /// Maps a zeroed frame at `page`. `PRESENT` and `USER_ACCESSIBLE` are
+    /// added to `flags`.
+    pub fn map(&mut self, page: Page, flags: PageTableFlags) -> Result<(), MapError> {
+        let start = page.start_address().as_u64();
+        if !(USER_START..USER_END).contains(&start) {
+            return Err(MapError::OutsideUserRegion);
+        }
+
+        let frame = allocate_frame().ok_or(MapError::OutOfMemory)?;
+        self.frames.push(frame);
+        zero_frame(frame);
+        self.map_frame(page, frame, flags)
+    }
+
+    /// Maps `frame`, which the caller has added to `self.frames`, at `page`.
+    fn map_frame(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), MapError> {
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let table_flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        let mut mapper =
+            unsafe { OffsetPageTable::new(table_at(self.p4), physical_memory_offset()) };
+        let mut allocator = RecordingAllocator(&mut self.frames);
+        let result = unsafe {
+            mapper.map_to_with_table_flags(page, frame, flags, table_flags, &mut allocator)
+        };
+        match result {
+            // not the active table, nothing to flush
+            Ok(flush) => flush.ignore(),
+            Err(MapToError::FrameAllocationFailed) => return Err(MapError::OutOfMemory),
+            Err(_) => return Err(MapError::AlreadyMapped),
+        }
+        Ok(())
+    }
+
+    /// Creates the address space of a forked program: the same pages at the
+    /// same addresses. Writable pages are not copied but become
+    /// copy-on-write in both address spaces.
+    pub fn fork(&mut self) -> Result<AddressSpace, MapError> {
+        unsafe { Self::fork_table(self.p4) }
+    }
+
+    /// [`fork`](Self::fork) for the address space with the level 4 table
+    /// `p4`, for callers that cannot hold a reference to it for that long.
+    ///
+    /// # Safety
+    ///
+    /// `p4` has to be the table of an address space that is neither dropped
+    /// nor changed until this returns.
+    pub unsafe fn fork_table(p4: PhysFrame) -> Result<AddressSpace, MapError> {
+        let mut child = AddressSpace::new()?;
+        let p3 = match table_at(p4)[USER_P4_INDEX].frame() {
+            Ok(frame) => frame,
+            Err(_) => return Ok(child),
+        };
+        let user = PageTableIndex::new(USER_P4_INDEX as u16);
+        for (i3, entry) in table_at(p3).iter().enumerate() {
+            let p2 = match entry.frame() {
+                Ok(frame) => frame,
+                Err(_) => continue,
+            };
+            for (i2, entry) in table_at(p2).iter().enumerate() {
+                let p1 = match entry.frame() {
+                    Ok(frame) => frame,
+                    Err(_) => continue,
+                };
+                for (i1, entry) in table_at(p1).iter_mut().enumerate() {
+                    let frame = match entry.frame() {
+                        Ok(frame) => frame,
+                        Err(_) => continue,
+                    };
+                    let mut flags = entry.flags();
+                    if flags.contains(PageTableFlags::WRITABLE) {
+                        flags.remove(PageTableFlags::WRITABLE);
+                        flags.insert(COPY_ON_WRITE);
+                        entry.set_flags(flags);
+                    }
+                    flags.remove(PageTableFlags::ACCESSED | PageTableFlags::DIRTY);
+                    let page = Page::from_page_table_indices(
+                        user,
+                        PageTableIndex::new(i3 as u16),
+                        PageTableIndex::new(i2 as u16),
+                        PageTableIndex::new(i1 as u16),
+                    );
+                    share_frame(frame);
+                    child.frames.push(frame);
+                    child.map_frame(page, frame, flags)?;
+                }
+            }
+        }
+        // this address space just lost write access to its own pages
+        if Cr3::read().0 == p4 {
+            tlb::flush_all();
+        }
+        Ok(child)
+    }
+
+    /// Gives this address space its own writable copy of the copy-on-write
+    /// page at `addr`. Returns false if the page is not copy-on-write.
+    pub fn break_cow(&mut self, addr: VirtAddr) -> Result<bool, MapError> {
+        let entry = match self.entry_mut(Page::containing_address(addr)) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        let flags = entry.flags();
+        if !flags.contains(COPY_ON_WRITE) {
+            return Ok(false);
+        }
+        let shared = entry.frame().map_err(|_| MapError::NotMapped)?;
+        let flags = (flags - COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+        // the other owners may have copied it already
+        if frame_owners(shared) == 1 {
+            entry.set_flags(flags);
+        } else {
+            let copy = allocate_frame().ok_or(MapError::OutOfMemory)?;
+            let offset = physical_memory_offset();
+            let src = offset + shared.start_address().as_u64();
+            let dst = offset + copy.start_address().as_u64();
+            unsafe {
+                core::ptr::copy_nonoverlapping(src.as_ptr::<u8>(), dst.as_mut_ptr::<u8>(), 4096)
+            };
+            entry.set_addr(copy.start_address(), flags);
+            if let Some(owned) = self.frames.iter_mut().find(|frame| **frame == shared) {
+                *owned = copy;
+            }
+            free_frame(shared);
+        }
+        if Cr3::read().0 == self.p4 {
+            tlb::flush(addr);
+        }
+        Ok(true)
+    }
+
+    /// The lowest level entry for `page`, if its page tables exist.
+    fn entry_mut(&mut self, page: Page) -> Option<&'static mut PageTableEntry> {
+        let p4 = unsafe { table_at(self.p4) };
+        let p3 = unsafe { table_at(p4[page.p4_index()].frame().ok()?) };
+        let p2 = unsafe { table_at(p3[page.p3_index()].frame().ok()?) };
+        let p1 = unsafe { table_at(p2[page.p2_index()].frame().ok()?) };
+        Some(&mut p1[page.p1_index()])
+    }
+
+    /// Flags of the page mapped at `addr`, if any.
+    pub fn page_flags(&self, addr: VirtAddr) -> Option<PageTableFlags> {
+        let mapper = unsafe { OffsetPageTable::new(table_at(self.p4), physical_memory_offset()) };
+        match mapper.translate(addr) {
+            TranslateResult::Mapped { flags, .. } => Some(flags),
+            _ => None,
+        }
+    }
+
+    /// Copies `data` to `addr` in this address space through the physical
+    /// memory mapping. The address space does not have to be active, and
+    /// only its user slot can be written. Copy-on-write pages are copied
+    /// first.
+    pub fn write(&mut self, addr: VirtAddr, data: &[u8]) -> Result<(), MapError> {
+        let end = addr.as_u64().checked_add(data.len() as u64);
+        if addr.as_u64() < USER_START || end.is_none_or(|end| end > USER_END) {
+            return Err(MapError::OutsideUserRegion);
+        }
+        let mut done = 0;
+        while done < data.len() {
+            let target = addr + done as u64;
+            self.break_cow(target)?;
+            let mapper =
+                unsafe { OffsetPageTable::new(table_at(self.p4), physical_memory_offset()) };
+            let phys = mapper.translate_addr(target).ok_or(MapError::NotMapped)?;
+            let count = (4096 - usize::from(target.page_offset())).min(data.len() - done);
+            let dst = physical_memory_offset() + phys.as_u64();
+            unsafe {
+                core::ptr::copy_nonoverlapping(data[done..].as_ptr(), dst.as_mut_ptr(), count)
+            };
+            done += count;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Must not run while the address space is active.
+    fn drop(&mut self) {
+        for frame in self.frames.drain(..) {
+            free_frame(frame);
+        }
+        free_frame(self.p4);
+    }
+}
@@ -0,0 +1,204 @@
+use core::{
+    arch::asm,
+    fmt::{self, Write},
+    ops::Range,
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::{
+    instructions::interrupts,
+    registers::{
+        control::{Cr0, Cr2, Cr3, Cr4},
+        rflags,
+    },
+    VirtAddr,
+};
+
+use crate::{
+    gdt, hlt_loop, ksyms, memory,
+    serial::{uart, SERIAL1},
+    smp, thread,
+    vga_buffer::{Color, WRITER},
+};
+
+/// Leaves room on the screen for a message spanning two lines.
+const MAX_FRAMES: usize = 10;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+const GPR_NAMES: [&str; 16] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+const RBP: usize = 6;
+const RSP: usize = 7;
+
+struct Registers {
+    /// In the order of [`GPR_NAMES`]. The register holding the address of
+    /// the array is stored as that address.
+    gprs: [u64; 16],
+    rip: u64,
+    rflags: u64,
+    cr0: u64,
+    cr2: u64,
+    cr3: u64,
+    cr4: u64,
+}
+
+impl Registers {
+    #[inline(always)]
+    fn capture() -> Self {
+        let mut gprs = [0u64; 16];
+        let rip: u64;
+        unsafe {
+            asm!(
+                "mov [{0}], rax",
+                "mov [{0} + 0x08], rbx",
+                "mov [{0} + 0x10], rcx",
+                "mov [{0} + 0x18], rdx",
+                "mov [{0} + 0x20], rsi",
+                "mov [{0} + 0x28], rdi",
+                "mov [{0} + 0x30], rbp",
+                "mov [{0} + 0x38], rsp",
+                "mov [{0} + 0x40], r8",
+                "mov [{0} + 0x48], r9",
+                "mov [{0} + 0x50], r10",
+                "mov [{0} + 0x58], r11",
+                "mov [{0} + 0x60], r12",
+                "mov [{0} + 0x68], r13",
+                "mov [{0} + 0x70], r14",
+                "mov [{0} + 0x78], r15",
+                in(reg) gprs.as_mut_ptr(),
+                options(nostack, preserves_flags)
+            );
+            asm!("lea {}, [rip]", out(reg) rip, options(nomem, nostack));
+        }
+        Registers {
+            gprs,
+            rip,
+            rflags: rflags::read_raw(),
+            cr0: Cr0::read_raw(),
+            cr2: Cr2::read_raw(),
+            cr3: Cr3::read_raw().0.start_address().as_u64(),
+            cr4: Cr4::read_raw(),
+        }
+    }
+}
+
+/// Writes to the VGA console and mirrors everything to `SERIAL1`.
+struct CrashConsole;
+
+impl Write for CrashConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        WRITER.lock().write_string(s);
+        let mut serial = SERIAL1.lock();
+        serial.write_str(s)?;
+        serial.flush();
+        Ok(())
+    }
+}
+
+/// Panic path for the running kernel.
+///
+/// The console locks may be held by the code that panicked, so they are
+/// forcibly released before painting the report.
+pub fn kernel_panic(info: &PanicInfo) -> ! {
+    interrupts::disable();
+    let registers = Registers::capture();
+    smp::halt_others();
+
+    unsafe {
+        WRITER.force_unlock();
+        SERIAL1.force_unlock();
+        uart::force_unlock(0);
+    }
+
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        let _ = writeln!(CrashConsole, "\npanicked while panicking: {}", info);
+        hlt_loop();
+    }
+
+    {
+        let mut writer = WRITER.lock();
+        writer.set_colors(Color::White, Color::Red);
+        writer.clear_screen();
+    }
+
+    let mut out = CrashConsole;
+    let _ = write_report(&mut out, info, &registers);
+    hlt_loop();
+}
+
+fn write_report(out: &mut CrashConsole, info: &PanicInfo, regs: &Registers) -> fmt::Result {
+    writeln!(out, "KERNEL PANIC")?;
+    // includes both the location and the message
+    writeln!(out, "{}", info)?;
+    writeln!(out)?;
+    let named = GPR_NAMES
+        .into_iter()
+        .zip(regs.gprs)
+        .chain([("rip", regs.rip), ("rfl", regs.rflags)]);
+    for (column, (name, value)) in named.enumerate() {
+        write!(out, "{:<3} {:#018x}", name, value)?;
+        // three to a line, which fits the 80 column screen
+        if column % 3 == 2 {
+            writeln!(out)?;
+        } else {
+            write!(out, " ")?;
+        }
+    }
+    writeln!(
+        out,
+        "cr0 {:#x} cr2 {:#018x} cr3 {:#018x} cr4 {:#x}",
+        regs.cr0, regs.cr2, regs.cr3, regs.cr4
+    )?;
+    writeln!(out)?;
+    writeln!(out, "backtrace:")?;
+    backtrace(out, regs.gprs[RBP], stack_range(regs.gprs[RSP]))
+}
+
+/// Addresses the frames of the panicking code can lie at: from `rsp` up to
+/// the top of the kernel stack it is on. Only the page of `rsp` itself is
+/// known to be mapped if that is none of the stacks known here, e.g. an AP's.
+fn stack_range(rsp: u64) -> Range<u64> {
+    let thread_top = gdt::kernel_stack().as_u64();
+    let stacks = [
+        thread_top.saturating_sub(thread::STACK_SIZE as u64)..thread_top,
+        memory::BOOT_STACK_TOP - memory::BOOT_STACK_SIZE..memory::BOOT_STACK_TOP,
+        gdt::double_fault_stack(),
+    ];
+    match stacks.into_iter().find(|stack| stack.contains(&rsp)) {
+        Some(stack) => rsp..stack.end,
+        None => rsp..(rsp & !0xfff) + 0x1000,
+    }
+}
+
+/// Walks the frame pointer chain; needs `frame-pointer: always` in the target.
+/// Stops at the first frame pointer that could not point into `stack`, a
+/// corrupted chain must not fault the panic screen.
+fn backtrace(out: &mut CrashConsole, mut rbp: u64, stack: Range<u64>) -> fmt::Result {
+    for depth in 0..MAX_FRAMES {
+        // the frame holds the saved rbp and the return address
+        let valid = rbp.is_multiple_of(8)
+            && VirtAddr::try_new(rbp).is_ok()
+            && rbp >= stack.start
+            && rbp.checked_add(16).is_some_and(|end| end <= stack.end);
+        if !valid {
+            break;
+        }
+        let (next, return_addr) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+        if return_addr == 0 {
+            break;
+        }
+        match ksyms::lookup(return_addr) {
+            Some((name, offset)) => writeln!(out, "{:>2}: {:#x} {}+{:#x}", depth, return_addr, name, offset)?,
+            None => writeln!(out, "{:>2}: {:#x}", depth, return_addr)?,
+        }
+        // the stack grows down, so older frames live at higher addresses
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
+    Ok(())
+}
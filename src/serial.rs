@@ -1,25 +1,61 @@
-use lazy_static::lazy_static;
-use spin::Mutex;
-use uart_16550::SerialPort;
-
-lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-        serial_port.init();
-        Mutex::new(serial_port)
-    };
+use crate::sync::IrqSpinlock;
+
+pub mod uart;
+
+use uart::{SerialPort, COM_IRQS, PORT_COUNT};
+
+/// COM1 to COM4. Ports initialize themselves on first use; [`init`] probes
+/// them and enables interrupt driven I/O.
+pub static SERIAL_PORTS: [IrqSpinlock<SerialPort>; PORT_COUNT] = [
+    IrqSpinlock::named("serial::COM1", SerialPort::new(0)),
+    IrqSpinlock::named("serial::COM2", SerialPort::new(1)),
+    IrqSpinlock::named("serial::COM3", SerialPort::new(2)),
+    IrqSpinlock::named("serial::COM4", SerialPort::new(3)),
+];
+
+/// COM1, used for test output and the serial console.
+pub static SERIAL1: &IrqSpinlock<SerialPort> = &SERIAL_PORTS[0];
+
+/// COM2, reserved for the GDB remote stub.
+pub static SERIAL2: &IrqSpinlock<SerialPort> = &SERIAL_PORTS[1];
+
+/// Probes COM1–COM4 and enables interrupts on the ports that exist.
+pub fn init() {
+    for (index, port) in SERIAL_PORTS.iter().enumerate() {
+        let mut port = port.lock();
+        if port.probe() {
+            port.init();
+            crate::interuptions::unmask_irq(COM_IRQS[index]);
+        }
+    }
+}
+
+/// Returns the port `index` (0 for COM1) if it was found by [`init`].
+pub fn port(index: usize) -> Option<&'static IrqSpinlock<SerialPort>> {
+    SERIAL_PORTS
+        .get(index)
+        .filter(|port| port.lock().is_present())
+}
+
+/// Called by the interrupt handlers for IRQ 3 and 4.
+/// Returns whether any input arrived.
+pub(crate) fn handle_interrupt(irq: u8) -> bool {
+    let received = uart::handle_interrupt(irq);
+    for index in 0..PORT_COUNT {
+        if received & (1 << index) != 0 {
+            crate::task::serial::wake(index);
+        }
+    }
+    received != 0
 }
 
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts; // new
-    interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed");
-    })
+    SERIAL1
+        .lock()
+        .write_fmt(args)
+        .expect("Printing to serial failed");
 }
 
 /// Prints to the host through the serial interface.
@@ -0,0 +1,447 @@
+//! Driver for the 16550 UARTs behind the legacy COM1–COM4 ports.
+//!
+//! Received bytes are queued by the interrupt handler; transmitted bytes are
+//! queued by writers and drained by the "transmitter empty" interrupt, or by
+//! polling when the queue is full or interrupts are disabled. Optional RTS/CTS
+//! flow control holds back the peer when the receive queue fills up and
+//! pauses transmission while CTS is deasserted.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::{
+    byte_queue::{ByteQueue, QUEUE_SIZE},
+    sync::IrqSpinlock,
+};
+
+pub const PORT_COUNT: usize = 4;
+pub const COM_BASES: [u16; PORT_COUNT] = [0x3F8, 0x2F8, 0x3E8, 0x2E8];
+/// COM1/COM3 share IRQ 4 and COM2/COM4 share IRQ 3.
+pub const COM_IRQS: [u8; PORT_COUNT] = [4, 3, 4, 3];
+
+const UART_CLOCK: u32 = 115_200;
+
+// register offsets
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+/// Interrupt identification on read, FIFO control on write.
+const INTERRUPT_ID: u16 = 2;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const MODEM_STATUS: u16 = 6;
+const SCRATCH: u16 = 7;
+
+const IER_RECEIVED: u8 = 0x01;
+const IER_TRANSMIT_EMPTY: u8 = 0x02;
+const IER_MODEM_STATUS: u8 = 0x08;
+const LCR_DLAB: u8 = 0x80;
+const MCR_DTR: u8 = 0x01;
+const MCR_RTS: u8 = 0x02;
+const MCR_OUT2: u8 = 0x08;
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_TRANSMIT_EMPTY: u8 = 0x20;
+const MSR_CTS: u8 = 0x10;
+const FIFO_DEPTH: usize = 16;
+
+/// Receive queue levels at which RTS is dropped and raised again.
+const RTS_HIGH_WATER: usize = QUEUE_SIZE * 3 / 4;
+const RTS_LOW_WATER: usize = QUEUE_SIZE / 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+}
+
+impl Parity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Parity::None => "none",
+            Parity::Odd => "odd",
+            Parity::Even => "even",
+            Parity::Mark => "mark",
+            Parity::Space => "space",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Parity> {
+        [
+            Parity::None,
+            Parity::Odd,
+            Parity::Even,
+            Parity::Mark,
+            Parity::Space,
+        ]
+        .iter()
+        .copied()
+        .find(|p| p.name() == name)
+    }
+
+    fn line_control_bits(self) -> u8 {
+        match self {
+            Parity::None => 0b000,
+            Parity::Odd => 0b001,
+            Parity::Even => 0b011,
+            Parity::Mark => 0b101,
+            Parity::Space => 0b111,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineConfig {
+    pub baud: u32,
+    /// 5 to 8
+    pub data_bits: u8,
+    pub parity: Parity,
+    /// 1 or 2
+    pub stop_bits: u8,
+    /// RTS/CTS hardware flow control
+    pub flow_control: bool,
+}
+
+impl LineConfig {
+    pub const DEFAULT: LineConfig = LineConfig {
+        baud: 38_400,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: 1,
+        flow_control: false,
+    };
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.baud == 0 || self.baud > UART_CLOCK || !UART_CLOCK.is_multiple_of(self.baud) {
+            return Err("baud rate must divide 115200");
+        }
+        if !(5..=8).contains(&self.data_bits) {
+            return Err("data bits must be 5 to 8");
+        }
+        if self.stop_bits != 1 && self.stop_bits != 2 {
+            return Err("stop bits must be 1 or 2");
+        }
+        Ok(())
+    }
+
+    fn line_control(&self) -> u8 {
+        let stop = if self.stop_bits == 2 { 1 << 2 } else { 0 };
+        (self.data_bits - 5) | stop | self.parity.line_control_bits() << 3
+    }
+}
+
+impl fmt::Display for LineConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} baud, {} data bits, parity {}, {} stop bit(s), {}crtscts",
+            self.baud,
+            self.data_bits,
+            self.parity.name(),
+            self.stop_bits,
+            if self.flow_control { "" } else { "-" }
+        )
+    }
+}
+
+/// State shared between a port's owner and the interrupt handler, which runs
+/// without the port lock.
+struct PortBuffers {
+    rx: ByteQueue,
+    /// Single consumer: only popped with `tx_lock` held, the interrupt may
+    /// run on another CPU than the writer draining it by polling.
+    tx: ByteQueue,
+    tx_lock: IrqSpinlock<()>,
+    flow_control: AtomicBool,
+    rts_held: AtomicBool,
+    present: AtomicBool,
+}
+
+static BUFFERS: [PortBuffers; PORT_COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: PortBuffers = PortBuffers {
+        rx: ByteQueue::new(),
+        tx: ByteQueue::new(),
+        tx_lock: IrqSpinlock::named("serial::tx", ()),
+        flow_control: AtomicBool::new(false),
+        rts_held: AtomicBool::new(false),
+        present: AtomicBool::new(false),
+    };
+    [EMPTY; PORT_COUNT]
+};
+
+fn read_reg(base: u16, offset: u16) -> u8 {
+    unsafe { Port::new(base + offset).read() }
+}
+
+fn write_reg(base: u16, offset: u16, value: u8) {
+    unsafe { Port::new(base + offset).write(value) }
+}
+
+pub struct SerialPort {
+    index: usize,
+    base: u16,
+    config: LineConfig,
+    initialized: bool,
+}
+
+impl SerialPort {
+    pub const fn new(index: usize) -> Self {
+        SerialPort {
+            index,
+            base: COM_BASES[index],
+            config: LineConfig::DEFAULT,
+            initialized: false,
+        }
+    }
+
+    fn buffers(&self) -> &'static PortBuffers {
+        &BUFFERS[self.index]
+    }
+
+    pub fn name(&self) -> &'static str {
+        ["COM1", "COM2", "COM3", "COM4"][self.index]
+    }
+
+    /// Checks for a UART by writing and reading back the scratch register.
+    pub fn probe(&self) -> bool {
+        let present = [0x55, 0xAA].iter().all(|pattern| {
+            write_reg(self.base, SCRATCH, *pattern);
+            read_reg(self.base, SCRATCH) == *pattern
+        });
+        self.buffers().present.store(present, Ordering::Relaxed);
+        present
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.buffers().present.load(Ordering::Relaxed)
+    }
+
+    /// Programs the line settings and enables receive, transmit and modem
+    /// status interrupts. The IRQ itself is unmasked by `serial::init`.
+    pub fn init(&mut self) {
+        self.initialized = true;
+        write_reg(self.base, INTERRUPT_ENABLE, 0);
+        self.apply_config();
+        // enable and clear FIFOs, 14 byte receive threshold
+        write_reg(self.base, FIFO_CONTROL, 0xC7);
+        write_reg(self.base, MODEM_CONTROL, MCR_DTR | MCR_RTS | MCR_OUT2);
+        write_reg(
+            self.base,
+            INTERRUPT_ENABLE,
+            IER_RECEIVED | IER_TRANSMIT_EMPTY | IER_MODEM_STATUS,
+        );
+    }
+
+    fn ensure_initialized(&mut self) {
+        if !self.initialized {
+            self.init();
+        }
+    }
+
+    fn apply_config(&mut self) {
+        let divisor = (UART_CLOCK / self.config.baud) as u16;
+        write_reg(self.base, LINE_CONTROL, LCR_DLAB);
+        write_reg(self.base, DATA, divisor as u8);
+        write_reg(self.base, INTERRUPT_ENABLE, (divisor >> 8) as u8);
+        write_reg(self.base, LINE_CONTROL, self.config.line_control());
+        self.buffers()
+            .flow_control
+            .store(self.config.flow_control, Ordering::Relaxed);
+    }
+
+    pub fn config(&self) -> LineConfig {
+        self.config
+    }
+
+    /// Changes the line settings after draining pending output.
+    pub fn set_config(&mut self, config: LineConfig) -> Result<(), &'static str> {
+        config.validate()?;
+        self.ensure_initialized();
+        self.flush();
+        interrupts::without_interrupts(|| {
+            let enabled = read_reg(self.base, INTERRUPT_ENABLE);
+            self.config = config;
+            self.apply_config();
+            write_reg(self.base, INTERRUPT_ENABLE, enabled);
+        });
+        Ok(())
+    }
+
+    /// Queues a byte for transmission, draining by polling if the queue is full.
+    pub fn send(&mut self, byte: u8) {
+        self.ensure_initialized();
+        let _tx = self.buffers().tx_lock.lock();
+        while !self.buffers().tx.push(byte) {
+            transmit_one(self.index, true);
+        }
+        // start the transmitter; later bytes follow from the interrupt
+        transmit_one(self.index, false);
+    }
+
+    /// Waits until all queued bytes have been handed to the UART.
+    pub fn flush(&mut self) {
+        drain(self.index);
+    }
+
+    pub fn try_receive(&mut self) -> Option<u8> {
+        self.ensure_initialized();
+        let buffers = self.buffers();
+        let byte = interrupts::without_interrupts(|| {
+            buffers.rx.pop().or_else(|| {
+                // with interrupts disabled nothing else drains the UART
+                if read_reg(self.base, LINE_STATUS) & LSR_DATA_READY != 0 {
+                    Some(read_reg(self.base, DATA))
+                } else {
+                    None
+                }
+            })
+        });
+        if buffers.rts_held.load(Ordering::Relaxed) && buffers.rx.len() <= RTS_LOW_WATER {
+            buffers.rts_held.store(false, Ordering::Relaxed);
+            write_reg(self.base, MODEM_CONTROL, MCR_DTR | MCR_RTS | MCR_OUT2);
+        }
+        byte
+    }
+
+    /// Busy-waits for the next received byte.
+    pub fn receive(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.try_receive() {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Number of received bytes dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.buffers().rx.dropped()
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Hands every queued byte of port `index` to the UART without taking the
+/// port lock, e.g. before the machine is switched off.
+pub fn drain(index: usize) {
+    let _tx = BUFFERS[index].tx_lock.lock();
+    while !BUFFERS[index].tx.is_empty() {
+        transmit_one(index, true);
+    }
+}
+
+/// Releases the transmit queue lock of port `index`.
+///
+/// # Safety
+///
+/// Same as [`IrqSpinlock::force_unlock`].
+pub unsafe fn force_unlock(index: usize) {
+    BUFFERS[index].tx_lock.force_unlock();
+}
+
+/// Writes straight to the UART of port `index`, bypassing the port lock.
+/// Queued output goes first. For diagnostics from code that may itself hold
+/// the lock.
+pub fn write_unlocked(index: usize, bytes: &[u8]) {
+    let base = COM_BASES[index];
+    let _tx = BUFFERS[index].tx_lock.lock();
+    while !BUFFERS[index].tx.is_empty() {
+        transmit_one(index, true);
+    }
+    for byte in bytes {
+        while read_reg(base, LINE_STATUS) & LSR_TRANSMIT_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        write_reg(base, DATA, *byte);
+    }
+}
+
+fn clear_to_send(index: usize) -> bool {
+    !BUFFERS[index].flow_control.load(Ordering::Relaxed)
+        || read_reg(COM_BASES[index], MODEM_STATUS) & MSR_CTS != 0
+}
+
+/// Moves one byte from the transmit queue to the UART if it can take it, or
+/// waits until it can if `wait` is set. The caller holds `tx_lock`.
+fn transmit_one(index: usize, wait: bool) {
+    let base = COM_BASES[index];
+    loop {
+        if read_reg(base, LINE_STATUS) & LSR_TRANSMIT_EMPTY != 0 && clear_to_send(index) {
+            if let Some(byte) = BUFFERS[index].tx.pop() {
+                write_reg(base, DATA, byte);
+            }
+            return;
+        }
+        if !wait {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Services every present port on `irq`. Returns a bit mask of the ports that
+/// received data.
+pub(crate) fn handle_interrupt(irq: u8) -> u8 {
+    let mut received = 0;
+    for index in 0..PORT_COUNT {
+        if COM_IRQS[index] != irq || !BUFFERS[index].present.load(Ordering::Relaxed) {
+            continue;
+        }
+        // reading the identification register acknowledges "transmitter
+        // empty"; keep going until the UART deasserts its interrupt line
+        for _ in 0..FIFO_DEPTH {
+            if read_reg(COM_BASES[index], INTERRUPT_ID) & 1 != 0 {
+                break;
+            }
+            if service_port(index) {
+                received |= 1 << index;
+            }
+        }
+    }
+    received
+}
+
+fn service_port(index: usize) -> bool {
+    let buffers = &BUFFERS[index];
+    let base = COM_BASES[index];
+    let flow_control = buffers.flow_control.load(Ordering::Relaxed);
+
+    let mut received = false;
+    while read_reg(base, LINE_STATUS) & LSR_DATA_READY != 0 {
+        buffers.rx.push(read_reg(base, DATA));
+        received = true;
+    }
+    if flow_control
+        && buffers.rx.len() >= RTS_HIGH_WATER
+        && !buffers.rts_held.swap(true, Ordering::Relaxed)
+    {
+        write_reg(base, MODEM_CONTROL, MCR_DTR | MCR_OUT2);
+    }
+
+    // reading the modem status acknowledges a CTS change
+    let cts = read_reg(base, MODEM_STATUS) & MSR_CTS != 0;
+    if (cts || !flow_control) && read_reg(base, LINE_STATUS) & LSR_TRANSMIT_EMPTY != 0 {
+        let _tx = buffers.tx_lock.lock();
+        for _ in 0..FIFO_DEPTH {
+            match buffers.tx.pop() {
+                Some(byte) => write_reg(base, DATA, byte),
+                None => break,
+            }
+        }
+    }
+    received
+}
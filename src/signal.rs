@@ -0,0 +1,488 @@
+//! POSIX-like signals for user programs.
+//!
+//! A signal sent to a thread is recorded as pending and acted on right
+//! before the thread next returns to ring 3: after a system call, or when the
+//! scheduler resumes it from an interrupt. Blocked signals stay pending until
+//! unblocked. Without a handler the default action runs, which terminates,
+//! stops or continues the program or ignores the signal.
+//!
+//! A handler is entered with the interrupted registers saved in a
+//! [`SignalFrame`] on the user stack and returns into the signal trampoline,
+//! a page the loader maps into every program that calls `sigreturn`.
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+use crate::{
+    memory::address_space::{AddressSpace, MapError, COPY_ON_WRITE, USER_END, USER_START},
+    sync::IrqSpinlock,
+    syscall::{user, Errno},
+    thread::{self, context::InterruptContext, ThreadId},
+};
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Signal {
+    SIGHUP = 1,
+    SIGINT = 2,
+    SIGQUIT = 3,
+    SIGILL = 4,
+    SIGABRT = 6,
+    SIGKILL = 9,
+    SIGUSR1 = 10,
+    SIGSEGV = 11,
+    SIGUSR2 = 12,
+    SIGPIPE = 13,
+    SIGALRM = 14,
+    SIGTERM = 15,
+    SIGCHLD = 17,
+    SIGCONT = 18,
+    SIGSTOP = 19,
+    SIGTSTP = 20,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+impl Signal {
+    const ALL: [Signal; 16] = [
+        Signal::SIGHUP,
+        Signal::SIGINT,
+        Signal::SIGQUIT,
+        Signal::SIGILL,
+        Signal::SIGABRT,
+        Signal::SIGKILL,
+        Signal::SIGUSR1,
+        Signal::SIGSEGV,
+        Signal::SIGUSR2,
+        Signal::SIGPIPE,
+        Signal::SIGALRM,
+        Signal::SIGTERM,
+        Signal::SIGCHLD,
+        Signal::SIGCONT,
+        Signal::SIGSTOP,
+        Signal::SIGTSTP,
+    ];
+
+    pub fn from_number(number: u64) -> Option<Signal> {
+        Signal::ALL
+            .iter()
+            .copied()
+            .find(|signal| *signal as u64 == number)
+    }
+
+    /// Accepts `INT` as well as `SIGINT`.
+    pub fn from_name(name: &str) -> Option<Signal> {
+        let name = name.strip_prefix("SIG").unwrap_or(name);
+        Signal::ALL
+            .iter()
+            .copied()
+            .find(|signal| &signal.name()[3..] == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Signal::SIGHUP => "SIGHUP",
+            Signal::SIGINT => "SIGINT",
+            Signal::SIGQUIT => "SIGQUIT",
+            Signal::SIGILL => "SIGILL",
+            Signal::SIGABRT => "SIGABRT",
+            Signal::SIGKILL => "SIGKILL",
+            Signal::SIGUSR1 => "SIGUSR1",
+            Signal::SIGSEGV => "SIGSEGV",
+            Signal::SIGUSR2 => "SIGUSR2",
+            Signal::SIGPIPE => "SIGPIPE",
+            Signal::SIGALRM => "SIGALRM",
+            Signal::SIGTERM => "SIGTERM",
+            Signal::SIGCHLD => "SIGCHLD",
+            Signal::SIGCONT => "SIGCONT",
+            Signal::SIGSTOP => "SIGSTOP",
+            Signal::SIGTSTP => "SIGTSTP",
+        }
+    }
+
+    pub fn default_action(self) -> DefaultAction {
+        match self {
+            Signal::SIGCHLD => DefaultAction::Ignore,
+            Signal::SIGCONT => DefaultAction::Continue,
+            Signal::SIGSTOP | Signal::SIGTSTP => DefaultAction::Stop,
+            _ => DefaultAction::Terminate,
+        }
+    }
+
+    /// SIGKILL and SIGSTOP can be neither caught, ignored nor blocked.
+    pub fn is_catchable(self) -> bool {
+        !matches!(self, Signal::SIGKILL | Signal::SIGSTOP)
+    }
+
+    fn bit(self) -> u64 {
+        1 << (self as u8)
+    }
+}
+
+/// A set of signals, bit `n` standing for signal number `n` as in the
+/// masks passed to `sigprocmask`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SignalSet(pub u64);
+
+impl SignalSet {
+    pub fn contains(self, signal: Signal) -> bool {
+        self.0 & signal.bit() != 0
+    }
+
+    pub fn insert(&mut self, signal: Signal) {
+        self.0 |= signal.bit();
+    }
+
+    pub fn remove(&mut self, signal: Signal) {
+        self.0 &= !signal.bit();
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The lowest numbered signal in the set.
+    pub fn first(self) -> Option<Signal> {
+        Signal::ALL
+            .iter()
+            .copied()
+            .find(|&signal| self.contains(signal))
+    }
+}
+
+/// What a thread does with a signal, set with `sigaction`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    Default,
+    Ignore,
+    /// Address of the handler in the program.
+    Handler(u64),
+}
+
+/// `sigaction` handler values standing for the default action and ignore.
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+impl Disposition {
+    pub fn from_u64(value: u64) -> Disposition {
+        match value {
+            SIG_DFL => Disposition::Default,
+            SIG_IGN => Disposition::Ignore,
+            handler => Disposition::Handler(handler),
+        }
+    }
+
+    pub fn as_u64(self) -> u64 {
+        match self {
+            Disposition::Default => SIG_DFL,
+            Disposition::Ignore => SIG_IGN,
+            Disposition::Handler(handler) => handler,
+        }
+    }
+}
+
+/// Pending and blocked signals and the dispositions of one thread.
+#[derive(Debug, Clone)]
+pub struct SignalState {
+    pub pending: SignalSet,
+    blocked: SignalSet,
+    dispositions: [Disposition; 32],
+}
+
+impl SignalState {
+    pub fn new() -> Self {
+        SignalState {
+            pending: SignalSet::default(),
+            blocked: SignalSet::default(),
+            dispositions: [Disposition::Default; 32],
+        }
+    }
+
+    pub fn disposition(&self, signal: Signal) -> Disposition {
+        self.dispositions[signal as usize]
+    }
+
+    /// Returns the previous disposition, or `None` for a signal that cannot
+    /// be caught.
+    pub fn set_disposition(
+        &mut self,
+        signal: Signal,
+        disposition: Disposition,
+    ) -> Option<Disposition> {
+        if !signal.is_catchable() {
+            return None;
+        }
+        if self.is_ignored(disposition, signal) {
+            // ignoring a signal discards it if it is already pending
+            self.pending.remove(signal);
+        }
+        Some(core::mem::replace(
+            &mut self.dispositions[signal as usize],
+            disposition,
+        ))
+    }
+
+    pub fn blocked(&self) -> SignalSet {
+        self.blocked
+    }
+
+    pub fn set_blocked(&mut self, mut blocked: SignalSet) {
+        blocked.remove(Signal::SIGKILL);
+        blocked.remove(Signal::SIGSTOP);
+        self.blocked = blocked;
+    }
+
+    /// Pending signals that are not blocked.
+    pub fn deliverable(&self) -> SignalSet {
+        SignalSet(self.pending.0 & !self.blocked.0)
+    }
+
+    /// Marks `signal` pending unless the thread ignores it. Returns whether
+    /// it was.
+    pub fn generate(&mut self, signal: Signal) -> bool {
+        // stopping and continuing cancel each other
+        match signal.default_action() {
+            DefaultAction::Stop => self.pending.remove(Signal::SIGCONT),
+            DefaultAction::Continue => {
+                self.pending.remove(Signal::SIGSTOP);
+                self.pending.remove(Signal::SIGTSTP);
+            }
+            _ => {}
+        }
+        if self.is_ignored(self.disposition(signal), signal) && !self.blocked.contains(signal) {
+            return false;
+        }
+        self.pending.insert(signal);
+        true
+    }
+
+    /// State of a forked copy: the same dispositions and mask, nothing
+    /// pending.
+    pub fn forked(&self) -> Self {
+        SignalState {
+            pending: SignalSet::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Caught signals return to their default action when a new program
+    /// image replaces the handlers; ignored ones stay ignored.
+    pub fn reset_handlers(&mut self) {
+        for disposition in self.dispositions.iter_mut() {
+            if let Disposition::Handler(_) = disposition {
+                *disposition = Disposition::Default;
+            }
+        }
+    }
+
+    /// Whether delivering `signal` would do nothing. A stopped thread is
+    /// continued when SIGCONT is sent, not when it is delivered.
+    fn is_ignored(&self, disposition: Disposition, signal: Signal) -> bool {
+        match disposition {
+            Disposition::Ignore => true,
+            Disposition::Default => matches!(
+                signal.default_action(),
+                DefaultAction::Ignore | DefaultAction::Continue
+            ),
+            Disposition::Handler(_) => false,
+        }
+    }
+}
+
+impl Default for SignalState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What the thread has to do before it may return to ring 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Return, possibly into a handler.
+    Resume,
+    Terminate(Signal),
+    Stop,
+}
+
+/// Pushed below the interrupted stack pointer when a handler is entered.
+/// The handler sees `return_address` as if it had been called, pointing at
+/// the trampoline.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SignalFrame {
+    pub return_address: u64,
+    pub signal: u64,
+    /// Blocked signals to restore on `sigreturn`.
+    pub blocked: u64,
+    pub context: InterruptContext,
+}
+
+/// Below the stack pointer, the System V ABI lets functions use these bytes
+/// without moving it.
+const RED_ZONE: u64 = 128;
+
+/// Flags a program may change through the saved context: the arithmetic
+/// flags, TF and DF.
+const USER_FLAGS: u64 = 0xDD5;
+const DIRECTION_FLAG: u64 = 1 << 10;
+
+/// Acts on the lowest numbered deliverable signal of a thread about to
+/// return to ring 3 with `context`. At most one handler is entered at a
+/// time, further signals follow once it returns.
+pub fn deliver(
+    state: &mut SignalState,
+    context: &mut InterruptContext,
+    space: &mut AddressSpace,
+) -> Delivery {
+    while let Some(signal) = state.deliverable().first() {
+        state.pending.remove(signal);
+        match state.disposition(signal) {
+            Disposition::Ignore => {}
+            Disposition::Default => match signal.default_action() {
+                DefaultAction::Terminate => return Delivery::Terminate(signal),
+                DefaultAction::Stop => return Delivery::Stop,
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+            },
+            Disposition::Handler(handler) => {
+                if enter_handler(state, signal, handler, context, space).is_err() {
+                    return Delivery::Terminate(Signal::SIGSEGV);
+                }
+                return Delivery::Resume;
+            }
+        }
+    }
+    Delivery::Resume
+}
+
+fn enter_handler(
+    state: &mut SignalState,
+    signal: Signal,
+    handler: u64,
+    context: &mut InterruptContext,
+    space: &mut AddressSpace,
+) -> Result<(), MapError> {
+    let size = size_of::<SignalFrame>() as u64;
+    let below = context
+        .frame
+        .rsp
+        .checked_sub(RED_ZONE + size + 16)
+        .ok_or(MapError::OutsideUserRegion)?;
+    // at function entry `rsp + 8` is 16 byte aligned
+    let address = (below & !0xf) + 8;
+    let frame = SignalFrame {
+        return_address: crate::exec::SIGNAL_TRAMPOLINE,
+        signal: signal as u64,
+        blocked: state.blocked.0,
+        context: *context,
+    };
+    // the frame must go where the program itself could have written it
+    let mut page = address & !0xfff;
+    while page < address + size {
+        let flags = space
+            .page_flags(VirtAddr::new(page))
+            .ok_or(MapError::NotMapped)?;
+        let writable = flags.intersects(PageTableFlags::WRITABLE | COPY_ON_WRITE);
+        if !writable || !flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+            return Err(MapError::NotMapped);
+        }
+        page += 4096;
+    }
+    let bytes =
+        unsafe { core::slice::from_raw_parts(&frame as *const _ as *const u8, size as usize) };
+    space.write(VirtAddr::new(address), bytes)?;
+
+    let mut blocked = state.blocked;
+    blocked.insert(signal);
+    state.set_blocked(blocked);
+    context.frame.rip = handler;
+    context.frame.rsp = address;
+    context.frame.rflags &= !DIRECTION_FLAG;
+    context.regs.rdi = signal as u64;
+    Ok(())
+}
+
+/// Returns from a handler: restores the context and blocked signals saved
+/// in the frame just above the stack pointer of `context`, which the
+/// handler's `ret` popped the return address off. Returns the restored
+/// `rax`, or fails if the frame points outside the user region.
+pub fn sigreturn(context: &mut InterruptContext) -> Result<u64, Errno> {
+    let address = context.frame.rsp.wrapping_sub(8);
+    let bytes = unsafe { user::slice(address, size_of::<SignalFrame>())? };
+    let frame = unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const SignalFrame) };
+
+    let saved = frame.context;
+    // `iretq` to a kernel or non-canonical address would fault in ring 0
+    if !(USER_START..USER_END).contains(&saved.frame.rip)
+        || !(USER_START..=USER_END).contains(&saved.frame.rsp)
+    {
+        return Err(Errno::EFAULT);
+    }
+    context.regs = saved.regs;
+    context.frame.rip = saved.frame.rip;
+    context.frame.rsp = saved.frame.rsp;
+    // segments and privileged flags are not the program's to choose
+    context.frame.rflags = (context.frame.rflags & !USER_FLAGS) | (saved.frame.rflags & USER_FLAGS);
+    thread::set_blocked_signals(SignalSet(frame.blocked));
+    Ok(saved.regs.rax)
+}
+
+/// Programs the shell runs in the foreground, interrupted by Ctrl+C.
+static FOREGROUND: IrqSpinlock<Vec<ThreadId>> =
+    IrqSpinlock::named("signal::FOREGROUND", Vec::new());
+
+pub fn add_foreground(id: ThreadId) {
+    FOREGROUND.lock().push(id);
+}
+
+pub fn remove_foreground(id: ThreadId) {
+    FOREGROUND.lock().retain(|&fg| fg != id);
+}
+
+/// Sends SIGINT to the foreground job. Returns false if there is none.
+/// Called by the keyboard interrupt on Ctrl+C, so it must not allocate.
+pub fn interrupt_foreground() -> bool {
+    let foreground = FOREGROUND.lock();
+    for &id in foreground.iter() {
+        // it may have exited already
+        let _ = thread::send_signal(id, Signal::SIGINT);
+    }
+    !foreground.is_empty()
+}
+
+#[test_case]
+fn test_signal_names_round_trip() {
+    for signal in Signal::ALL {
+        assert_eq!(Signal::from_number(signal as u64), Some(signal));
+        assert_eq!(Signal::from_name(signal.name()), Some(signal));
+    }
+    assert_eq!(Signal::from_name("INT"), Some(Signal::SIGINT));
+    assert_eq!(Signal::from_number(0), None);
+}
+
+#[test_case]
+fn test_ignored_and_blocked_signals() {
+    let mut state = SignalState::new();
+    assert!(!state.generate(Signal::SIGCHLD));
+    assert_eq!(
+        state.set_disposition(Signal::SIGINT, Disposition::Ignore),
+        Some(Disposition::Default)
+    );
+    assert!(!state.generate(Signal::SIGINT));
+    assert_eq!(
+        state.set_disposition(Signal::SIGKILL, Disposition::Ignore),
+        None
+    );
+
+    state.set_blocked(SignalSet(u64::MAX));
+    assert!(state.generate(Signal::SIGTERM));
+    assert!(state.generate(Signal::SIGKILL));
+    assert_eq!(state.deliverable().first(), Some(Signal::SIGKILL));
+}
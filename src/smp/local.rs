@@ -0,0 +1,68 @@
+use super::{cpu_id, MAX_CPUS};
+
+/// One value per processor, declared with [`cpu_local!`](crate::cpu_local).
+///
+/// [`get`](CpuLocal::get) picks the calling CPU's slot through the GS base,
+/// so it works from `kernel::init` on, before and after the application
+/// processors are started. A value that interrupt handlers change as well
+/// has to be accessed with interrupts disabled or be atomic.
+pub struct CpuLocal<T> {
+    values: [T; MAX_CPUS],
+}
+
+// every slot is only touched by its own processor
+unsafe impl<T: Send> Sync for CpuLocal<T> {}
+
+impl<T> CpuLocal<T> {
+    pub const fn new(values: [T; MAX_CPUS]) -> Self {
+        CpuLocal { values }
+    }
+
+    /// The calling CPU's value.
+    pub fn get(&self) -> &T {
+        &self.values[cpu_id()]
+    }
+}
+
+impl<T: Sync> CpuLocal<T> {
+    /// The value of processor `id`, for statistics gathered across CPUs.
+    pub fn for_cpu(&self, id: usize) -> &T {
+        &self.values[id]
+    }
+}
+
+/// Declares statics with a separate value for every processor:
+///
+/// ```ignore
+/// cpu_local! {
+///     static DEPTH: Cell<usize> = Cell::new(0);
+/// }
+/// DEPTH.get().set(1);
+/// ```
+///
+/// The initializer must be a constant expression.
+#[macro_export]
+macro_rules! cpu_local {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)+) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::smp::CpuLocal<$ty> = {
+                #[allow(clippy::declare_interior_mutable_const)]
+                const INIT: $ty = $init;
+                $crate::smp::CpuLocal::new([INIT; $crate::smp::MAX_CPUS])
+            };
+        )+
+    };
+}
+
+#[test_case]
+fn values_are_per_cpu() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    crate::cpu_local! {
+        static VALUE: AtomicUsize = AtomicUsize::new(0);
+    }
+    VALUE.get().store(7, Ordering::Relaxed);
+    assert_eq!(VALUE.for_cpu(cpu_id()).load(Ordering::Relaxed), 7);
+    assert_eq!(VALUE.for_cpu(1).load(Ordering::Relaxed), 0);
+}
@@ -0,0 +1,419 @@
+//! Bring-up of the application processors (APs) and inter-processor
+//! interrupts.
+//!
+//! Threads only run on the bootstrap processor (BSP) so far. The APs load
+//! their own descriptor tables and per-CPU data, then halt and only take TLB
+//! shootdowns.
+
+mod local;
+mod trampoline;
+
+pub use local::CpuLocal;
+
+use alloc::{boxed::Box, vec};
+use core::{
+    arch::asm,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+};
+use x86_64::{
+    instructions::{port::Port, tlb},
+    registers::{control::Cr3, model_specific::GsBase},
+    structures::paging::PageTableFlags,
+    VirtAddr,
+};
+
+use self::trampoline::Parameters;
+use crate::{
+    acpi,
+    apic::{self, Destination},
+    fpu, gdt, interuptions, memory, syscall,
+    task::timer::{self, TICKS_PER_SECOND},
+};
+
+pub const MAX_CPUS: usize = 16;
+
+const STACK_SIZE: usize = 4096 * 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CpuState {
+    Offline,
+    Starting,
+    Online,
+}
+
+impl CpuState {
+    fn from_u8(value: u8) -> CpuState {
+        match value {
+            1 => CpuState::Starting,
+            2 => CpuState::Online,
+            _ => CpuState::Offline,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CpuState::Offline => "offline",
+            CpuState::Starting => "starting",
+            CpuState::Online => "online",
+        }
+    }
+}
+
+/// Offsets of [`Cpu`] fields read by assembly through the GS base.
+pub(crate) const KERNEL_STACK_OFFSET: usize = 8;
+pub(crate) const USER_STACK_OFFSET: usize = 16;
+
+/// Per-CPU data, reached through the GS base with [`current`].
+#[repr(C)]
+pub struct Cpu {
+    /// Address of this structure, the word `gs:0` points at.
+    this: AtomicU64,
+    /// Top of the running thread's kernel stack, which `syscall` switches to.
+    kernel_stack: AtomicU64,
+    /// User stack pointer, parked here by the `syscall` entry while it
+    /// switches stacks.
+    user_stack: AtomicU64,
+    id: usize,
+    apic_id: AtomicU32,
+    state: AtomicU8,
+    tlb_shootdowns: AtomicU64,
+    reschedules: AtomicU64,
+}
+
+impl Cpu {
+    const fn new(id: usize, apic_id: u32) -> Self {
+        Cpu {
+            this: AtomicU64::new(0),
+            kernel_stack: AtomicU64::new(0),
+            user_stack: AtomicU64::new(0),
+            id,
+            apic_id: AtomicU32::new(apic_id),
+            state: AtomicU8::new(CpuState::Offline as u8),
+            tlb_shootdowns: AtomicU64::new(0),
+            reschedules: AtomicU64::new(0),
+        }
+    }
+
+    /// Index in [`cpus`], 0 for the bootstrap processor.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn apic_id(&self) -> u32 {
+        self.apic_id.load(Ordering::Relaxed)
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState::from_u8(self.state.load(Ordering::Acquire))
+    }
+
+    fn set_state(&self, state: CpuState) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    pub fn tlb_shootdowns(&self) -> u64 {
+        self.tlb_shootdowns.load(Ordering::Relaxed)
+    }
+
+    pub fn reschedules(&self) -> u64 {
+        self.reschedules.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_kernel_stack(&self, stack_top: VirtAddr) {
+        self.kernel_stack
+            .store(stack_top.as_u64(), Ordering::Relaxed);
+    }
+}
+
+const _: () = assert!(core::mem::offset_of!(Cpu, kernel_stack) == KERNEL_STACK_OFFSET);
+const _: () = assert!(core::mem::offset_of!(Cpu, user_stack) == USER_STACK_OFFSET);
+
+static BSP: Cpu = Cpu::new(0, 0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_CPU: AtomicPtr<Cpu> = AtomicPtr::new(ptr::null_mut());
+static CPUS: [AtomicPtr<Cpu>; MAX_CPUS] = [NO_CPU; MAX_CPUS];
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn register(cpu: &'static Cpu) {
+    let addr = cpu as *const Cpu;
+    cpu.this.store(addr as u64, Ordering::Relaxed);
+    CPUS[cpu.id].store(addr as *mut Cpu, Ordering::Release);
+    CPU_COUNT.fetch_max(cpu.id + 1, Ordering::AcqRel);
+}
+
+/// Makes [`current`] and [`CpuLocal`] work on the bootstrap processor. Runs
+/// first in `kernel::init`.
+pub fn init_bsp() {
+    register(&BSP);
+    BSP.set_state(CpuState::Online);
+    GsBase::write(VirtAddr::from_ptr(&BSP));
+}
+
+/// Per-CPU data of the calling processor.
+pub fn current() -> &'static Cpu {
+    let cpu: *const Cpu;
+    unsafe {
+        asm!(
+            "mov {}, gs:[0]",
+            out(reg) cpu,
+            options(nostack, readonly, preserves_flags)
+        );
+        &*cpu
+    }
+}
+
+/// Index of the calling processor, 0 for the bootstrap processor.
+pub fn cpu_id() -> usize {
+    current().id
+}
+
+/// Every processor that was found, whether it came online or not.
+pub fn cpus() -> impl Iterator<Item = &'static Cpu> {
+    CPUS[..CPU_COUNT.load(Ordering::Acquire)]
+        .iter()
+        .filter_map(|cpu| unsafe { cpu.load(Ordering::Acquire).as_ref() })
+}
+
+pub fn online_count() -> usize {
+    cpus().filter(|cpu| cpu.state() == CpuState::Online).count()
+}
+
+/// Starts every application processor listed by ACPI. Needs the heap, the
+/// frame allocator and the timer interrupt.
+pub fn init() {
+    apic::init();
+    let bsp_apic_id = apic::id();
+    BSP.apic_id.store(bsp_apic_id, Ordering::Relaxed);
+
+    let processors = acpi::processors();
+    if processors.len() <= 1 {
+        log::info!("running on the bootstrap processor only");
+        return;
+    }
+
+    let frame = unsafe { trampoline::install() };
+    // paging is switched on while executing from this page
+    let mapped =
+        match memory::identity_map(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE) {
+            Ok(mapped) => mapped,
+            Err(err) => {
+                log::error!("cannot map the SMP trampoline: {:?}", err);
+                return;
+            }
+        };
+
+    for processor in processors.iter().filter(|p| p.apic_id != bsp_apic_id) {
+        let id = CPU_COUNT.load(Ordering::Acquire);
+        if id == MAX_CPUS {
+            log::warn!("ignoring processors beyond the first {}", MAX_CPUS);
+            break;
+        }
+        start(id, processor.apic_id);
+    }
+    // every processor is either past the trampoline or parked by INIT, so
+    // low memory need not stay mapped
+    if mapped {
+        match memory::identity_unmap(frame) {
+            Ok(()) => tlb_shootdown(Some(VirtAddr::new(frame.start_address().as_u64()))),
+            Err(err) => log::warn!("cannot unmap the SMP trampoline: {:?}", err),
+        }
+    }
+    log::info!(
+        "{} of {} processors online",
+        online_count(),
+        processors.len()
+    );
+}
+
+/// INIT-SIPI-SIPI sequence for one processor, waiting up to a second for it
+/// to come online.
+fn start(id: usize, apic_id: u32) {
+    let cpu: &'static Cpu = Box::leak(Box::new(Cpu::new(id, apic_id)));
+    register(cpu);
+    cpu.set_state(CpuState::Starting);
+
+    let stack = Box::leak(vec![0u8; STACK_SIZE].into_boxed_slice());
+    trampoline::set_parameters(&Parameters {
+        page_table: Cr3::read().0,
+        stack_top: (stack.as_ptr() as u64 + STACK_SIZE as u64) & !0xF,
+        entry: ap_main as *const () as u64,
+        arg: cpu as *const Cpu as u64,
+    });
+
+    apic::send_init(apic_id);
+    // at least 10 ms
+    wait_ticks(2);
+    for _ in 0..2 {
+        apic::send_startup(apic_id, trampoline::STARTUP_PAGE);
+        delay_us(200);
+        if cpu.state() == CpuState::Online {
+            break;
+        }
+    }
+
+    let deadline = timer::ticks() + TICKS_PER_SECOND;
+    while cpu.state() != CpuState::Online && timer::ticks() < deadline {
+        core::hint::spin_loop();
+    }
+    // a late processor sees this and halts instead of coming online
+    if cpu
+        .state
+        .compare_exchange(
+            CpuState::Starting as u8,
+            CpuState::Offline as u8,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .is_ok()
+    {
+        // back to waiting for a startup IPI, it must not reach the
+        // trampoline once that is unmapped
+        apic::send_init(apic_id);
+        log::warn!("CPU {} (APIC id {}) did not start", id, apic_id);
+    }
+}
+
+fn wait_ticks(ticks: u64) {
+    let until = timer::ticks() + ticks;
+    while timer::ticks() < until {
+        core::hint::spin_loop();
+    }
+}
+
+/// Busy waits roughly `us` microseconds, one port write each.
+fn delay_us(us: u32) {
+    let mut port: Port<u8> = Port::new(0x80);
+    for _ in 0..us {
+        unsafe { port.write(0) };
+    }
+}
+
+/// Called by the trampoline on the new processor's own stack.
+extern "C" fn ap_main(cpu: *const Cpu) -> ! {
+    let cpu = unsafe { &*cpu };
+    GsBase::write(VirtAddr::from_ptr(cpu));
+    gdt::init_ap();
+    fpu::init();
+    interuptions::init_idt();
+    syscall::init_cpu();
+    apic::init();
+
+    let started = cpu.state.compare_exchange(
+        CpuState::Starting as u8,
+        CpuState::Online as u8,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    );
+    if started.is_ok() {
+        x86_64::instructions::interrupts::enable();
+    }
+    crate::hlt_loop()
+}
+
+/// Taken by the CPU that sends a shootdown until every target has flushed.
+static SHOOTDOWN_LOCK: AtomicBool = AtomicBool::new(false);
+/// Page to flush, 0 for the whole TLB.
+static SHOOTDOWN_ADDRESS: AtomicU64 = AtomicU64::new(0);
+/// CPUs that still have to flush, one bit per id.
+static SHOOTDOWN_PENDING: AtomicU64 = AtomicU64::new(0);
+
+fn flush(address: u64) {
+    match address {
+        0 => tlb::flush_all(),
+        address => tlb::flush(VirtAddr::new(address)),
+    }
+}
+
+/// Flushes `page`, or the whole TLB for `None`, on every online CPU and
+/// waits until all of them have done so.
+///
+/// Must not be called while holding a lock that another CPU may spin on
+/// with interrupts disabled, it would never see the IPI.
+pub fn tlb_shootdown(page: Option<VirtAddr>) {
+    let address = page.map_or(0, |page| page.align_down(4096u64).as_u64());
+    flush(address);
+
+    let me = cpu_id();
+    let targets = cpus()
+        .filter(|cpu| cpu.id != me && cpu.state() == CpuState::Online)
+        .fold(0u64, |mask, cpu| mask | 1 << cpu.id);
+    if targets == 0 {
+        return;
+    }
+
+    // answer the requests of other CPUs while waiting, two of them shooting
+    // down at the same time would deadlock otherwise
+    while SHOOTDOWN_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        service_shootdown();
+        core::hint::spin_loop();
+    }
+    SHOOTDOWN_ADDRESS.store(address, Ordering::Relaxed);
+    SHOOTDOWN_PENDING.store(targets, Ordering::Release);
+    for cpu in cpus().filter(|cpu| targets & 1 << cpu.id != 0) {
+        apic::send_ipi(
+            Destination::Apic(cpu.apic_id()),
+            interuptions::TLB_SHOOTDOWN_VECTOR,
+        );
+    }
+    while SHOOTDOWN_PENDING.load(Ordering::Acquire) != 0 {
+        core::hint::spin_loop();
+    }
+    SHOOTDOWN_LOCK.store(false, Ordering::Release);
+}
+
+fn service_shootdown() {
+    let cpu = current();
+    let bit = 1 << cpu.id;
+    if SHOOTDOWN_PENDING.load(Ordering::Acquire) & bit == 0 {
+        return;
+    }
+    flush(SHOOTDOWN_ADDRESS.load(Ordering::Relaxed));
+    cpu.tlb_shootdowns.fetch_add(1, Ordering::Relaxed);
+    SHOOTDOWN_PENDING.fetch_and(!bit, Ordering::Release);
+}
+
+pub(crate) fn handle_tlb_shootdown() {
+    service_shootdown();
+    apic::end_of_interrupt();
+}
+
+/// Asks the BSP to run its scheduler. The APs run no threads, so there is
+/// nothing for them to reschedule.
+pub fn send_reschedule() {
+    apic::send_ipi(
+        Destination::Apic(BSP.apic_id()),
+        interuptions::RESCHEDULE_VECTOR,
+    );
+}
+
+pub(crate) fn handle_reschedule() {
+    current().reschedules.fetch_add(1, Ordering::Relaxed);
+    apic::end_of_interrupt();
+}
+
+/// Set once [`halt_others`] was called; every NMI then stops its CPU.
+static HALTING: AtomicBool = AtomicBool::new(false);
+
+/// Stops every other CPU for good, so the panic screen has the machine to
+/// itself. Sent as an NMI to also reach CPUs spinning with interrupts
+/// disabled.
+pub fn halt_others() {
+    if online_count() < 2 {
+        return;
+    }
+    HALTING.store(true, Ordering::SeqCst);
+    apic::send_nmi(Destination::AllButSelf);
+}
+
+pub(crate) fn handle_nmi() {
+    if HALTING.load(Ordering::SeqCst) {
+        x86_64::instructions::interrupts::disable();
+        crate::hlt_loop();
+    }
+}
@@ -0,0 +1,146 @@
+use core::ptr::addr_of;
+use x86_64::{structures::paging::PhysFrame, PhysAddr};
+
+/// Physical address the trampoline is copied to. Application processors
+/// start in real mode at `STARTUP_PAGE * 4096`.
+pub(super) const TRAMPOLINE: u64 = 0x8000;
+pub(super) const STARTUP_PAGE: u8 = (TRAMPOLINE >> 12) as u8;
+
+// Real mode -> protected mode -> long mode, then a call to the entry point
+// with the argument in rdi. The code is only ever run from its copy at
+// `TRAMPOLINE`, so every address is computed relative to that. The
+// parameters at the end are filled in before each processor is started.
+core::arch::global_asm!(
+    ".pushsection .text.ap_trampoline, \"ax\"",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_end",
+    ".global ap_trampoline_cr3",
+    ".global ap_trampoline_stack",
+    ".global ap_trampoline_entry",
+    ".global ap_trampoline_arg",
+    ".code16",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    "xorw %ax, %ax",
+    "movw %ax, %ds",
+    "lgdtl ap_trampoline_gdt_ptr - ap_trampoline_start + {base}",
+    "movl %cr0, %eax",
+    "orl $1, %eax",
+    "movl %eax, %cr0",
+    "ljmpl $0x08, $(ap_trampoline_32 - ap_trampoline_start + {base})",
+    ".code32",
+    "ap_trampoline_32:",
+    "movw $0x10, %ax",
+    "movw %ax, %ds",
+    "movw %ax, %es",
+    "movw %ax, %ss",
+    // PAE
+    "movl %cr4, %eax",
+    "orl $0x20, %eax",
+    "movl %eax, %cr4",
+    "movl ap_trampoline_cr3 - ap_trampoline_start + {base}, %eax",
+    "movl %eax, %cr3",
+    // EFER: syscall, long mode, no-execute
+    "movl $0xC0000080, %ecx",
+    "rdmsr",
+    "orl $0x901, %eax",
+    "wrmsr",
+    // paging, write protect, protected mode
+    "movl %cr0, %eax",
+    "orl $0x80010001, %eax",
+    "movl %eax, %cr0",
+    "ljmpl $0x18, $(ap_trampoline_64 - ap_trampoline_start + {base})",
+    ".code64",
+    "ap_trampoline_64:",
+    "movw $0x10, %ax",
+    "movw %ax, %ds",
+    "movw %ax, %es",
+    "movw %ax, %ss",
+    "movq ap_trampoline_stack - ap_trampoline_start + {base}, %rsp",
+    "movq ap_trampoline_arg - ap_trampoline_start + {base}, %rdi",
+    "movq ap_trampoline_entry - ap_trampoline_start + {base}, %rax",
+    "xorq %rbp, %rbp",
+    "callq *%rax",
+    "2:",
+    "hlt",
+    "jmp 2b",
+    ".balign 8",
+    "ap_trampoline_gdt:",
+    ".quad 0",
+    // 32-bit code, 32-bit data, 64-bit code
+    ".quad 0x00CF9A000000FFFF",
+    ".quad 0x00CF92000000FFFF",
+    ".quad 0x00AF9A000000FFFF",
+    "ap_trampoline_gdt_ptr:",
+    ".word ap_trampoline_gdt_ptr - ap_trampoline_gdt - 1",
+    ".long ap_trampoline_gdt - ap_trampoline_start + {base}",
+    ".balign 8",
+    "ap_trampoline_cr3: .quad 0",
+    "ap_trampoline_stack: .quad 0",
+    "ap_trampoline_entry: .quad 0",
+    "ap_trampoline_arg: .quad 0",
+    "ap_trampoline_end:",
+    ".popsection",
+    base = const TRAMPOLINE,
+    options(att_syntax)
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_trampoline_cr3: u8;
+    static ap_trampoline_stack: u8;
+    static ap_trampoline_entry: u8;
+    static ap_trampoline_arg: u8;
+}
+
+/// Values the trampoline hands to the starting processor.
+pub(super) struct Parameters {
+    /// Level 4 table, must lie below 4 GiB.
+    pub page_table: PhysFrame,
+    pub stack_top: u64,
+    pub entry: u64,
+    pub arg: u64,
+}
+
+fn start() -> usize {
+    addr_of!(ap_trampoline_start) as usize
+}
+
+fn len() -> usize {
+    addr_of!(ap_trampoline_end) as usize - start()
+}
+
+fn target() -> *mut u8 {
+    (crate::memory::physical_memory_offset() + TRAMPOLINE).as_mut_ptr()
+}
+
+/// Copies the trampoline to [`TRAMPOLINE`].
+///
+/// # Safety
+///
+/// The frame at `TRAMPOLINE` must not be used by anything else.
+pub(super) unsafe fn install() -> PhysFrame {
+    core::ptr::copy_nonoverlapping(start() as *const u8, target(), len());
+    PhysFrame::containing_address(PhysAddr::new(TRAMPOLINE))
+}
+
+/// Sets the parameters of the installed trampoline for the next startup.
+pub(super) fn set_parameters(parameters: &Parameters) {
+    let cr3 = parameters.page_table.start_address().as_u64();
+    assert!(
+        cr3 < 1 << 32,
+        "level 4 table out of reach of the trampoline"
+    );
+    let fields = [
+        (addr_of!(ap_trampoline_cr3), cr3),
+        (addr_of!(ap_trampoline_stack), parameters.stack_top),
+        (addr_of!(ap_trampoline_entry), parameters.entry),
+        (addr_of!(ap_trampoline_arg), parameters.arg),
+    ];
+    for (symbol, value) in fields {
+        let offset = symbol as usize - start();
+        unsafe { core::ptr::write_volatile(target().add(offset) as *mut u64, value) };
+    }
+}
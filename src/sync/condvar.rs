@@ -0,0 +1,49 @@
+use super::{MutexGuard, WaitQueue};
+
+/// A condition variable for use with [`Mutex`](super::Mutex).
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Releases `guard`, parks until notified and locks the mutex again.
+    /// Wakeups can be spurious, so callers check their condition in a loop.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        // queued before the unlock, so a notify right after it is not lost
+        self.waiters.wait_after(|| drop(guard));
+        mutex.lock()
+    }
+
+    /// Waits until `condition` holds for the protected value.
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,296 @@
+//! Lock dependency validator, built with the `lockdep` feature.
+//!
+//! Every named lock is a class. Acquisitions are recorded per thread (and
+//! separately for interrupt handlers), and each lock taken while another is
+//! held adds an edge to the class order graph. Reports go straight to COM1
+//! without taking any lock:
+//!
+//! - a class taken while it is already held (recursion)
+//! - an edge closing a cycle, the classic ABBA deadlock
+//! - a class used in interrupt handlers that is also held with interrupts
+//!   enabled elsewhere
+//! - a sleeping lock taken in an interrupt handler
+//!
+//! Without the feature all hooks compile to nothing.
+
+#[cfg(feature = "lockdep")]
+pub use enabled::*;
+
+/// Whether a lock spins with interrupts off or parks the thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Spin,
+    Sleep,
+}
+
+#[cfg(not(feature = "lockdep"))]
+#[inline(always)]
+pub fn acquire(_class: Option<&'static str>, _kind: LockKind, _irqs_enabled: bool) {}
+
+#[cfg(not(feature = "lockdep"))]
+#[inline(always)]
+pub fn release(_class: Option<&'static str>) {}
+
+#[cfg(feature = "lockdep")]
+mod enabled {
+    use core::fmt::{self, Write};
+    use x86_64::instructions::interrupts;
+
+    use super::LockKind;
+    use crate::{interuptions, serial::uart, smp, thread};
+
+    const MAX_CLASSES: usize = 64;
+    const MAX_DEPTH: usize = 16;
+    const MAX_CONTEXTS: usize = 32;
+    /// Held-lock stack shared by the interrupt handlers of CPU 0, the other
+    /// CPUs count down from here.
+    const IRQ_CONTEXT: u64 = u64::MAX;
+
+    #[derive(Clone, Copy)]
+    struct HeldStack {
+        owner: Option<u64>,
+        classes: [u8; MAX_DEPTH],
+        depth: usize,
+    }
+
+    impl HeldStack {
+        const EMPTY: HeldStack = HeldStack {
+            owner: None,
+            classes: [0; MAX_DEPTH],
+            depth: 0,
+        };
+
+        fn held(&self) -> &[u8] {
+            &self.classes[..self.depth]
+        }
+    }
+
+    /// Fixed size so locks can be checked before the heap exists.
+    struct State {
+        names: [Option<&'static str>; MAX_CLASSES],
+        /// Bit `b` of `after[a]` is set once `b` was taken while `a` was held.
+        after: [u64; MAX_CLASSES],
+        /// Ordering violations already reported, same layout as `after`.
+        reported: [u64; MAX_CLASSES],
+        used_in_irq: u64,
+        /// Held with interrupts enabled outside interrupt handlers.
+        held_irqs_enabled: u64,
+        irq_reported: u64,
+        stacks: [HeldStack; MAX_CONTEXTS],
+        reports: usize,
+    }
+
+    // a plain spinlock: lockdep must not validate itself
+    static STATE: spin::Mutex<State> = spin::Mutex::new(State {
+        names: [None; MAX_CLASSES],
+        after: [0; MAX_CLASSES],
+        reported: [0; MAX_CLASSES],
+        used_in_irq: 0,
+        held_irqs_enabled: 0,
+        irq_reported: 0,
+        stacks: [HeldStack::EMPTY; MAX_CONTEXTS],
+        reports: 0,
+    });
+
+    struct Console;
+
+    impl Write for Console {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            uart::write_unlocked(0, s.as_bytes());
+            Ok(())
+        }
+    }
+
+    impl State {
+        fn class_id(&mut self, name: &'static str) -> Option<usize> {
+            if let Some(id) = self.names.iter().position(|n| *n == Some(name)) {
+                return Some(id);
+            }
+            let id = self.names.iter().position(Option::is_none)?;
+            self.names[id] = Some(name);
+            Some(id)
+        }
+
+        fn name(&self, id: u8) -> &'static str {
+            self.names[usize::from(id)].unwrap_or("?")
+        }
+
+        fn stack_index(&mut self, owner: u64, create: bool) -> Option<usize> {
+            if let Some(index) = self.stacks.iter().position(|s| s.owner == Some(owner)) {
+                return Some(index);
+            }
+            if !create {
+                return None;
+            }
+            let index = self.stacks.iter().position(|s| s.owner.is_none())?;
+            self.stacks[index].owner = Some(owner);
+            Some(index)
+        }
+
+        /// Whether `to` was ever taken, directly or indirectly, after `from`.
+        fn reaches(&self, from: usize, to: usize) -> bool {
+            let mut visited = 0u64;
+            let mut pending = 1u64 << from;
+            while pending != 0 {
+                let class = pending.trailing_zeros() as usize;
+                pending &= !(1 << class);
+                if class == to {
+                    return true;
+                }
+                visited |= 1 << class;
+                pending |= self.after[class] & !visited;
+            }
+            false
+        }
+
+        fn report(&mut self, stack: usize, args: fmt::Arguments) {
+            self.reports += 1;
+            let _ = write!(Console, "\nlockdep: {}\nlockdep: held:", args);
+            for class in self.stacks[stack].held() {
+                let _ = write!(Console, " {}", self.name(*class));
+            }
+            let _ = writeln!(Console, " (thread {})", thread::current_id());
+        }
+    }
+
+    /// Owner of the held-lock stack the caller uses.
+    fn context(in_irq: bool) -> u64 {
+        if in_irq {
+            IRQ_CONTEXT - smp::cpu_id() as u64
+        } else {
+            thread::current_id()
+        }
+    }
+
+    /// Records that the lock `class` is about to be taken. Called before
+    /// spinning or sleeping so the report comes out ahead of a hang.
+    pub fn acquire(class: Option<&'static str>, kind: LockKind, irqs_enabled: bool) {
+        let name = match class {
+            Some(name) => name,
+            None => return,
+        };
+        interrupts::without_interrupts(|| {
+            let mut state = STATE.lock();
+            let class = match state.class_id(name) {
+                Some(class) => class,
+                None => return,
+            };
+            let bit = 1u64 << class;
+            let in_irq = interuptions::in_interrupt();
+            let owner = context(in_irq);
+            let stack = match state.stack_index(owner, true) {
+                Some(stack) => stack,
+                None => return,
+            };
+
+            if in_irq && kind == LockKind::Sleep {
+                state.report(
+                    stack,
+                    format_args!("sleeping lock {} taken in interrupt context", name),
+                );
+            }
+
+            let held = state.stacks[stack];
+            if held.held().contains(&(class as u8)) {
+                state.report(stack, format_args!("recursive locking of {}", name));
+            }
+            for &other in held.held() {
+                let other = usize::from(other);
+                if other == class {
+                    continue;
+                }
+                if state.reaches(class, other) && state.reported[other] & bit == 0 {
+                    state.reported[other] |= bit;
+                    let other_name = state.name(other as u8);
+                    state.report(
+                        stack,
+                        format_args!(
+                            "possible deadlock: {} taken while holding {}, \
+                             but {} was taken while holding {} before",
+                            name, other_name, other_name, name
+                        ),
+                    );
+                }
+                state.after[other] |= bit;
+            }
+
+            if in_irq {
+                state.used_in_irq |= bit;
+            } else if irqs_enabled {
+                state.held_irqs_enabled |= bit;
+            }
+            if state.used_in_irq & state.held_irqs_enabled & !state.irq_reported & bit != 0 {
+                state.irq_reported |= bit;
+                state.report(
+                    stack,
+                    format_args!(
+                        "{} is taken in interrupt handlers and held with interrupts enabled",
+                        name
+                    ),
+                );
+            }
+
+            let held = &mut state.stacks[stack];
+            if held.depth < MAX_DEPTH {
+                held.classes[held.depth] = class as u8;
+                held.depth += 1;
+            }
+        });
+    }
+
+    /// Records that the lock `class` was released.
+    pub fn release(class: Option<&'static str>) {
+        let name = match class {
+            Some(name) => name,
+            None => return,
+        };
+        interrupts::without_interrupts(|| {
+            let mut state = STATE.lock();
+            let class = match state.names.iter().position(|n| *n == Some(name)) {
+                Some(class) => class as u8,
+                None => return,
+            };
+            let owner = context(interuptions::in_interrupt());
+            let stack = match state.stack_index(owner, false) {
+                Some(stack) => stack,
+                None => return,
+            };
+            let held = &mut state.stacks[stack];
+            // locks need not be released in order
+            if let Some(position) = held.held().iter().rposition(|c| *c == class) {
+                held.classes.copy_within(position + 1..held.depth, position);
+                held.depth -= 1;
+            }
+            if held.depth == 0 {
+                held.owner = None;
+            }
+        });
+    }
+
+    /// Number of problems reported so far.
+    pub fn report_count() -> usize {
+        interrupts::without_interrupts(|| STATE.lock().reports)
+    }
+
+    #[test_case]
+    fn test_detects_abba_and_recursion() {
+        let before = report_count();
+        acquire(Some("test::a"), LockKind::Spin, false);
+        acquire(Some("test::b"), LockKind::Spin, false);
+        release(Some("test::b"));
+        release(Some("test::a"));
+        assert_eq!(report_count(), before);
+
+        acquire(Some("test::b"), LockKind::Spin, false);
+        acquire(Some("test::a"), LockKind::Spin, false);
+        release(Some("test::a"));
+        release(Some("test::b"));
+        assert_eq!(report_count(), before + 1);
+
+        acquire(Some("test::a"), LockKind::Spin, false);
+        acquire(Some("test::a"), LockKind::Spin, false);
+        release(Some("test::a"));
+        release(Some("test::a"));
+        assert_eq!(report_count(), before + 2);
+    }
+}
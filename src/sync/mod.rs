@@ -0,0 +1,18 @@
+//! Locks and blocking primitives.
+//!
+//! [`IrqSpinlock`] is for data shared with interrupt handlers. Everything
+//! else here parks the calling thread on a [`WaitQueue`] instead of spinning
+//! once the scheduler runs, and must not be used from interrupt context.
+
+mod condvar;
+pub mod lockdep;
+mod mutex;
+mod semaphore;
+mod spinlock;
+mod wait_queue;
+
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexGuard};
+pub use semaphore::Semaphore;
+pub use spinlock::{IrqSpinlock, IrqSpinlockGuard};
+pub use wait_queue::WaitQueue;
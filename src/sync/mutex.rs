@@ -0,0 +1,123 @@
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{
+    lockdep::{self, LockKind},
+    WaitQueue,
+};
+use x86_64::instructions::interrupts;
+
+/// A mutex that parks waiting threads instead of spinning. Not usable from
+/// interrupt handlers.
+pub struct Mutex<T: ?Sized> {
+    /// Lock class for [`lockdep`]; unnamed locks are not tracked.
+    class: Option<&'static str>,
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Mutex {
+            class: None,
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// A mutex checked by [`lockdep`] under the class `class`.
+    pub const fn named(class: &'static str, value: T) -> Self {
+        Mutex {
+            class: Some(class),
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        lockdep::acquire(self.class, LockKind::Sleep, interrupts::are_enabled());
+        loop {
+            if let Some(guard) = self.try_acquire() {
+                return guard;
+            }
+            self.waiters
+                .wait_until(|| !self.locked.load(Ordering::Relaxed));
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let guard = self.try_acquire()?;
+        lockdep::acquire(self.class, LockKind::Sleep, interrupts::are_enabled());
+        Some(guard)
+    }
+
+    fn try_acquire(&self) -> Option<MutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    fn unlock(&self) {
+        lockdep::release(self.class);
+        self.locked.store(false, Ordering::Release);
+        self.waiters.wake_one();
+    }
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    pub(super) fn mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+#[test_case]
+fn test_lock_excludes_second_holder() {
+    let mutex = Mutex::new(1);
+    {
+        let mut guard = mutex.lock();
+        *guard += 1;
+        assert!(mutex.try_lock().is_none());
+    }
+    assert_eq!(*mutex.lock(), 2);
+    assert!(!mutex.is_locked());
+}
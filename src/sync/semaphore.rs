@@ -0,0 +1,53 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::WaitQueue;
+
+/// A counting semaphore.
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Semaphore {
+            count: AtomicUsize::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Takes a unit, parking until one is available.
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            self.waiters
+                .wait_until(|| self.count.load(Ordering::Relaxed) > 0);
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    pub fn available(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+#[test_case]
+fn test_counts_units() {
+    let semaphore = Semaphore::new(2);
+    semaphore.acquire();
+    assert!(semaphore.try_acquire());
+    assert!(!semaphore.try_acquire());
+    semaphore.release();
+    assert_eq!(semaphore.available(), 1);
+}
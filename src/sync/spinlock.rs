@@ -0,0 +1,129 @@
+use core::{
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+use x86_64::instructions::interrupts;
+
+use super::lockdep::{self, LockKind};
+
+/// A spinlock that keeps interrupts disabled while it is held, so an
+/// interrupt handler taking the same lock can never spin on a holder it
+/// interrupted.
+pub struct IrqSpinlock<T: ?Sized> {
+    /// Lock class for [`lockdep`]; unnamed locks are not tracked.
+    class: Option<&'static str>,
+    inner: spin::Mutex<T>,
+}
+
+pub struct IrqSpinlockGuard<'a, T: ?Sized> {
+    class: Option<&'static str>,
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    /// Interrupts are re-enabled on release only if they were on before.
+    interrupts_enabled: bool,
+}
+
+impl<T> IrqSpinlock<T> {
+    pub const fn new(value: T) -> Self {
+        IrqSpinlock {
+            class: None,
+            inner: spin::Mutex::new(value),
+        }
+    }
+
+    /// A lock checked by [`lockdep`] under the class `class`.
+    pub const fn named(class: &'static str, value: T) -> Self {
+        IrqSpinlock {
+            class: Some(class),
+            inner: spin::Mutex::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> IrqSpinlock<T> {
+    pub fn lock(&self) -> IrqSpinlockGuard<'_, T> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        lockdep::acquire(self.class, LockKind::Spin, interrupts::are_enabled());
+        IrqSpinlockGuard {
+            class: self.class,
+            guard: ManuallyDrop::new(self.inner.lock()),
+            interrupts_enabled,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqSpinlockGuard<'_, T>> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => {
+                lockdep::acquire(self.class, LockKind::Spin, interrupts::are_enabled());
+                Some(IrqSpinlockGuard {
+                    class: self.class,
+                    guard: ManuallyDrop::new(guard),
+                    interrupts_enabled,
+                })
+            }
+            None => {
+                if interrupts_enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    /// Releases the lock without a guard.
+    ///
+    /// # Safety
+    ///
+    /// Only for paths that will never return to the holder, like the panic
+    /// screen.
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock();
+    }
+}
+
+impl<T: ?Sized> Deref for IrqSpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinlockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for IrqSpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        // unlock before interrupts come back on
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        lockdep::release(self.class);
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+#[test_case]
+fn test_restores_interrupt_flag() {
+    let lock = IrqSpinlock::new(0);
+    assert!(interrupts::are_enabled());
+    {
+        let mut value = lock.lock();
+        *value += 1;
+        assert!(!interrupts::are_enabled());
+        assert!(lock.try_lock().is_none());
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+
+    interrupts::without_interrupts(|| {
+        drop(lock.lock());
+        assert!(!interrupts::are_enabled());
+    });
+    assert_eq!(*lock.lock(), 1);
+}
@@ -0,0 +1,121 @@
+use alloc::collections::VecDeque;
+
+use super::IrqSpinlock;
+use crate::thread::{self, ThreadId};
+
+/// Threads parked until some condition becomes true.
+///
+/// Conditions are checked with the queue lock held, so a waker that changes
+/// the state before calling [`wake_one`](WaitQueue::wake_one) or
+/// [`wake_all`](WaitQueue::wake_all) can never be missed. Before the
+/// scheduler runs, waiting falls back to spinning.
+///
+/// Every entry carries the token of the wait it was queued for, see
+/// [`thread::block_current`]. A thread woken some other way takes its entry
+/// out again, and a leftover entry cannot end a later wait of the same
+/// thread.
+pub struct WaitQueue {
+    waiters: IrqSpinlock<VecDeque<Waiter>>,
+}
+
+type Waiter = (ThreadId, u64);
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: IrqSpinlock::new(VecDeque::new()),
+        }
+    }
+
+    /// Parks the current thread until `condition` returns true. Spurious
+    /// wakeups are handled by checking again.
+    ///
+    /// `condition` runs with interrupts disabled, so any lock it takes must be
+    /// an [`IrqSpinlock`].
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        let mut queued = None;
+        loop {
+            {
+                let mut waiters = self.waiters.lock();
+                if let Some(waiter) = queued.take() {
+                    waiters.retain(|entry| *entry != waiter);
+                }
+                if condition() {
+                    return;
+                }
+                if thread::is_running() {
+                    let waiter = (thread::current(), thread::block_current());
+                    waiters.push_back(waiter);
+                    queued = Some(waiter);
+                }
+            }
+            // blocked threads only return from here once woken
+            if thread::is_running() {
+                thread::yield_now();
+            } else {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Like [`wait_until`](WaitQueue::wait_until), running `release` after
+    /// the current thread has been queued but before it sleeps. Used to drop
+    /// a lock without missing a wakeup sent right after.
+    pub fn wait_after(&self, release: impl FnOnce()) {
+        if !thread::is_running() {
+            release();
+            return;
+        }
+        let waiter = {
+            let mut waiters = self.waiters.lock();
+            let waiter = (thread::current(), thread::block_current());
+            waiters.push_back(waiter);
+            waiter
+        };
+        release();
+        thread::yield_now();
+        self.waiters.lock().retain(|entry| *entry != waiter);
+    }
+
+    /// Wakes the longest waiting thread. Returns whether there was one.
+    /// Entries of threads that stopped waiting are dropped on the way.
+    pub fn wake_one(&self) -> bool {
+        loop {
+            let next = self.waiters.lock().pop_front();
+            match next {
+                Some((id, token)) => {
+                    if thread::unblock(id, token) {
+                        return true;
+                    }
+                }
+                None => return false,
+            }
+        }
+    }
+
+    pub fn wake_all(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        for (id, token) in waiters {
+            thread::unblock(id, token);
+        }
+    }
+
+    /// Like [`wake_all`](WaitQueue::wake_all) for threads waiting on user
+    /// input, which also get the interactive boost.
+    pub fn wake_all_interactive(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        for (id, token) in waiters {
+            thread::unblock_interactive(id, token);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().is_empty()
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
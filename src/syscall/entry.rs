@@ -0,0 +1,90 @@
+use core::sync::atomic::AtomicU64;
+
+use crate::{
+    smp,
+    thread::{self, context::InterruptContext},
+};
+
+pub(super) static USER_CODE: AtomicU64 = AtomicU64::new(0);
+pub(super) static USER_DATA: AtomicU64 = AtomicU64::new(0);
+
+// `syscall` leaves the user rip in rcx and rflags in r11 and does not switch
+// stacks. Build the frame an interrupt from ring 3 would have pushed so both
+// entry paths share `InterruptContext` and return with `iretq`.
+//
+// Unlike an interrupt, `syscall` only ever comes from ring 3, so GS always
+// needs swapping. The stacks live in the per-CPU data it then points at, see
+// `smp::Cpu`; interrupts stay masked until the user stack has been pushed.
+core::arch::global_asm!(
+    ".global syscall_entry",
+    "syscall_entry:",
+    "swapgs",
+    "mov gs:[{user_stack}], rsp",
+    "mov rsp, gs:[{kernel_stack}]",
+    "push qword ptr [rip + {user_data}]",
+    "push qword ptr gs:[{user_stack}]",
+    "push r11",
+    "push qword ptr [rip + {user_code}]",
+    "push rcx",
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov rdi, rsp",
+    "call {handler}",
+    "mov rsp, rax",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    "swapgs",
+    "iretq",
+    user_stack = const smp::USER_STACK_OFFSET,
+    kernel_stack = const smp::KERNEL_STACK_OFFSET,
+    user_data = sym USER_DATA,
+    user_code = sym USER_CODE,
+    handler = sym syscall_dispatch,
+);
+
+crate::context_switch_stub!(int80_stub, syscall_dispatch);
+
+extern "C" {
+    pub(super) fn syscall_entry();
+}
+
+pub(super) fn int80_entry() -> u64 {
+    int80_stub as *const () as u64
+}
+
+extern "C" fn syscall_dispatch(context: *mut InterruptContext) -> *mut InterruptContext {
+    let context_ref = unsafe { &mut *context };
+    // both entry paths mask interrupts, but calls may block
+    x86_64::instructions::interrupts::enable();
+    context_ref.regs.rax = super::dispatch(context_ref);
+    // a handler is entered with the result saved for `sigreturn`
+    thread::handle_signals(context_ref);
+    x86_64::instructions::interrupts::disable();
+    context
+}
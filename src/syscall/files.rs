@@ -0,0 +1,62 @@
+use alloc::{collections::BTreeMap, string::String};
+
+use super::{Errno, STDERR};
+
+/// Files a single program can have open at once.
+pub const MAX_FILES: usize = 64;
+
+/// A file opened with `open`. Files are looked up by path, relative to the
+/// current directory, on every access.
+#[derive(Clone)]
+pub struct OpenFile {
+    pub name: String,
+    pub offset: usize,
+}
+
+/// The files a thread has open, by descriptor. Each thread has its own, so
+/// descriptors of other programs cannot be reached.
+#[derive(Clone, Default)]
+pub struct FileTable {
+    files: BTreeMap<u64, OpenFile>,
+}
+
+impl FileTable {
+    pub const fn new() -> Self {
+        FileTable {
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `file` under the lowest free descriptor after the standard
+    /// streams.
+    pub fn insert(&mut self, file: OpenFile) -> Result<u64, Errno> {
+        if self.files.len() >= MAX_FILES {
+            return Err(Errno::EMFILE);
+        }
+        let fd = (STDERR + 1..)
+            .find(|fd| !self.files.contains_key(fd))
+            .unwrap();
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    pub fn get(&self, fd: u64) -> Option<&OpenFile> {
+        self.files.get(&fd)
+    }
+
+    pub fn get_mut(&mut self, fd: u64) -> Option<&mut OpenFile> {
+        self.files.get_mut(&fd)
+    }
+
+    pub fn remove(&mut self, fd: u64) -> Option<OpenFile> {
+        self.files.remove(&fd)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
@@ -0,0 +1,451 @@
+//! System calls from ring 3.
+//!
+//! The number goes in `rax`, arguments in `rdi`, `rsi`, `rdx`, `r10`, `r8`
+//! and `r9` (the Linux convention, `rcx` is clobbered by `syscall`). The
+//! result comes back in `rax`, negated [`Errno`] values signal errors.
+//! `int 0x80` takes the same registers for CPUs without `syscall`.
+
+use alloc::{string::String, vec::Vec};
+use core::sync::atomic::Ordering;
+use x86_64::{
+    registers::{
+        model_specific::{Efer, EferFlags, LStar, SFMask, Star},
+        rflags::RFlags,
+    },
+    VirtAddr,
+};
+
+use self::files::OpenFile;
+use crate::{
+    exec::{self, ExecError},
+    filesystem::file_tree::{self, File},
+    gdt,
+    ipc::{self, IpcError},
+    line_editor,
+    memory::address_space::MapError,
+    print,
+    signal::{self, Disposition, Signal, SignalSet},
+    smp,
+    thread::{
+        self, context::InterruptContext, ExitStatus, ForkError, KillError, ThreadId, WaitError,
+    },
+};
+
+mod entry;
+pub mod files;
+pub mod user;
+
+pub const INT80_VECTOR: u8 = 0x80;
+
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_OPEN: u64 = 2;
+pub const SYS_CLOSE: u64 = 3;
+pub const SYS_EXIT: u64 = 4;
+pub const SYS_SLEEP: u64 = 5;
+pub const SYS_GETPID: u64 = 6;
+pub const SYS_CHANNEL: u64 = 7;
+pub const SYS_SEND: u64 = 8;
+pub const SYS_RECV: u64 = 9;
+pub const SYS_HANDLE_CLOSE: u64 = 10;
+pub const SYS_KILL: u64 = 11;
+pub const SYS_SIGACTION: u64 = 12;
+pub const SYS_SIGPROCMASK: u64 = 13;
+pub const SYS_SIGRETURN: u64 = 14;
+pub const SYS_FORK: u64 = 15;
+pub const SYS_EXEC: u64 = 16;
+pub const SYS_WAITPID: u64 = 17;
+pub const SYS_GETPPID: u64 = 18;
+
+/// `open` flag creating the file if it does not exist.
+pub const O_CREAT: u64 = 0x40;
+
+/// `send`/`recv` flag failing with `EAGAIN` instead of blocking.
+pub const MSG_NONBLOCK: u64 = 1;
+
+/// `sigprocmask` operations.
+pub const SIG_BLOCK: u64 = 0;
+pub const SIG_UNBLOCK: u64 = 1;
+pub const SIG_SETMASK: u64 = 2;
+
+/// `waitpid` pid waiting for any child.
+pub const WAIT_ANY: u64 = u64::MAX;
+/// `waitpid` flag returning 0 instead of blocking if no child has exited.
+pub const WNOHANG: u64 = 1;
+
+/// Most arguments `exec` passes to the new program.
+pub const MAX_ARGS: usize = 32;
+
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    E2BIG = 7,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EFAULT = 14,
+    EINVAL = 22,
+    EMFILE = 24,
+    EPIPE = 32,
+    ENAMETOOLONG = 36,
+    ENOSYS = 38,
+    EMSGSIZE = 90,
+}
+
+impl From<IpcError> for Errno {
+    fn from(error: IpcError) -> Self {
+        match error {
+            IpcError::WouldBlock => Errno::EAGAIN,
+            IpcError::Disconnected => Errno::EPIPE,
+            IpcError::Interrupted => Errno::EINTR,
+            IpcError::BadHandle | IpcError::WrongEnd => Errno::EBADF,
+            IpcError::TooLarge => Errno::EMSGSIZE,
+            IpcError::InvalidCapacity => Errno::EINVAL,
+            IpcError::TooManyHandles => Errno::EMFILE,
+        }
+    }
+}
+
+impl From<ExecError> for Errno {
+    fn from(error: ExecError) -> Self {
+        match error {
+            ExecError::NotFound => Errno::ENOENT,
+            ExecError::Elf(_) => Errno::ENOEXEC,
+            ExecError::Map(MapError::OutOfMemory) => Errno::ENOMEM,
+            ExecError::Map(_) => Errno::ENOEXEC,
+            ExecError::ArgumentsTooLong => Errno::E2BIG,
+        }
+    }
+}
+
+enum Handler {
+    Args(fn(&[u64; 6]) -> Result<u64, Errno>),
+    /// Calls that read or replace the saved user context.
+    Context(fn(&mut InterruptContext) -> Result<u64, Errno>),
+}
+
+/// Indexed by syscall number.
+static SYSCALLS: [Handler; 19] = [
+    Handler::Args(sys_read),
+    Handler::Args(sys_write),
+    Handler::Args(sys_open),
+    Handler::Args(sys_close),
+    Handler::Args(sys_exit),
+    Handler::Args(sys_sleep),
+    Handler::Args(sys_getpid),
+    Handler::Args(sys_channel),
+    Handler::Args(sys_send),
+    Handler::Args(sys_recv),
+    Handler::Args(sys_handle_close),
+    Handler::Args(sys_kill),
+    Handler::Args(sys_sigaction),
+    Handler::Args(sys_sigprocmask),
+    Handler::Context(sys_sigreturn),
+    Handler::Context(sys_fork),
+    Handler::Context(sys_exec),
+    Handler::Args(sys_waitpid),
+    Handler::Args(sys_getppid),
+];
+
+/// Enables `syscall` and points it at the entry stub. Needs the GDT.
+pub fn init() {
+    let selectors = gdt::selectors();
+    let user_code = u64::from(selectors.user_code_selector.0);
+    let user_data = u64::from(selectors.user_data_selector.0);
+    entry::USER_CODE.store(user_code, Ordering::Relaxed);
+    entry::USER_DATA.store(user_data, Ordering::Relaxed);
+    set_kernel_stack(gdt::kernel_stack());
+    init_cpu();
+}
+
+/// Points `syscall` of the calling CPU at the entry stub. The MSRs are per
+/// processor, so application processors call this on their own.
+pub fn init_cpu() {
+    let selectors = gdt::selectors();
+    Star::write(
+        selectors.user_code_selector,
+        selectors.user_data_selector,
+        selectors.code_selector,
+        selectors.data_selector,
+    )
+    .expect("GDT layout does not match syscall/sysret");
+    LStar::write(VirtAddr::from_ptr(entry::syscall_entry as *const ()));
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::TRAP_FLAG | RFlags::DIRECTION_FLAG);
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+}
+
+/// Address of the `int 0x80` stub for the IDT.
+pub fn int80_handler() -> VirtAddr {
+    VirtAddr::new(entry::int80_entry())
+}
+
+/// Sets the stack `syscall` switches to on the calling CPU. Called together
+/// with the TSS update.
+pub(crate) fn set_kernel_stack(stack_top: VirtAddr) {
+    smp::current().set_kernel_stack(stack_top);
+}
+
+fn dispatch(context: &mut InterruptContext) -> u64 {
+    let regs = &context.regs;
+    let args = [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9];
+    let result = match SYSCALLS.get(regs.rax as usize) {
+        Some(Handler::Args(handler)) => handler(&args),
+        Some(Handler::Context(handler)) => handler(context),
+        None => Err(Errno::ENOSYS),
+    };
+    match result {
+        Ok(value) => value,
+        Err(errno) => (-(errno as i64)) as u64,
+    }
+}
+
+fn sys_read(args: &[u64; 6]) -> Result<u64, Errno> {
+    let buf = unsafe { user::slice_mut(args[1], args[2] as usize)? };
+    if args[0] == STDIN {
+        if let Some(stdin) = ipc::stdin(thread::current()) {
+            return Ok(stdin.read(buf)? as u64);
+        }
+        return Ok(line_editor::read_stdin(buf) as u64);
+    }
+
+    let file = thread::with_files(|files| files.get(args[0]).cloned()).ok_or(Errno::EBADF)?;
+    let count = file_tree::read_file(&file.name, file.offset, buf).ok_or(Errno::ENOENT)?;
+    advance(args[0], count);
+    Ok(count as u64)
+}
+
+fn sys_write(args: &[u64; 6]) -> Result<u64, Errno> {
+    let buf = unsafe { user::slice(args[1], args[2] as usize)? };
+    if args[0] == STDOUT {
+        if let Some(stdout) = ipc::stdout(thread::current()) {
+            return Ok(stdout.write(buf)? as u64);
+        }
+    }
+    if args[0] == STDOUT || args[0] == STDERR {
+        print!("{}", String::from_utf8_lossy(buf));
+        return Ok(buf.len() as u64);
+    }
+
+    let file = thread::with_files(|files| files.get(args[0]).cloned()).ok_or(Errno::EBADF)?;
+    let count = file_tree::write_file(&file.name, file.offset, buf).ok_or(Errno::ENOENT)?;
+    advance(args[0], count);
+    Ok(count as u64)
+}
+
+/// Moves the offset of file `fd` of the current thread past `count` bytes
+/// just read or written. The file tree is accessed without the scheduler
+/// lock, which guards the descriptors.
+fn advance(fd: u64, count: usize) {
+    thread::with_files(|files| {
+        if let Some(file) = files.get_mut(fd) {
+            file.offset += count;
+        }
+    });
+}
+
+fn sys_open(args: &[u64; 6]) -> Result<u64, Errno> {
+    let name = user::string(args[0], args[1] as usize)?;
+    if name.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    if !file_tree::file_exists(&name) {
+        if args[2] & O_CREAT == 0 {
+            return Err(Errno::ENOENT);
+        }
+        file_tree::insert_content(File::new(name.clone(), Vec::new()));
+    }
+
+    thread::with_files(|files| files.insert(OpenFile { name, offset: 0 }))
+}
+
+fn sys_close(args: &[u64; 6]) -> Result<u64, Errno> {
+    thread::with_files(|files| files.remove(args[0])).ok_or(Errno::EBADF)?;
+    Ok(0)
+}
+
+fn sys_exit(args: &[u64; 6]) -> Result<u64, Errno> {
+    thread::exit_with(ExitStatus::Code(args[0] as i64))
+}
+
+fn sys_sleep(args: &[u64; 6]) -> Result<u64, Errno> {
+    thread::sleep(args[0]);
+    Ok(0)
+}
+
+fn sys_getpid(_args: &[u64; 6]) -> Result<u64, Errno> {
+    Ok(thread::current().as_u64())
+}
+
+/// `channel(capacity, handles)`: stores the sending and receiving handle as
+/// two `u64` at `handles`.
+fn sys_channel(args: &[u64; 6]) -> Result<u64, Errno> {
+    let out = unsafe { user::slice_mut(args[1], 16)? };
+    let (send, recv) = ipc::create_channel(thread::current(), args[0] as usize)?;
+    out[..8].copy_from_slice(&send.to_le_bytes());
+    out[8..].copy_from_slice(&recv.to_le_bytes());
+    Ok(0)
+}
+
+/// `send(handle, buf, len, flags)`
+fn sys_send(args: &[u64; 6]) -> Result<u64, Errno> {
+    if args[2] as usize > ipc::MAX_MESSAGE {
+        return Err(Errno::EMSGSIZE);
+    }
+    let message = unsafe { user::slice(args[1], args[2] as usize)? }.to_vec();
+    let blocking = args[3] & MSG_NONBLOCK == 0;
+    ipc::send(thread::current(), args[0], message, blocking)?;
+    Ok(0)
+}
+
+/// `recv(handle, buf, len, flags)` returns the length of the message. A
+/// message longer than `len` is cut off.
+fn sys_recv(args: &[u64; 6]) -> Result<u64, Errno> {
+    let buf = unsafe { user::slice_mut(args[1], args[2] as usize)? };
+    let blocking = args[3] & MSG_NONBLOCK == 0;
+    let message = ipc::recv(thread::current(), args[0], blocking)?;
+    let count = message.len().min(buf.len());
+    buf[..count].copy_from_slice(&message[..count]);
+    Ok(message.len() as u64)
+}
+
+fn sys_handle_close(args: &[u64; 6]) -> Result<u64, Errno> {
+    ipc::close(thread::current(), args[0])?;
+    Ok(0)
+}
+
+/// `kill(pid, signal)`
+fn sys_kill(args: &[u64; 6]) -> Result<u64, Errno> {
+    let signal = Signal::from_number(args[1]).ok_or(Errno::EINVAL)?;
+    match thread::send_signal(ThreadId::from_u64(args[0]), signal) {
+        Ok(()) => Ok(0),
+        Err(KillError::NoSuchThread) => Err(Errno::ESRCH),
+        Err(KillError::KernelThread) => Err(Errno::EPERM),
+    }
+}
+
+/// `sigaction(signal, handler)` returns the previous handler. `SIG_DFL`
+/// and `SIG_IGN` stand for the default action and ignoring the signal.
+fn sys_sigaction(args: &[u64; 6]) -> Result<u64, Errno> {
+    let signal = Signal::from_number(args[0]).ok_or(Errno::EINVAL)?;
+    let disposition = Disposition::from_u64(args[1]);
+    if let Disposition::Handler(handler) = disposition {
+        user::check(handler, 1, false)?;
+    }
+    let old = thread::set_signal_disposition(signal, disposition).ok_or(Errno::EINVAL)?;
+    Ok(old.as_u64())
+}
+
+/// `sigprocmask(how, set)` returns the previous mask.
+fn sys_sigprocmask(args: &[u64; 6]) -> Result<u64, Errno> {
+    let old = thread::blocked_signals();
+    let blocked = match args[0] {
+        SIG_BLOCK => old.0 | args[1],
+        SIG_UNBLOCK => old.0 & !args[1],
+        SIG_SETMASK => args[1],
+        _ => return Err(Errno::EINVAL),
+    };
+    thread::set_blocked_signals(SignalSet(blocked));
+    Ok(old.0)
+}
+
+/// Returns from a signal handler with the registers saved when it was
+/// entered.
+fn sys_sigreturn(context: &mut InterruptContext) -> Result<u64, Errno> {
+    match signal::sigreturn(context) {
+        Ok(rax) => Ok(rax),
+        // there is nothing sensible to return to
+        Err(_) => thread::exit_with(ExitStatus::Signaled(Signal::SIGSEGV)),
+    }
+}
+
+/// `fork()` returns the pid of the copy to the caller and 0 to the copy.
+fn sys_fork(context: &mut InterruptContext) -> Result<u64, Errno> {
+    match thread::fork(context) {
+        Ok(child) => Ok(child.as_u64()),
+        Err(ForkError::KernelThread) => Err(Errno::EPERM),
+        Err(ForkError::OutOfMemory) => Err(Errno::ENOMEM),
+    }
+}
+
+/// `exec(path, path_len, argv, argc)` runs the executable `path` in place
+/// of the caller. `argv` points at `argc` pairs of string pointer and
+/// length, `argv[0]` of the new program is `path`. Only returns on errors.
+fn sys_exec(context: &mut InterruptContext) -> Result<u64, Errno> {
+    let regs = &context.regs;
+    let (argv, argc) = (regs.rdx, regs.r10 as usize);
+    let name = user::string(regs.rdi, regs.rsi as usize)?;
+    if argc > MAX_ARGS {
+        return Err(Errno::E2BIG);
+    }
+    // everything is copied before the old image goes away
+    let pairs = unsafe { user::slice(argv, argc * 16)? };
+    let args = pairs
+        .chunks_exact(16)
+        .map(|pair| {
+            let ptr = u64::from_le_bytes(pair[..8].try_into().unwrap());
+            let len = u64::from_le_bytes(pair[8..].try_into().unwrap());
+            user::string(ptr, len as usize)
+        })
+        .collect::<Result<Vec<String>, Errno>>()?;
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    exec::replace_current(&name, &args, &[], context)?;
+    Ok(0)
+}
+
+/// `waitpid(pid, status, flags)` waits for the child `pid`, or any child for
+/// [`WAIT_ANY`], to exit and returns its pid. Unless `status` is null the
+/// exit status is stored there as a `u64`, see [`ExitStatus::wait_status`].
+fn sys_waitpid(args: &[u64; 6]) -> Result<u64, Errno> {
+    let id = match args[0] {
+        WAIT_ANY => None,
+        pid => Some(ThreadId::from_u64(pid)),
+    };
+    if args[1] != 0 {
+        user::check(args[1], 8, true)?;
+    }
+    let block = args[2] & WNOHANG == 0;
+    let (child, status) = match thread::wait_child(id, block) {
+        Ok(Some(exited)) => exited,
+        Ok(None) => return Ok(0),
+        Err(WaitError::Interrupted) => return Err(Errno::EINTR),
+        Err(_) => return Err(Errno::ECHILD),
+    };
+    if args[1] != 0 {
+        let out = unsafe { user::slice_mut(args[1], 8)? };
+        out.copy_from_slice(&status.wait_status().to_le_bytes());
+    }
+    Ok(child.as_u64())
+}
+
+/// Returns the pid of the parent, or 0 for a program started by the kernel
+/// itself.
+fn sys_getppid(_args: &[u64; 6]) -> Result<u64, Errno> {
+    Ok(thread::parent().map_or(0, ThreadId::as_u64))
+}
+
+#[test_case]
+fn test_unknown_syscall_is_enosys() {
+    let mut context = InterruptContext::default();
+    context.regs.rax = 1000;
+    assert_eq!(dispatch(&mut context), (-(Errno::ENOSYS as i64)) as u64);
+}
+
+#[test_case]
+fn test_write_rejects_bad_pointer() {
+    let mut context = InterruptContext::default();
+    context.regs.rax = SYS_WRITE;
+    context.regs.rdi = STDOUT;
+    context.regs.rsi = 0xb8000;
+    context.regs.rdx = 4;
+    assert_eq!(dispatch(&mut context), (-(Errno::EFAULT as i64)) as u64);
+}
@@ -0,0 +1,95 @@
+use alloc::string::String;
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+use super::Errno;
+use crate::{
+    memory::{
+        self,
+        address_space::{COPY_ON_WRITE, USER_END, USER_START},
+    },
+    thread,
+};
+
+/// Longest path accepted from user space.
+pub const PATH_MAX: usize = 256;
+
+/// Checks that `len` bytes at `ptr` are mapped user accessible, and writable
+/// if `write` is set. Copy-on-write pages are copied before they are
+/// written.
+///
+/// Only the lowest level entry is inspected; the loader never maps user pages
+/// below a kernel-only table.
+pub fn check(ptr: u64, len: usize, write: bool) -> Result<(), Errno> {
+    if len == 0 {
+        return Ok(());
+    }
+    let end = ptr.checked_add(len as u64).ok_or(Errno::EFAULT)?;
+    if ptr < USER_START || end > USER_END {
+        return Err(Errno::EFAULT);
+    }
+
+    let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if write {
+        required |= PageTableFlags::WRITABLE;
+    }
+    let mut page = ptr & !0xfff;
+    while page < end {
+        let mut flags = memory::page_flags(VirtAddr::new(page)).ok_or(Errno::EFAULT)?;
+        if write && flags.contains(COPY_ON_WRITE) && thread::break_cow(VirtAddr::new(page)) {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if !flags.contains(required) {
+            return Err(Errno::EFAULT);
+        }
+        page += 4096;
+    }
+    Ok(())
+}
+
+/// Borrows a user buffer for reading.
+///
+/// # Safety
+///
+/// The mapping must not change while the slice is alive.
+pub unsafe fn slice<'a>(ptr: u64, len: usize) -> Result<&'a [u8], Errno> {
+    check(ptr, len, false)?;
+    if len == 0 {
+        return Ok(&[]);
+    }
+    Ok(core::slice::from_raw_parts(ptr as *const u8, len))
+}
+
+/// Borrows a user buffer for writing.
+///
+/// # Safety
+///
+/// The mapping must not change while the slice is alive.
+pub unsafe fn slice_mut<'a>(ptr: u64, len: usize) -> Result<&'a mut [u8], Errno> {
+    check(ptr, len, true)?;
+    if len == 0 {
+        return Ok(&mut []);
+    }
+    Ok(core::slice::from_raw_parts_mut(ptr as *mut u8, len))
+}
+
+/// Copies a UTF-8 string of `len` bytes out of user memory.
+pub fn string(ptr: u64, len: usize) -> Result<String, Errno> {
+    if len > PATH_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    let bytes = unsafe { slice(ptr, len)? };
+    core::str::from_utf8(bytes)
+        .map(String::from)
+        .map_err(|_| Errno::EINVAL)
+}
+
+#[test_case]
+fn test_rejects_kernel_and_unmapped_pointers() {
+    assert_eq!(check(0, 1, false), Err(Errno::EFAULT));
+    assert_eq!(check(USER_START, 1, false), Err(Errno::EFAULT));
+    assert_eq!(check(USER_END - 1, 2, false), Err(Errno::EFAULT));
+    assert_eq!(check(u64::MAX, 1, false), Err(Errno::EFAULT));
+    // the VGA buffer is mapped, but only for the kernel
+    assert_eq!(check(0xb8000, 1, false), Err(Errno::EFAULT));
+    assert_eq!(check(0, 0, false), Ok(()));
+}
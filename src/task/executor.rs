@@ -0,0 +1,149 @@
+use alloc::{collections::BTreeMap, collections::VecDeque, sync::Arc, task::Wake};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+use crossbeam_queue::ArrayQueue;
+use lazy_static::lazy_static;
+use x86_64::instructions::interrupts;
+
+use super::{Task, TaskId};
+use crate::sync::IrqSpinlock;
+
+const TASK_QUEUE_SIZE: usize = 100;
+
+lazy_static! {
+    /// Tasks spawned while the executor is running, e.g. from other tasks.
+    static ref SPAWN_QUEUE: IrqSpinlock<VecDeque<Task>> = IrqSpinlock::new(VecDeque::new());
+}
+
+pub(super) fn spawn(task: Task) {
+    SPAWN_QUEUE.lock().push_back(task);
+}
+
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    waker_cache: BTreeMap<TaskId, (Waker, Arc<TaskWaker>)>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ArrayQueue::new(TASK_QUEUE_SIZE)),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        let task_id = task.id;
+        if self.tasks.insert(task.id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.task_queue.push(task_id).expect("task queue full");
+    }
+
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.spawn_queued_tasks();
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    fn spawn_queued_tasks(&mut self) {
+        while let Some(task) = SPAWN_QUEUE.lock().pop_front() {
+            self.spawn(task);
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        let Self {
+            tasks,
+            task_queue,
+            waker_cache,
+        } = self;
+
+        while let Ok(task_id) = task_queue.pop() {
+            let task = match tasks.get_mut(&task_id) {
+                Some(task) => task,
+                None => continue, // task no longer exists
+            };
+            let (waker, task_waker) = waker_cache
+                .entry(task_id)
+                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+            // wakes from here on queue the task again
+            task_waker.queued.store(false, Ordering::Release);
+            let mut context = Context::from_waker(waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {
+                    tasks.remove(&task_id);
+                    waker_cache.remove(&task_id);
+                }
+                Poll::Pending => {}
+            }
+        }
+    }
+
+    /// Halts the CPU until the next interrupt if there is nothing to poll.
+    ///
+    /// Interrupts are disabled for the check so a wakeup arriving between the
+    /// check and `hlt` is not lost.
+    fn sleep_if_idle(&self) {
+        interrupts::disable();
+        if self.task_queue.is_empty() && SPAWN_QUEUE.lock().is_empty() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    /// The task is in the queue and will be polled; further wakes until then
+    /// add nothing.
+    queued: AtomicBool,
+}
+
+impl TaskWaker {
+    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> (Waker, Arc<TaskWaker>) {
+        let task_waker = Arc::new(TaskWaker {
+            task_id,
+            task_queue,
+            // a new waker belongs to a task that is being polled
+            queued: AtomicBool::new(false),
+        });
+        (Waker::from(task_waker.clone()), task_waker)
+    }
+
+    /// Called from interrupt handlers, so it must not panic.
+    fn wake_task(&self) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // every task is queued at most once, so this only fails with more
+        // than TASK_QUEUE_SIZE tasks; a lost wake beats a panic in an IRQ
+        if self.task_queue.push(self.task_id).is_err() {
+            self.queued.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
@@ -0,0 +1,76 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures_util::{
+    stream::{Stream, StreamExt},
+    task::AtomicWaker,
+};
+
+use crate::{
+    keyboard::{KEYBOARD, SCANCODE_QUEUE},
+    line_editor::LINE_EDITOR,
+};
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Wakes the task waiting on a [`ScancodeStream`]. Called from the keyboard
+/// interrupt handler after a scancode was queued.
+pub(crate) fn wake() {
+    WAKER.wake();
+}
+
+/// Stream of raw scancodes from the keyboard interrupt handler.
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    pub const fn new() -> Self {
+        ScancodeStream { _private: () }
+    }
+}
+
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        // fast path
+        if let Some(scancode) = SCANCODE_QUEUE.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        WAKER.register(cx.waker());
+        match SCANCODE_QUEUE.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Decodes scancodes and feeds the resulting keys to the line editor.
+pub async fn handle_keypresses() {
+    let mut scancodes = ScancodeStream::new();
+
+    while let Some(scancode) = scancodes.next().await {
+        let key = {
+            let mut keyboard = KEYBOARD.lock();
+            match keyboard.add_byte(scancode) {
+                Ok(Some(key_event)) => keyboard.process_keyevent(key_event),
+                _ => None,
+            }
+        };
+        if let Some(key) = key {
+            LINE_EDITOR.lock().handle_key(key);
+        }
+    }
+}
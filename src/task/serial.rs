@@ -0,0 +1,69 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures_util::{
+    stream::{Stream, StreamExt},
+    task::AtomicWaker,
+};
+
+use crate::{
+    line_editor::LINE_EDITOR,
+    serial::{uart::PORT_COUNT, SERIAL_PORTS},
+};
+
+static WAKERS: [AtomicWaker; PORT_COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicWaker = AtomicWaker::new();
+    [EMPTY; PORT_COUNT]
+};
+
+/// Wakes the task waiting on the [`SerialInputStream`] of port `index`.
+/// Called from the serial interrupt handler after bytes were queued.
+pub(crate) fn wake(index: usize) {
+    WAKERS[index].wake();
+}
+
+/// Stream of bytes received on one serial port.
+pub struct SerialInputStream {
+    index: usize,
+}
+
+impl SerialInputStream {
+    /// `index` 0 is COM1.
+    pub const fn new(index: usize) -> Self {
+        SerialInputStream { index }
+    }
+
+    fn try_receive(&self) -> Option<u8> {
+        SERIAL_PORTS[self.index].lock().try_receive()
+    }
+}
+
+impl Stream for SerialInputStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        if let Some(byte) = self.try_receive() {
+            return Poll::Ready(Some(byte));
+        }
+
+        WAKERS[self.index].register(cx.waker());
+        match self.try_receive() {
+            Some(byte) => {
+                WAKERS[self.index].take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Feeds COM1 input to the same line editor as the keyboard.
+pub async fn handle_serial_input() {
+    let mut input = SerialInputStream::new(0);
+
+    while let Some(byte) = input.next().await {
+        LINE_EDITOR.lock().handle_byte(byte);
+    }
+}
@@ -0,0 +1,152 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::{stream::Stream, task::AtomicWaker};
+use x86_64::instructions::port::Port;
+
+/// Frequency the PIT is programmed to in [`init`].
+pub const TICKS_PER_SECOND: u64 = 100;
+
+const PIT_FREQUENCY: u64 = 1_193_182;
+const MAX_TICK_STREAMS: usize = 32;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// One waker slot per live [`TickStream`]; `USED_SLOTS` is the allocation
+/// bitmap. Fixed slots keep the interrupt handler free of locks and
+/// allocations.
+static WAKERS: [AtomicWaker; MAX_TICK_STREAMS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicWaker = AtomicWaker::new();
+    [EMPTY; MAX_TICK_STREAMS]
+};
+static USED_SLOTS: AtomicU32 = AtomicU32::new(0);
+
+/// Programs PIT channel 0 to fire [`TICKS_PER_SECOND`] times a second.
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TICKS_PER_SECOND) as u16;
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel0: Port<u8> = Port::new(0x40);
+    unsafe {
+        // channel 0, lobyte/hibyte, rate generator
+        command.write(0x34);
+        channel0.write(divisor as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+}
+
+/// Number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Called by the timer interrupt handler.
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    let used = USED_SLOTS.load(Ordering::Acquire);
+    for (slot, waker) in WAKERS.iter().enumerate() {
+        if used & (1 << slot) != 0 {
+            waker.wake();
+        }
+    }
+}
+
+/// Stream yielding the current tick count each time the timer fires.
+pub struct TickStream {
+    slot: usize,
+    last: u64,
+}
+
+impl TickStream {
+    /// Returns `None` if all waker slots are taken.
+    pub fn new() -> Option<Self> {
+        let mut used = USED_SLOTS.load(Ordering::Relaxed);
+        loop {
+            let slot = (!used).trailing_zeros() as usize;
+            if slot >= MAX_TICK_STREAMS {
+                return None;
+            }
+            match USED_SLOTS.compare_exchange(
+                used,
+                used | (1 << slot),
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Some(TickStream {
+                        slot,
+                        last: ticks(),
+                    })
+                }
+                Err(current) => used = current,
+            }
+        }
+    }
+}
+
+impl Drop for TickStream {
+    fn drop(&mut self) {
+        WAKERS[self.slot].take();
+        USED_SLOTS.fetch_and(!(1 << self.slot), Ordering::AcqRel);
+    }
+}
+
+impl Stream for TickStream {
+    type Item = u64;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u64>> {
+        let now = ticks();
+        if now > self.last {
+            self.last = now;
+            return Poll::Ready(Some(now));
+        }
+
+        WAKERS[self.slot].register(cx.waker());
+        let now = ticks();
+        if now > self.last {
+            WAKERS[self.slot].take();
+            self.last = now;
+            Poll::Ready(Some(now))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Future that completes once the tick count reaches a deadline.
+pub struct Sleep {
+    deadline: u64,
+    ticks: Option<TickStream>,
+}
+
+/// Waits for `ticks` timer interrupts.
+pub fn sleep(ticks_to_wait: u64) -> Sleep {
+    Sleep {
+        deadline: ticks() + ticks_to_wait,
+        ticks: None,
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+        if self.ticks.is_none() {
+            self.ticks = Some(TickStream::new().expect("out of tick stream slots"));
+        }
+        let deadline = self.deadline;
+        let stream = self.ticks.as_mut().unwrap();
+        while let Poll::Ready(Some(now)) = Pin::new(&mut *stream).poll_next(cx) {
+            if now >= deadline {
+                return Poll::Ready(());
+            }
+        }
+        Poll::Pending
+    }
+}
@@ -0,0 +1,105 @@
+/// General purpose registers in the order the interrupt stubs push them.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct SavedRegisters {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+}
+
+/// Frame pushed by the CPU on interrupt entry and consumed by `iretq`.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct InterruptFrame {
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// Everything a thread needs to resume: the stack pointer of a suspended
+/// thread points at one of these.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct InterruptContext {
+    pub regs: SavedRegisters,
+    pub frame: InterruptFrame,
+}
+
+/// Defines an interrupt entry point `$name` that saves all general purpose
+/// registers, calls `$handler(context: *mut InterruptContext) ->
+/// *mut InterruptContext` and resumes whichever context the handler returns.
+///
+/// Returning a different context than the one passed in switches threads.
+///
+/// Entries from and returns to ring 3 execute `swapgs`, so the GS base
+/// always points at the per-CPU data while in the kernel.
+#[macro_export]
+macro_rules! context_switch_stub {
+    ($name:ident, $handler:path) => {
+        core::arch::global_asm!(
+            concat!(".global ", stringify!($name)),
+            concat!(stringify!($name), ":"),
+            "test qword ptr [rsp + 8], 3",
+            "jz 2f",
+            "swapgs",
+            "2:",
+            "push rax",
+            "push rbx",
+            "push rcx",
+            "push rdx",
+            "push rsi",
+            "push rdi",
+            "push rbp",
+            "push r8",
+            "push r9",
+            "push r10",
+            "push r11",
+            "push r12",
+            "push r13",
+            "push r14",
+            "push r15",
+            "mov rdi, rsp",
+            "call {handler}",
+            "mov rsp, rax",
+            "pop r15",
+            "pop r14",
+            "pop r13",
+            "pop r12",
+            "pop r11",
+            "pop r10",
+            "pop r9",
+            "pop r8",
+            "pop rbp",
+            "pop rdi",
+            "pop rsi",
+            "pop rdx",
+            "pop rcx",
+            "pop rbx",
+            "pop rax",
+            "test qword ptr [rsp + 8], 3",
+            "jz 2f",
+            "swapgs",
+            "2:",
+            "iretq",
+            handler = sym $handler,
+        );
+
+        extern "C" {
+            fn $name();
+        }
+    };
+}
@@ -0,0 +1,772 @@
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::{
+    fmt, mem,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use spin::Mutex;
+use x86_64::{
+    instructions::{
+        interrupts,
+        segmentation::{Segment, CS, SS},
+    },
+    registers::control::Cr3,
+    VirtAddr,
+};
+
+use crate::{
+    fpu::{self, FpuState},
+    gdt, interuptions,
+    ipc::{self, Stdio},
+    memory::address_space::AddressSpace,
+    signal::{Delivery, Disposition, Signal, SignalSet},
+    syscall::files::FileTable,
+    task::timer,
+};
+use context::{InterruptContext, InterruptFrame, SavedRegisters};
+use scheduler::{Scheduler, Thread};
+
+pub mod context;
+pub mod policy;
+mod scheduler;
+
+/// Size of the kernel stack of every thread but the boot thread.
+pub(crate) const STACK_SIZE: usize = 4096 * 4;
+
+static SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+
+crate::cpu_local! {
+    /// Id of the thread running on each CPU, readable without the scheduler
+    /// lock.
+    static CURRENT: AtomicU64 = AtomicU64::new(0);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// An id named by a user program or the shell. It need not exist.
+    pub fn from_u64(id: u64) -> Self {
+        ThreadId(id)
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    Running,
+    Ready,
+    /// Sleeping until the given timer tick.
+    Sleeping(u64),
+    Blocked,
+    /// Stopped by a signal until SIGCONT or SIGKILL arrives.
+    Stopped,
+    Exited,
+}
+
+impl ThreadState {
+    pub fn name(self) -> &'static str {
+        match self {
+            ThreadState::Running => "running",
+            ThreadState::Ready => "ready",
+            ThreadState::Sleeping(_) => "sleeping",
+            ThreadState::Blocked => "blocked",
+            ThreadState::Stopped => "stopped",
+            ThreadState::Exited => "zombie",
+        }
+    }
+}
+
+/// How a thread ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Returned, or called `exit` with the given code.
+    Code(i64),
+    /// Terminated by SIGKILL.
+    Killed,
+    /// Terminated by the default action of another signal.
+    Signaled(Signal),
+}
+
+impl ExitStatus {
+    pub fn from_signal(signal: Signal) -> Self {
+        match signal {
+            Signal::SIGKILL => ExitStatus::Killed,
+            signal => ExitStatus::Signaled(signal),
+        }
+    }
+
+    /// Encoding reported by `waitpid`: the exit code in bits 8 to 15, or the
+    /// number of the terminating signal in the low bits.
+    pub fn wait_status(self) -> u64 {
+        match self {
+            ExitStatus::Code(code) => ((code as u64) & 0xff) << 8,
+            ExitStatus::Killed => Signal::SIGKILL as u64,
+            ExitStatus::Signaled(signal) => signal as u64,
+        }
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitStatus::Code(code) => write!(f, "exited with {}", code),
+            ExitStatus::Killed => write!(f, "killed"),
+            ExitStatus::Signaled(signal) => write!(f, "terminated by {}", signal.name()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NiceError {
+    NoSuchThread,
+    /// Outside of [`policy::MIN_NICE`] to [`policy::MAX_NICE`].
+    OutOfRange,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillError {
+    NoSuchThread,
+    /// Only user programs get signals; kernel threads may hold locks.
+    KernelThread,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    NoSuchThread,
+    /// The thread was not started by the caller.
+    NotAChild,
+    /// The caller has no children left to wait for.
+    NoChildren,
+    /// A signal arrived while waiting.
+    Interrupted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForkError {
+    /// Only user programs can be forked.
+    KernelThread,
+    OutOfMemory,
+}
+
+/// Snapshot of a thread for display purposes.
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: String,
+    pub state: ThreadState,
+    pub parent: Option<ThreadId>,
+    pub cpu_ticks: u64,
+    /// Ticks spent sleeping or blocked.
+    pub sleep_ticks: u64,
+    pub nice: i8,
+    /// Bytes of kernel stack and user address space.
+    pub memory: usize,
+    pub exit_status: Option<ExitStatus>,
+}
+
+impl ThreadInfo {
+    fn of(t: &Thread) -> Self {
+        ThreadInfo {
+            id: t.id,
+            name: t.name.clone(),
+            state: t.state,
+            parent: t.parent,
+            cpu_ticks: t.cpu_ticks,
+            sleep_ticks: t.sleep_ticks,
+            nice: t.nice,
+            memory: t.memory_usage(),
+            exit_status: t.exit_status,
+        }
+    }
+}
+
+/// Turns the code running `kernel_main` into the boot thread and starts the
+/// idle and init threads. Needs the heap; until this runs the timer never
+/// switches.
+pub fn init() {
+    let mut boot = Thread::new(
+        "boot",
+        core::ptr::null_mut(),
+        None,
+        gdt::kernel_stack(),
+        None,
+    );
+    boot.state = ThreadState::Running;
+    interrupts::without_interrupts(|| {
+        *SCHEDULER.lock() = Some(Scheduler::new(boot));
+    });
+
+    let idle = spawn("idle", || loop {
+        x86_64::instructions::hlt();
+    });
+    with_scheduler(|scheduler| {
+        scheduler.ready.retain(|id| *id != idle);
+        scheduler.idle = Some(idle);
+    });
+    // it must not find itself childless before it is known as init
+    interrupts::without_interrupts(|| {
+        let init = spawn("init", reap_orphans);
+        with_scheduler(|scheduler| scheduler.init = Some(init));
+    });
+}
+
+/// Body of the init thread: collects the exit status of programs whose
+/// parent exited before them, so they do not stay zombies, and frees
+/// threads nobody waits for.
+fn reap_orphans() {
+    loop {
+        if let Ok(Some((id, status))) = wait_child(None, true) {
+            log::debug!("init reaped thread {}: {}", id.0, status);
+        }
+    }
+}
+
+fn with_scheduler<R>(f: impl FnOnce(&mut Scheduler) -> R) -> R {
+    interrupts::without_interrupts(|| {
+        f(SCHEDULER
+            .lock()
+            .as_mut()
+            .expect("thread::init has not been called"))
+    })
+}
+
+/// Charges the current timer tick to the running thread. Called by the timer
+/// interrupt before [`schedule`].
+pub(crate) fn account_tick() {
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        scheduler.account_tick();
+    }
+}
+
+/// Called by the yield stub: like [`schedule`], letting every other ready
+/// thread go first.
+pub(crate) fn schedule_yield(context: *mut InterruptContext) -> *mut InterruptContext {
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        scheduler.current_mut().yielded = true;
+    }
+    schedule(context)
+}
+
+/// Called by the keyboard and serial interrupts when input arrives: moves
+/// the threads handling it to the front and switches to them right away.
+pub(crate) fn input_arrived(context: *mut InterruptContext) -> *mut InterruptContext {
+    let boosted = match SCHEDULER.lock().as_mut() {
+        Some(scheduler) => scheduler.boost_interactive(),
+        None => false,
+    };
+    if boosted {
+        schedule(context)
+    } else {
+        context
+    }
+}
+
+/// Called by the timer and yield interrupt stubs.
+pub(crate) fn schedule(context: *mut InterruptContext) -> *mut InterruptContext {
+    // interrupts are disabled inside the stubs
+    match SCHEDULER.lock().as_mut() {
+        Some(scheduler) => {
+            let next = scheduler.schedule(context, timer::ticks());
+            CURRENT
+                .get()
+                .store(scheduler.current.as_u64(), Ordering::Relaxed);
+            next
+        }
+        None => context,
+    }
+}
+
+/// Gives the FPU to the running thread, see [`fpu`](crate::fpu).
+pub(crate) fn claim_fpu() {
+    with_scheduler(|scheduler| scheduler.claim_fpu());
+}
+
+/// Starts a kernel thread running `f` on its own stack.
+pub fn spawn<F>(name: &str, f: F) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_in(name, f, None)
+}
+
+/// Starts a thread that switches to `address_space` and drops to ring 3 at
+/// `entry`. The address space is freed once the thread has exited; the
+/// calling thread becomes its parent and has to `wait` for it.
+pub fn spawn_user(
+    name: &str,
+    address_space: AddressSpace,
+    entry: VirtAddr,
+    user_stack: VirtAddr,
+) -> ThreadId {
+    spawn_in(
+        name,
+        move || unsafe { gdt::enter_user_mode(entry, user_stack) },
+        Some(address_space),
+    )
+}
+
+fn spawn_in<F>(name: &str, f: F, address_space: Option<AddressSpace>) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    let mut stack = vec![0u8; STACK_SIZE].into_boxed_slice();
+    let stack_top = (stack.as_mut_ptr() as u64 + STACK_SIZE as u64) & !0xf;
+
+    // the entry point sees the stack as if it had been `call`ed
+    let entry_rsp = stack_top - 8;
+    let context_addr = entry_rsp - mem::size_of::<InterruptContext>() as u64;
+    let closure: Box<Box<dyn FnOnce() + Send>> = Box::new(Box::new(f));
+
+    let context = context_addr as *mut InterruptContext;
+    unsafe {
+        (entry_rsp as *mut u64).write(0);
+        context.write(InterruptContext {
+            regs: SavedRegisters {
+                rdi: Box::into_raw(closure) as u64,
+                ..SavedRegisters::default()
+            },
+            frame: InterruptFrame {
+                rip: thread_entry as *const () as u64,
+                cs: u64::from(CS::get_reg().0),
+                // interrupts enabled
+                rflags: 0x202,
+                rsp: entry_rsp,
+                ss: u64::from(SS::get_reg().0),
+            },
+        });
+    }
+
+    let thread = Thread::new(
+        name,
+        context,
+        Some(stack),
+        VirtAddr::new(stack_top),
+        address_space,
+    );
+    start(thread)
+}
+
+/// Hands a new thread to the scheduler. It inherits the nice value of the
+/// current thread, which becomes the parent of a user program.
+fn start(mut thread: Thread) -> ThreadId {
+    let id = thread.id;
+    log::debug!("spawned thread {} ({})", id.0, thread.name);
+    with_scheduler(|scheduler| {
+        thread.nice = scheduler.current_mut().nice;
+        if thread.address_space.is_some() {
+            thread.parent = Some(scheduler.current);
+        }
+        scheduler.add(thread)
+    });
+    id
+}
+
+/// Starts a copy of the calling user program that resumes from its system
+/// call `context` with `rax` 0. The copy shares its pages copy-on-write and
+/// inherits the signal dispositions and mask, the nice value, the FPU
+/// registers, the open files and the standard streams.
+pub fn fork(context: &InterruptContext) -> Result<ThreadId, ForkError> {
+    // allocated up front, the scheduler lock is held with interrupts off
+    let mut fpu_state = fpu::is_enabled().then(FpuState::new);
+    let (parent, name, page_table, signals, files, uses_fpu) = with_scheduler(|scheduler| {
+        let uses_fpu = match fpu_state.as_mut() {
+            Some(state) => scheduler.copy_fpu(state),
+            None => false,
+        };
+        let current = scheduler.current_mut();
+        let space = current
+            .address_space
+            .as_ref()
+            .ok_or(ForkError::KernelThread)?;
+        Ok((
+            current.id,
+            current.name.clone(),
+            space.page_table(),
+            current.signals.forked(),
+            current.files.clone(),
+            uses_fpu,
+        ))
+    })?;
+    // Copying the page tables takes a while and allocates, so it runs
+    // without the lock. Only this thread could drop or change its address
+    // space, and it is busy here.
+    let space =
+        unsafe { AddressSpace::fork_table(page_table) }.map_err(|_| ForkError::OutOfMemory)?;
+
+    let mut stack = vec![0u8; STACK_SIZE].into_boxed_slice();
+    let stack_top = (stack.as_mut_ptr() as u64 + STACK_SIZE as u64) & !0xf;
+    // where an interrupt from ring 3 would have saved it
+    let context_addr = stack_top - mem::size_of::<InterruptContext>() as u64;
+    let child_context = context_addr as *mut InterruptContext;
+    unsafe {
+        child_context.write(InterruptContext {
+            regs: SavedRegisters {
+                rax: 0,
+                ..context.regs
+            },
+            frame: context.frame,
+        });
+    }
+
+    let mut thread = Thread::new(
+        &name,
+        child_context,
+        Some(stack),
+        VirtAddr::new(stack_top),
+        Some(space),
+    );
+    thread.signals = signals;
+    thread.files = files;
+    thread.fpu = fpu_state.filter(|_| uses_fpu);
+    // the streams have to be in place before the child can write anything
+    let id = interrupts::without_interrupts(|| {
+        let id = start(thread);
+        let stdio = Stdio {
+            stdin: ipc::stdin(parent),
+            stdout: ipc::stdout(parent),
+        };
+        if stdio.stdin.is_some() || stdio.stdout.is_some() {
+            ipc::set_stdio(id, stdio);
+        }
+        id
+    });
+    Ok(id)
+}
+
+/// Replaces the address space of the calling user program with `space`,
+/// e.g. one a new executable was loaded into, and renames the thread.
+/// Caught signals return to their default action, the FPU registers to
+/// their initial state and open files are closed.
+pub fn replace_image(name: &str, space: AddressSpace) {
+    let old = with_scheduler(|scheduler| {
+        let old_fpu = scheduler.reset_fpu();
+        let current = scheduler.current_mut();
+        let (_, flags) = Cr3::read();
+        unsafe { Cr3::write(space.page_table(), flags) };
+        current.name = String::from(name);
+        current.signals.reset_handlers();
+        let files = mem::take(&mut current.files);
+        (current.address_space.replace(space), old_fpu, files)
+    });
+    // no longer active, so it can go
+    drop(old);
+}
+
+/// Runs `f` on the files the current thread has open. The scheduler lock is
+/// held, so `f` must not block.
+pub fn with_files<R>(f: impl FnOnce(&mut FileTable) -> R) -> R {
+    with_scheduler(|scheduler| f(&mut scheduler.current_mut().files))
+}
+
+/// Gives the current thread its own copy of the copy-on-write page at
+/// `addr`. Returns false if there is no such page or no memory to copy it.
+pub fn break_cow(addr: VirtAddr) -> bool {
+    with_scheduler(
+        |scheduler| match scheduler.current_mut().address_space.as_mut() {
+            Some(space) => space.break_cow(addr) == Ok(true),
+            None => false,
+        },
+    )
+}
+
+extern "C" fn thread_entry(closure: *mut Box<dyn FnOnce() + Send>) -> ! {
+    let f = unsafe { Box::from_raw(closure) };
+    f();
+    exit_with(ExitStatus::Code(0))
+}
+
+/// Whether [`init`] has run, i.e. threads can block.
+pub fn is_running() -> bool {
+    interrupts::without_interrupts(|| SCHEDULER.lock().is_some())
+}
+
+/// Marks the current thread blocked without switching away. The caller
+/// yields once it has published how to wake it, see
+/// [`WaitQueue`](crate::sync::WaitQueue). Returns the token a waker has to
+/// present to [`unblock`]; it changes with every call, so a waker holding an
+/// old one cannot end a later wait.
+pub fn block_current() -> u64 {
+    with_scheduler(|scheduler| {
+        let current = scheduler.current_mut();
+        current.state = ThreadState::Blocked;
+        current.wait_token += 1;
+        current.wait_token
+    })
+}
+
+/// The running thread's id without taking the scheduler lock, for code that
+/// may run while it is held. The boot thread before [`init`] counts as 0.
+pub fn current_id() -> u64 {
+    CURRENT.get().load(Ordering::Relaxed)
+}
+
+pub fn current() -> ThreadId {
+    with_scheduler(|scheduler| scheduler.current)
+}
+
+const _: () = assert!(interuptions::YIELD_VECTOR == 0x81);
+
+/// Gives up the rest of the current time slice.
+pub fn yield_now() {
+    unsafe { core::arch::asm!("int 0x81") };
+}
+
+/// Blocks the current thread for at least `ticks` timer ticks.
+pub fn sleep(ticks: u64) {
+    let until = timer::ticks() + ticks;
+    with_scheduler(|scheduler| scheduler.current_mut().state = ThreadState::Sleeping(until));
+    yield_now();
+}
+
+/// Blocks until the thread `id` has exited.
+pub fn join(id: ThreadId) {
+    let waiting = with_scheduler(|scheduler| {
+        let me = scheduler.current;
+        match scheduler.threads.get_mut(&id) {
+            Some(thread) if thread.state != ThreadState::Exited => {
+                thread.joiners.push(me);
+                scheduler.current_mut().state = ThreadState::Blocked;
+                true
+            }
+            _ => false,
+        }
+    });
+    if waiting {
+        yield_now();
+    }
+}
+
+/// Terminates the current thread and wakes everything joining it.
+pub fn exit() -> ! {
+    exit_with(ExitStatus::Code(0))
+}
+
+/// Like [`exit`], leaving `status` for the parent to collect with [`wait`].
+pub fn exit_with(status: ExitStatus) -> ! {
+    // closing handles wakes other threads, which needs the scheduler lock
+    crate::ipc::release_all(current());
+    let files = with_scheduler(|scheduler| {
+        let files = mem::take(&mut scheduler.current_mut().files);
+        let current = scheduler.current;
+        scheduler.exit_thread(current, status);
+        files
+    });
+    // closed now rather than when the parent collects the zombie
+    drop(files);
+    loop {
+        yield_now();
+    }
+}
+
+/// Terminates the user program running on thread `id` with SIGKILL.
+pub fn kill(id: ThreadId) -> Result<(), KillError> {
+    send_signal(id, Signal::SIGKILL)
+}
+
+/// Sends `signal` to the user program running on thread `id`. A thread
+/// sleeping or blocked in the kernel is woken if the signal is not blocked,
+/// the signal is acted on when it is about to return to user mode.
+pub fn send_signal(id: ThreadId, signal: Signal) -> Result<(), KillError> {
+    with_scheduler(|scheduler| {
+        let thread = scheduler
+            .threads
+            .get_mut(&id)
+            .ok_or(KillError::NoSuchThread)?;
+        if thread.address_space.is_none() {
+            return Err(KillError::KernelThread);
+        }
+        if thread.state == ThreadState::Exited {
+            return Ok(());
+        }
+        let generated = thread.signals.generate(signal);
+        let wake = match thread.state {
+            ThreadState::Stopped => matches!(signal, Signal::SIGKILL | Signal::SIGCONT),
+            ThreadState::Sleeping(_) | ThreadState::Blocked => {
+                generated && thread.signals.deliverable().contains(signal)
+            }
+            _ => false,
+        };
+        if wake {
+            scheduler.make_ready(id);
+        }
+        Ok(())
+    })
+}
+
+/// Whether the current thread has a signal to act on and should stop what
+/// it is doing. Checked by blocking system calls.
+pub fn signal_pending() -> bool {
+    with_scheduler(|scheduler| !scheduler.current_mut().signals.deliverable().is_empty())
+}
+
+/// Acts on the signals of the current thread before a system call returns
+/// to user mode with `context`: terminates or stops it, or enters a handler.
+pub(crate) fn handle_signals(context: &mut InterruptContext) {
+    loop {
+        let delivery = with_scheduler(|scheduler| {
+            let current = scheduler.current;
+            scheduler.deliver_signals(current, context)
+        });
+        match delivery {
+            Delivery::Resume => return,
+            Delivery::Terminate(signal) => exit_with(ExitStatus::from_signal(signal)),
+            Delivery::Stop => {
+                with_scheduler(|scheduler| scheduler.current_mut().state = ThreadState::Stopped);
+                yield_now();
+            }
+        }
+    }
+}
+
+/// Sets what the current thread does with `signal` and returns what it did
+/// before, or `None` for SIGKILL and SIGSTOP.
+pub fn set_signal_disposition(signal: Signal, disposition: Disposition) -> Option<Disposition> {
+    with_scheduler(|scheduler| {
+        scheduler
+            .current_mut()
+            .signals
+            .set_disposition(signal, disposition)
+    })
+}
+
+/// Signals the current thread has blocked.
+pub fn blocked_signals() -> SignalSet {
+    with_scheduler(|scheduler| scheduler.current_mut().signals.blocked())
+}
+
+pub fn set_blocked_signals(blocked: SignalSet) {
+    with_scheduler(|scheduler| scheduler.current_mut().signals.set_blocked(blocked));
+}
+
+/// Blocks until the child `id` has exited, then removes it and returns its
+/// exit status.
+pub fn wait(id: ThreadId) -> Result<ExitStatus, WaitError> {
+    let (_, status) = wait_child(Some(id), true)?.expect("blocking wait returned nothing");
+    Ok(status)
+}
+
+/// Waits for the child `id` of the current thread, or for any child, to
+/// exit, then removes it and returns its id and exit status. Without
+/// `block` returns `Ok(None)` if no child has exited yet. Children whose
+/// parent exits are adopted by the init thread.
+pub fn wait_child(
+    id: Option<ThreadId>,
+    block: bool,
+) -> Result<Option<(ThreadId, ExitStatus)>, WaitError> {
+    loop {
+        match with_scheduler(|scheduler| scheduler.collect_child(id, block))? {
+            Some(zombie) => {
+                let child = zombie.id;
+                let status = zombie.exit_status.unwrap_or(ExitStatus::Killed);
+                // stack and page tables are freed here, with the scheduler unlocked
+                drop(zombie);
+                // a thread killed by the scheduler never got to close them
+                crate::ipc::release_all(child);
+                return Ok(Some((child, status)));
+            }
+            None if !block => return Ok(None),
+            None => {
+                yield_now();
+                if signal_pending() {
+                    with_scheduler(|scheduler| scheduler.current_mut().waiting_for_child = false);
+                    return Err(WaitError::Interrupted);
+                }
+            }
+        }
+    }
+}
+
+/// Makes `to` the parent of the current thread's child `id`, so it is the
+/// one to collect the exit status.
+pub fn hand_over_child(id: ThreadId, to: ThreadId) -> Result<(), WaitError> {
+    with_scheduler(|scheduler| {
+        let me = scheduler.current;
+        if !scheduler.threads.contains_key(&to) {
+            return Err(WaitError::NoSuchThread);
+        }
+        let child = scheduler
+            .threads
+            .get_mut(&id)
+            .ok_or(WaitError::NoSuchThread)?;
+        if child.parent != Some(me) {
+            return Err(WaitError::NotAChild);
+        }
+        child.parent = Some(to);
+        Ok(())
+    })
+}
+
+/// Parent of the current thread, if it is a user program.
+pub fn parent() -> Option<ThreadId> {
+    with_scheduler(|scheduler| scheduler.current_mut().parent)
+}
+
+/// Marks the current thread as handling user input, e.g. the shell. It is
+/// boosted whenever a key or serial byte arrives.
+pub fn mark_interactive() {
+    with_scheduler(|scheduler| scheduler.current_mut().interactive = true);
+}
+
+/// Like [`unblock`], also moving the thread ahead of the others because it
+/// has been waiting for user input.
+pub fn unblock_interactive(id: ThreadId, token: u64) -> bool {
+    with_scheduler(|scheduler| {
+        let woken = scheduler.unblock(id, token);
+        if woken {
+            scheduler.boost(id);
+        }
+        woken
+    })
+}
+
+/// Sets the nice value of thread `id`, see [`policy`]. Threads it spawns
+/// from now on inherit it.
+pub fn set_nice(id: ThreadId, nice: i8) -> Result<(), NiceError> {
+    if !policy::is_valid_nice(nice) {
+        return Err(NiceError::OutOfRange);
+    }
+    with_scheduler(|scheduler| {
+        let thread = scheduler
+            .threads
+            .get_mut(&id)
+            .ok_or(NiceError::NoSuchThread)?;
+        thread.nice = nice;
+        Ok(())
+    })
+}
+
+/// Marks a thread runnable again if it is still blocked in the wait that
+/// [`block_current`] returned `token` for. Returns whether it was.
+pub fn unblock(id: ThreadId, token: u64) -> bool {
+    with_scheduler(|scheduler| scheduler.unblock(id, token))
+}
+
+pub fn list() -> Vec<ThreadInfo> {
+    with_scheduler(|scheduler| scheduler.threads.values().map(ThreadInfo::of).collect())
+}
+
+/// Snapshot of a single thread, `None` once it has been reaped.
+pub fn info(id: ThreadId) -> Option<ThreadInfo> {
+    with_scheduler(|scheduler| scheduler.threads.get(&id).map(ThreadInfo::of))
+}
+
+/// Sleeps a tick at a time until `done` returns true or `timeout` ticks have
+/// passed. Returns whether `done` did.
+pub fn poll_until(timeout: u64, mut done: impl FnMut() -> bool) -> bool {
+    let deadline = timer::ticks() + timeout;
+    while !done() {
+        if timer::ticks() >= deadline {
+            return false;
+        }
+        sleep(1);
+    }
+    true
+}
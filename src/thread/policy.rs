@@ -0,0 +1,61 @@
+//! Fair-share scheduling by virtual runtime.
+//!
+//! Every tick a thread runs adds to its virtual runtime, scaled down by the
+//! weight of its nice value, and the ready thread with the least virtual
+//! runtime runs next. A thread at nice 0 thus gets about 1.25 times the CPU
+//! of one at nice 1, the same ratio as on Linux.
+//!
+//! Threads waking from sleep are placed slightly ahead of the others instead
+//! of keeping whatever credit they built up while away, and threads handling
+//! user input are moved further ahead whenever a key arrives.
+
+pub const MIN_NICE: i8 = -20;
+pub const MAX_NICE: i8 = 19;
+
+/// Virtual runtime of one tick at nice 0.
+pub const TICK: u64 = 1024;
+/// How far behind the least virtual runtime a waking sleeper may start.
+pub const SLEEPER_CREDIT: u64 = 3 * TICK;
+/// How far behind it an interactive thread is moved when input arrives.
+pub const INTERACTIVE_BOOST: u64 = 6 * TICK;
+
+/// Weight of each nice value, from -20 to 19. Each step is about 1.25x.
+const WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+pub fn is_valid_nice(nice: i8) -> bool {
+    (MIN_NICE..=MAX_NICE).contains(&nice)
+}
+
+fn weight(nice: i8) -> u64 {
+    WEIGHTS[(nice.clamp(MIN_NICE, MAX_NICE) - MIN_NICE) as usize]
+}
+
+/// Virtual runtime charged for one tick at `nice`.
+pub fn tick_cost(nice: i8) -> u64 {
+    TICK * WEIGHTS[(-MIN_NICE) as usize] / weight(nice)
+}
+
+/// Virtual runtime of a thread waking up while the least among the running
+/// and ready threads is `min_vruntime`.
+pub fn placed_on_wakeup(vruntime: u64, min_vruntime: u64) -> u64 {
+    vruntime.max(min_vruntime.saturating_sub(SLEEPER_CREDIT))
+}
+
+#[test_case]
+fn test_lower_nice_is_charged_less() {
+    assert_eq!(tick_cost(0), TICK);
+    assert!(tick_cost(-5) < tick_cost(0));
+    assert!(tick_cost(19) > tick_cost(18));
+    assert_eq!(tick_cost(100), tick_cost(MAX_NICE));
+}
+
+#[test_case]
+fn test_sleeper_credit_is_bounded() {
+    let min = 100 * TICK;
+    assert_eq!(placed_on_wakeup(0, min), min - SLEEPER_CREDIT);
+    assert_eq!(placed_on_wakeup(min + 1, min), min + 1);
+}
@@ -0,0 +1,498 @@
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    vec::Vec,
+};
+use x86_64::{registers::control::Cr3, structures::paging::PhysFrame, VirtAddr};
+
+use super::{context::InterruptContext, policy, ExitStatus, ThreadId, ThreadState, WaitError};
+use crate::{
+    fpu::{self, FpuState},
+    gdt,
+    memory::address_space::AddressSpace,
+    signal::{self, Delivery, Signal, SignalState},
+    syscall::files::FileTable,
+    task::timer,
+};
+
+pub(super) struct Thread {
+    pub id: ThreadId,
+    pub name: String,
+    pub state: ThreadState,
+    /// Saved stack pointer while the thread is not running.
+    pub context: *mut InterruptContext,
+    /// `None` for the boot thread, which runs on the bootloader's stack.
+    pub stack: Option<Box<[u8]>>,
+    /// Loaded into the TSS while this thread runs, so interrupts from ring 3
+    /// land on the thread's own kernel stack.
+    pub kernel_stack_top: VirtAddr,
+    /// Threads blocked in `join` on this one.
+    pub joiners: Vec<ThreadId>,
+    /// Page tables of a user program; kernel threads run on the kernel's.
+    pub address_space: Option<AddressSpace>,
+    /// Thread that started this user program and collects its exit status
+    /// with `wait`. Exited threads with a parent stay around as zombies.
+    pub parent: Option<ThreadId>,
+    /// Timer ticks spent running.
+    pub cpu_ticks: u64,
+    pub exit_status: Option<ExitStatus>,
+    /// Pending, blocked and handled signals; acted on before the thread
+    /// next runs user code.
+    pub signals: SignalState,
+    /// Files opened with the `open` system call.
+    pub files: FileTable,
+    /// FPU registers, allocated when the thread first uses the FPU.
+    pub fpu: Option<FpuState>,
+    /// From -20 (most CPU) to 19 (least), inherited from the spawning thread.
+    pub nice: i8,
+    /// Weighted CPU time, see [`policy`].
+    pub vruntime: u64,
+    /// Timer ticks spent sleeping or blocked.
+    pub sleep_ticks: u64,
+    /// Tick at which the thread last went to sleep or blocked, while it is.
+    pub asleep_since: Option<u64>,
+    /// Handles user input and is boosted whenever some arrives.
+    pub interactive: bool,
+    /// Called `yield_now`; others go first at the next switch.
+    pub yielded: bool,
+    /// Blocked until one of its children exits.
+    pub waiting_for_child: bool,
+    /// Identifies the latest wait on a wait queue, see
+    /// [`super::block_current`].
+    pub wait_token: u64,
+}
+
+impl Thread {
+    /// A ready thread that first runs by resuming `context`.
+    pub fn new(
+        name: &str,
+        context: *mut InterruptContext,
+        stack: Option<Box<[u8]>>,
+        kernel_stack_top: VirtAddr,
+        address_space: Option<AddressSpace>,
+    ) -> Self {
+        Thread {
+            id: ThreadId::new(),
+            name: String::from(name),
+            state: ThreadState::Ready,
+            context,
+            stack,
+            kernel_stack_top,
+            joiners: Vec::new(),
+            address_space,
+            parent: None,
+            cpu_ticks: 0,
+            exit_status: None,
+            signals: SignalState::new(),
+            files: FileTable::new(),
+            fpu: None,
+            nice: 0,
+            vruntime: 0,
+            sleep_ticks: 0,
+            asleep_since: None,
+            interactive: false,
+            yielded: false,
+            waiting_for_child: false,
+            wait_token: 0,
+        }
+    }
+
+    /// Bytes of memory owned by the thread: its kernel stack and, for user
+    /// programs, the address space.
+    pub fn memory_usage(&self) -> usize {
+        let stack = self.stack.as_ref().map_or(0, |stack| stack.len());
+        let space = self
+            .address_space
+            .as_ref()
+            .map_or(0, |space| space.memory_usage());
+        stack + space
+    }
+
+    fn in_user_mode(&self) -> bool {
+        !self.context.is_null() && unsafe { (*self.context).frame.cs & 3 == 3 }
+    }
+
+    /// Marks the thread ready, charging the time it slept or was blocked.
+    /// Returns `false` for an exited thread, which is left alone.
+    fn wake(&mut self, min_vruntime: u64) -> bool {
+        if self.state == ThreadState::Exited {
+            return false;
+        }
+        if let Some(since) = self.asleep_since.take() {
+            self.sleep_ticks += timer::ticks().saturating_sub(since);
+            self.vruntime = policy::placed_on_wakeup(self.vruntime, min_vruntime);
+        }
+        self.state = ThreadState::Ready;
+        true
+    }
+}
+
+// Raw context pointers are only dereferenced by the owning thread's stub.
+unsafe impl Send for Thread {}
+
+pub(super) struct Scheduler {
+    pub threads: BTreeMap<ThreadId, Thread>,
+    pub ready: VecDeque<ThreadId>,
+    pub current: ThreadId,
+    pub idle: Option<ThreadId>,
+    /// Adopts the children of exited programs, see [`super::wait_child`].
+    pub init: Option<ThreadId>,
+    /// Least virtual runtime among running and ready threads, never
+    /// decreasing. New and waking threads are placed relative to it.
+    pub min_vruntime: u64,
+    /// Level 4 table active when the scheduler started.
+    pub kernel_page_table: PhysFrame,
+}
+
+impl Scheduler {
+    pub fn new(boot: Thread) -> Self {
+        let current = boot.id;
+        let mut threads = BTreeMap::new();
+        threads.insert(boot.id, boot);
+        Scheduler {
+            threads,
+            ready: VecDeque::new(),
+            current,
+            idle: None,
+            init: None,
+            min_vruntime: 0,
+            kernel_page_table: Cr3::read().0,
+        }
+    }
+
+    pub fn add(&mut self, mut thread: Thread) {
+        thread.vruntime = self.min_vruntime;
+        if thread.state == ThreadState::Ready {
+            self.ready.push_back(thread.id);
+        }
+        self.threads.insert(thread.id, thread);
+    }
+
+    pub fn current_mut(&mut self) -> &mut Thread {
+        self.threads
+            .get_mut(&self.current)
+            .expect("current thread missing")
+    }
+
+    /// Marks a thread exited and wakes everything joining it and its parent.
+    /// Its children are handed to the init thread.
+    pub fn exit_thread(&mut self, id: ThreadId, status: ExitStatus) {
+        let thread = match self.threads.get_mut(&id) {
+            Some(thread) => thread,
+            None => return,
+        };
+        thread.state = ThreadState::Exited;
+        thread.exit_status = Some(status);
+        if thread.parent.is_none() && Some(id) != self.init {
+            // init removes it, so its stack is not freed in the timer interrupt
+            thread.parent = self.init;
+        }
+        let parent = thread.parent;
+        let joiners = core::mem::take(&mut thread.joiners);
+        for joiner in joiners {
+            self.make_ready(joiner);
+        }
+
+        if let Some(init) = self.init {
+            let mut zombies = false;
+            for child in self.threads.values_mut().filter(|t| t.parent == Some(id)) {
+                child.parent = Some(init);
+                zombies |= child.state == ThreadState::Exited;
+            }
+            if zombies {
+                self.wake_waiting_parent(init);
+            }
+        }
+        if let Some(parent) = parent.and_then(|parent| self.threads.get_mut(&parent)) {
+            if parent.address_space.is_some() {
+                parent.signals.generate(Signal::SIGCHLD);
+            }
+            let parent = parent.id;
+            self.wake_waiting_parent(parent);
+        }
+    }
+
+    fn wake_waiting_parent(&mut self, id: ThreadId) {
+        if let Some(thread) = self.threads.get_mut(&id) {
+            if thread.waiting_for_child && thread.state == ThreadState::Blocked {
+                thread.waiting_for_child = false;
+                self.make_ready(id);
+            }
+        }
+    }
+
+    /// Removes an exited child of the current thread, `id` or any, and
+    /// returns it. If there is none yet and `block` is set, marks the current
+    /// thread blocked until a child exits.
+    pub fn collect_child(
+        &mut self,
+        id: Option<ThreadId>,
+        block: bool,
+    ) -> Result<Option<Thread>, WaitError> {
+        let me = self.current;
+        self.current_mut().waiting_for_child = false;
+        if let Some(id) = id {
+            let thread = self.threads.get(&id).ok_or(WaitError::NoSuchThread)?;
+            if thread.parent != Some(me) {
+                return Err(WaitError::NotAChild);
+            }
+        }
+        let mut children = self
+            .threads
+            .values()
+            .filter(|t| t.parent == Some(me) && id.is_none_or(|id| t.id == id));
+        let zombie = children
+            .clone()
+            .find(|t| t.state == ThreadState::Exited)
+            .map(|t| t.id);
+        // init waits for orphans that do not exist yet
+        let childless = children.next().is_none() && Some(me) != self.init;
+        if let Some(zombie) = zombie {
+            return Ok(self.remove(zombie));
+        }
+        if childless {
+            return Err(WaitError::NoChildren);
+        }
+        if block {
+            let current = self.current_mut();
+            current.waiting_for_child = true;
+            current.state = ThreadState::Blocked;
+            // no wait queue entry left behind may end this wait
+            current.wait_token += 1;
+        }
+        Ok(None)
+    }
+
+    /// Readies `id` if it is blocked in the wait `token` belongs to.
+    pub fn unblock(&mut self, id: ThreadId, token: u64) -> bool {
+        match self.threads.get(&id) {
+            Some(t) if t.state == ThreadState::Blocked && t.wait_token == token => {
+                self.make_ready(id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn make_ready(&mut self, id: ThreadId) {
+        let min_vruntime = self.min_vruntime;
+        if let Some(thread) = self.threads.get_mut(&id) {
+            if thread.wake(min_vruntime) {
+                self.ready.push_back(id);
+            }
+        }
+    }
+
+    /// Moves an interactive thread ahead of everything else ready.
+    pub fn boost(&mut self, id: ThreadId) {
+        let target = self.min_vruntime.saturating_sub(policy::INTERACTIVE_BOOST);
+        if let Some(thread) = self.threads.get_mut(&id) {
+            thread.vruntime = thread.vruntime.min(target);
+        }
+    }
+
+    /// Boosts every thread marked interactive. Returns whether there was one.
+    pub fn boost_interactive(&mut self) -> bool {
+        let target = self.min_vruntime.saturating_sub(policy::INTERACTIVE_BOOST);
+        let mut boosted = false;
+        for thread in self.threads.values_mut().filter(|t| t.interactive) {
+            thread.vruntime = thread.vruntime.min(target);
+            boosted = true;
+        }
+        boosted
+    }
+
+    /// Charges the current timer tick to the running thread.
+    pub fn account_tick(&mut self) {
+        let idle = self.idle;
+        let thread = self.current_mut();
+        thread.cpu_ticks += 1;
+        if Some(thread.id) != idle {
+            thread.vruntime += policy::tick_cost(thread.nice);
+        }
+    }
+
+    /// Saves `context` for the current thread and returns the context of the
+    /// thread that should run next.
+    pub fn schedule(&mut self, context: *mut InterruptContext, now: u64) -> *mut InterruptContext {
+        let current = self.current;
+        let idle = self.idle;
+        let thread = self.current_mut();
+        thread.context = context;
+        let yielded = core::mem::take(&mut thread.yielded);
+        match thread.state {
+            ThreadState::Running => {
+                thread.state = ThreadState::Ready;
+                if Some(current) != idle {
+                    self.ready.push_back(current);
+                }
+            }
+            ThreadState::Sleeping(_) | ThreadState::Blocked | ThreadState::Stopped => {
+                thread.asleep_since.get_or_insert(now);
+            }
+            _ => {}
+        }
+
+        self.wake_sleepers(now);
+
+        let next = loop {
+            match self.pop_next(yielded.then_some(current)) {
+                Some(id) => {
+                    let thread = &self.threads[&id];
+                    // no kernel locks are held by a thread interrupted in ring 3
+                    if thread.signals.deliverable().is_empty() || !thread.in_user_mode() {
+                        break id;
+                    }
+                    let context = unsafe { &mut *thread.context };
+                    match self.deliver_signals(id, context) {
+                        Delivery::Resume => break id,
+                        Delivery::Terminate(signal) => {
+                            self.exit_thread(id, ExitStatus::from_signal(signal))
+                        }
+                        Delivery::Stop => {
+                            let thread = self.threads.get_mut(&id).unwrap();
+                            thread.state = ThreadState::Stopped;
+                            thread.asleep_since = Some(now);
+                        }
+                    }
+                }
+                None => break idle.unwrap_or(current),
+            }
+        };
+
+        self.current = next;
+        self.update_min_vruntime();
+        let kernel_page_table = self.kernel_page_table;
+        let thread = self.current_mut();
+        thread.state = ThreadState::Running;
+        unsafe { gdt::set_kernel_stack(thread.kernel_stack_top) };
+        let page_table = match &thread.address_space {
+            Some(space) => space.page_table(),
+            None => kernel_page_table,
+        };
+        let (active, flags) = Cr3::read();
+        if active != page_table {
+            unsafe { Cr3::write(page_table, flags) };
+        }
+        fpu::switch_to(next);
+        thread.context
+    }
+
+    /// Acts on the signals of thread `id`, which is about to return to user
+    /// mode with `context`, see [`signal::deliver`].
+    pub fn deliver_signals(&mut self, id: ThreadId, context: &mut InterruptContext) -> Delivery {
+        match self.threads.get_mut(&id) {
+            Some(Thread {
+                signals,
+                address_space: Some(space),
+                ..
+            }) => signal::deliver(signals, context, space),
+            _ => Delivery::Resume,
+        }
+    }
+
+    /// Loads the FPU registers of the current thread, saving those of the
+    /// previous owner first. Called on the device-not-available exception.
+    pub fn claim_fpu(&mut self) {
+        fpu::clear_task_switched();
+        let current = self.current;
+        let owner = fpu::owner();
+        if owner == Some(current) {
+            return;
+        }
+        // the owner may have exited since, its state is gone then
+        if let Some(previous) = owner.and_then(|id| self.threads.get_mut(&id)) {
+            if let Some(state) = previous.fpu.as_mut() {
+                state.save();
+            }
+        }
+        self.current_mut()
+            .fpu
+            .get_or_insert_with(FpuState::new)
+            .restore();
+        fpu::set_owner(Some(current));
+    }
+
+    /// Copies the FPU registers of the current thread into `state`, from
+    /// the FPU itself if they are loaded. Returns false if the thread never
+    /// used the FPU.
+    pub fn copy_fpu(&self, state: &mut FpuState) -> bool {
+        let current = self
+            .threads
+            .get(&self.current)
+            .expect("current thread missing");
+        if fpu::owner() == Some(current.id) {
+            state.save();
+        } else if let Some(saved) = current.fpu.as_ref() {
+            state.copy_from(saved);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Gives the current thread clean FPU registers from its next FPU
+    /// instruction on. Returns the old state, for the caller to drop once
+    /// the lock is released.
+    pub fn reset_fpu(&mut self) -> Option<FpuState> {
+        let current = self.current;
+        if fpu::owner() == Some(current) {
+            fpu::set_owner(None);
+            // trap on the next FPU instruction to load the clean state
+            fpu::switch_to(current);
+        }
+        self.current_mut().fpu.take()
+    }
+
+    /// Takes the ready thread with the least virtual runtime off the queue,
+    /// the one queued first among equals. A thread that just yielded only
+    /// runs again if nothing else is ready.
+    fn pop_next(&mut self, yielded: Option<ThreadId>) -> Option<ThreadId> {
+        let threads = &self.threads;
+        // drop stale entries, e.g. of threads killed while queued
+        self.ready
+            .retain(|id| threads.get(id).map(|t| t.state) == Some(ThreadState::Ready));
+        let (index, _) = self
+            .ready
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, id)| (Some(**id) == yielded, threads[*id].vruntime))?;
+        self.ready.remove(index)
+    }
+
+    fn update_min_vruntime(&mut self) {
+        let threads = &self.threads;
+        let running = Some(self.current)
+            .filter(|id| Some(*id) != self.idle)
+            .map(|id| threads[&id].vruntime);
+        let least = self
+            .ready
+            .iter()
+            .filter_map(|id| threads.get(id))
+            .map(|t| t.vruntime)
+            .chain(running)
+            .min();
+        if let Some(least) = least {
+            self.min_vruntime = self.min_vruntime.max(least);
+        }
+    }
+
+    /// Runs on every timer tick, so it walks the threads in place instead of
+    /// collecting the woken ones first.
+    fn wake_sleepers(&mut self, now: u64) {
+        let min_vruntime = self.min_vruntime;
+        for thread in self.threads.values_mut() {
+            if matches!(thread.state, ThreadState::Sleeping(until) if until <= now)
+                && thread.wake(min_vruntime)
+            {
+                self.ready.push_back(thread.id);
+            }
+        }
+    }
+
+    /// Removes a zombie once its parent has collected the exit status.
+    pub fn remove(&mut self, id: ThreadId) -> Option<Thread> {
+        self.threads.remove(&id)
+    }
+}
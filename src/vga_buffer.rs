@@ -1,7 +1,7 @@
 use lazy_static::lazy_static;
-use spin::Mutex;
 use volatile::Volatile;
-use x86_64::instructions::interrupts;
+
+use crate::sync::IrqSpinlock;
 
 #[macro_export]
 macro_rules! print {
@@ -17,18 +17,22 @@ macro_rules! println {
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
-    })
+    WRITER.lock().write_fmt(args).unwrap();
+    if crate::config::CONFIG.serial_console() {
+        crate::serial::_print(args);
+    }
 }
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        collumn_pos: 0,
-        row_pos: 0,
-        color_code: ColorByte::new(Color::White, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
+    pub static ref WRITER: IrqSpinlock<Writer> = IrqSpinlock::named(
+        "vga_buffer::WRITER",
+        Writer {
+            collumn_pos: 0,
+            row_pos: 0,
+            color_code: ColorByte::new(Color::White, Color::Black),
+            buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        }
+    );
 }
 
 #[allow(dead_code)]
@@ -116,6 +120,10 @@ impl Writer {
         self.color_code = ColorByte::new(color, Color::Black);
     }
 
+    pub fn set_colors(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorByte::new(foreground, background);
+    }
+
     pub fn get_debug_info(&mut self) -> usize {
         self.collumn_pos.clone()
     }
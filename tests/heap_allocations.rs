@@ -7,7 +7,10 @@
 extern crate alloc;
 
 use alloc::{boxed::Box, vec::Vec};
-use kernel::alocator::HEAP_SIZE;
+use kernel::alocator::{
+    fixed_size_blocks::{cached_blocks, CACHE_LIMIT},
+    HEAP_SIZE,
+};
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 
@@ -64,6 +67,38 @@ fn many_boxes_long_lived() {
     assert_eq!(*long_lived, 1); // new
 }
 
+#[test_case]
+fn freed_block_is_reused() {
+    let first = Box::new([0u8; 48]);
+    let addr = &*first as *const [u8; 48] as usize;
+    drop(first);
+    // served from this CPU's cache
+    let second = Box::new([1u8; 48]);
+    assert_eq!(&*second as *const [u8; 48] as usize, addr);
+}
+
+#[test_case]
+fn block_cache_is_bounded() {
+    let mut boxes = Vec::with_capacity(2 * CACHE_LIMIT);
+    // empties this CPU's cache of 64 byte blocks first
+    for _ in 0..2 * CACHE_LIMIT {
+        boxes.push(Box::new([0u8; 48]));
+    }
+    assert_eq!(cached_blocks(64), 0);
+
+    let addrs: Vec<usize> = boxes
+        .iter()
+        .map(|block| &**block as *const [u8; 48] as usize)
+        .collect();
+    boxes.clear();
+    // the rest went back to the shared lists
+    assert_eq!(cached_blocks(64), CACHE_LIMIT);
+
+    let block = Box::new([1u8; 48]);
+    assert_eq!(&*block as *const [u8; 48] as usize, addrs[CACHE_LIMIT - 1]);
+    assert_eq!(cached_blocks(64), CACHE_LIMIT - 1);
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     kernel::test_panic_handler(info)
@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use kernel::{
+    ipc::{self, IpcError},
+    thread,
+};
+
+kernel::test_harness!(thread::init);
+
+#[test_case]
+fn channel_is_bounded() {
+    let (sender, receiver) = ipc::channel(2);
+    assert_eq!(sender.try_send(1), Ok(()));
+    assert_eq!(sender.try_send(2), Ok(()));
+    assert_eq!(sender.try_send(3), Err(IpcError::WouldBlock));
+    assert_eq!(receiver.try_recv(), Ok(1));
+    assert_eq!(receiver.try_recv(), Ok(2));
+    assert_eq!(receiver.try_recv(), Err(IpcError::WouldBlock));
+}
+
+#[test_case]
+fn blocked_receiver_gets_message_from_thread() {
+    let (sender, receiver) = ipc::channel(1);
+    let producer = thread::spawn("producer", move || {
+        for i in 0..4 {
+            sender.send(i).unwrap();
+        }
+    });
+    for i in 0..4 {
+        assert_eq!(receiver.recv(), Ok(i));
+    }
+    thread::join(producer);
+    assert_eq!(receiver.recv(), Err(IpcError::Disconnected));
+}
+
+#[test_case]
+fn send_fails_without_receiver() {
+    let (sender, receiver) = ipc::channel(1);
+    drop(receiver);
+    assert_eq!(sender.send(1), Err(IpcError::Disconnected));
+}
+
+#[test_case]
+fn handles_belong_to_their_owner() {
+    let me = thread::current();
+    let (send, recv) = ipc::create_channel(me, 4).unwrap();
+    ipc::send(me, send, vec![1, 2, 3], false).unwrap();
+    assert_eq!(ipc::recv(me, recv, false), Ok(vec![1, 2, 3]));
+    assert_eq!(ipc::recv(me, send, false), Err(IpcError::WrongEnd));
+
+    let other = thread::spawn("other", || {});
+    ipc::transfer(recv, me, other).unwrap();
+    assert_eq!(ipc::recv(me, recv, false), Err(IpcError::BadHandle));
+    thread::join(other);
+    ipc::release_all(other);
+    assert_eq!(
+        ipc::send(me, send, vec![4], false),
+        Err(IpcError::Disconnected)
+    );
+    ipc::close(me, send).unwrap();
+    assert_eq!(ipc::handle_count(me), 0);
+}
+
+#[test_case]
+fn pipe_streams_until_writer_is_gone() {
+    let (writer, reader) = ipc::pipe();
+    let data = vec![7u8; ipc::PIPE_CAPACITY * 2];
+    let producer = thread::spawn("writer", move || {
+        assert_eq!(writer.write(&data), Ok(data.len()));
+    });
+    let mut total = 0;
+    let mut buf = [0; 512];
+    loop {
+        let count = reader.read(&mut buf).unwrap();
+        if count == 0 {
+            break;
+        }
+        assert!(buf[..count].iter().all(|&byte| byte == 7));
+        total += count;
+    }
+    thread::join(producer);
+    assert_eq!(total, ipc::PIPE_CAPACITY * 2);
+}
+
+#[test_case]
+fn pipe_write_fails_without_reader() {
+    let (writer, reader) = ipc::pipe();
+    drop(reader);
+    assert_eq!(writer.write(b"x"), Err(IpcError::Disconnected));
+}
@@ -0,0 +1,203 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use kernel::{
+    exec,
+    filesystem::file_tree::{self, File},
+    syscall::{self, files::MAX_FILES},
+    thread::{self, ExitStatus},
+};
+
+kernel::test_harness!(|| {
+    thread::init();
+    file_tree::insert_content(File::new(
+        String::from("exit7"),
+        exec::program_image(&exit_code(7), false),
+    ));
+    file_tree::insert_content(File::new(
+        String::from("fork"),
+        exec::program_image(&fork_code(), true),
+    ));
+    file_tree::insert_content(File::new(
+        String::from("exec"),
+        exec::program_image(&exec_code(), false),
+    ));
+    file_tree::insert_content(File::new(
+        String::from("orphan"),
+        exec::program_image(&orphan_code(), false),
+    ));
+    file_tree::insert_content(File::new(
+        String::from("open_all"),
+        exec::program_image(&open_all_code(), false),
+    ));
+});
+
+fn exit_code(status: u32) -> Vec<u8> {
+    let mut code = Vec::new();
+    code.push(0xb8); // mov eax, SYS_EXIT
+    code.extend((syscall::SYS_EXIT as u32).to_le_bytes());
+    code.push(0xbf); // mov edi, status
+    code.extend(status.to_le_bytes());
+    code.extend([0x0f, 0x05]); // syscall
+    code
+}
+
+/// Forks; the child writes to its copy of the shared page and exits with 7,
+/// the parent waits for it and exits with the code it got.
+fn fork_code() -> Vec<u8> {
+    let mut code = Vec::new();
+    code.push(0xb8); // mov eax, SYS_FORK
+    code.extend((syscall::SYS_FORK as u32).to_le_bytes());
+    code.extend([0x0f, 0x05]); // syscall
+    code.extend([0x48, 0x85, 0xc0]); // test rax, rax
+    code.extend([0x75, 0x13]); // jnz parent
+
+    // child:
+    code.extend([0xc6, 0x05]); // mov byte [rip + status], 1
+    code.extend(49u32.to_le_bytes());
+    code.push(1);
+    code.extend(exit_code(7));
+
+    // parent:
+    code.extend([0x48, 0x89, 0xc7]); // mov rdi, rax
+    code.push(0xb8); // mov eax, SYS_WAITPID
+    code.extend((syscall::SYS_WAITPID as u32).to_le_bytes());
+    code.extend([0x48, 0x8d, 0x35]); // lea rsi, [rip + status]
+    code.extend(22u32.to_le_bytes());
+    code.extend([0x31, 0xd2]); // xor edx, edx
+    code.extend([0x0f, 0x05]); // syscall
+    code.extend([0x48, 0x8b, 0x3d]); // mov rdi, [rip + status]
+    code.extend(11u32.to_le_bytes());
+    code.extend([0x48, 0xc1, 0xef, 0x08]); // shr rdi, 8
+    code.push(0xb8); // mov eax, SYS_EXIT
+    code.extend((syscall::SYS_EXIT as u32).to_le_bytes());
+    code.extend([0x0f, 0x05]); // syscall
+
+    // status:
+    code.extend([0; 8]);
+    code
+}
+
+/// Replaces itself with `exit7`, exits with 1 if that fails.
+fn exec_code() -> Vec<u8> {
+    const PATH: &[u8] = b"exit7";
+
+    let mut code = Vec::new();
+    code.push(0xb8); // mov eax, SYS_EXEC
+    code.extend((syscall::SYS_EXEC as u32).to_le_bytes());
+    code.extend([0x48, 0x8d, 0x3d]); // lea rdi, [rip + path]
+    code.extend(24u32.to_le_bytes());
+    code.push(0xbe); // mov esi, len
+    code.extend((PATH.len() as u32).to_le_bytes());
+    code.extend([0x31, 0xd2]); // xor edx, edx
+    code.extend([0x45, 0x31, 0xd2]); // xor r10d, r10d
+    code.extend([0x0f, 0x05]); // syscall
+    code.extend(exit_code(1));
+    code.extend_from_slice(PATH);
+    code
+}
+
+/// Forks and exits right away, leaving the child to sleep and exit on its
+/// own.
+fn orphan_code() -> Vec<u8> {
+    let mut code = Vec::new();
+    code.push(0xb8); // mov eax, SYS_FORK
+    code.extend((syscall::SYS_FORK as u32).to_le_bytes());
+    code.extend([0x0f, 0x05]); // syscall
+    code.extend([0x48, 0x85, 0xc0]); // test rax, rax
+    code.extend([0x74, 0x0c]); // jz child
+    code.extend(exit_code(0));
+
+    // child:
+    code.push(0xb8); // mov eax, SYS_SLEEP
+    code.extend((syscall::SYS_SLEEP as u32).to_le_bytes());
+    code.push(0xbf); // mov edi, 20
+    code.extend(20u32.to_le_bytes());
+    code.extend([0x0f, 0x05]); // syscall
+    code.extend(exit_code(3));
+    code
+}
+
+/// Opens a file until `open` fails, never closing it, and exits with the
+/// number of times it succeeded.
+fn open_all_code() -> Vec<u8> {
+    const PATH: &[u8] = b"opened";
+
+    let mut code = Vec::new();
+    code.extend([0x31, 0xdb]); // xor ebx, ebx
+
+    // loop:
+    code.push(0xb8); // mov eax, SYS_OPEN
+    code.extend((syscall::SYS_OPEN as u32).to_le_bytes());
+    code.extend([0x48, 0x8d, 0x3d]); // lea rdi, [rip + path]
+    code.extend(30u32.to_le_bytes());
+    code.push(0xbe); // mov esi, len
+    code.extend((PATH.len() as u32).to_le_bytes());
+    code.push(0xba); // mov edx, O_CREAT
+    code.extend((syscall::O_CREAT as u32).to_le_bytes());
+    code.extend([0x0f, 0x05]); // syscall
+    code.extend([0x48, 0x85, 0xc0]); // test rax, rax
+    code.extend([0x78, 0x04]); // js done
+    code.extend([0xff, 0xc3]); // inc ebx
+    code.extend([0xeb, 0xdf]); // jmp loop
+
+    // done:
+    code.extend([0x89, 0xdf]); // mov edi, ebx
+    code.push(0xb8); // mov eax, SYS_EXIT
+    code.extend((syscall::SYS_EXIT as u32).to_le_bytes());
+    code.extend([0x0f, 0x05]); // syscall
+
+    // path:
+    code.extend_from_slice(PATH);
+    code
+}
+
+#[test_case]
+fn fork_and_waitpid() {
+    let id = exec::spawn("fork", &[], &[]).expect("fork failed to start");
+    assert_eq!(thread::wait(id), Ok(ExitStatus::Code(7)));
+}
+
+#[test_case]
+fn exec_replaces_the_program() {
+    let id = exec::spawn("exec", &[], &[]).expect("exec failed to start");
+    assert_eq!(thread::wait(id), Ok(ExitStatus::Code(7)));
+}
+
+#[test_case]
+fn init_adopts_and_reaps_orphans() {
+    let id = exec::spawn("orphan", &[], &[]).expect("orphan failed to start");
+    assert_eq!(thread::wait(id), Ok(ExitStatus::Code(0)));
+
+    let threads = thread::list();
+    let init = threads
+        .iter()
+        .find(|info| info.name == "init")
+        .expect("no init thread");
+    let child = threads
+        .iter()
+        .find(|info| info.name == "orphan")
+        .expect("forked child missing");
+    assert_eq!(child.parent, Some(init.id));
+
+    let child = child.id;
+    assert!(
+        thread::poll_until(100, || thread::info(child).is_none()),
+        "orphan was not reaped"
+    );
+}
+
+#[test_case]
+fn open_files_are_closed_on_exit() {
+    // a descriptor left open by one run would be missing from the next
+    for _ in 0..2 {
+        let id = exec::spawn("open_all", &[], &[]).expect("open_all failed to start");
+        assert_eq!(thread::wait(id), Ok(ExitStatus::Code(MAX_FILES as i64)));
+    }
+}
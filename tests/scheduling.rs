@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use kernel::thread::{self, NiceError, ThreadId};
+
+kernel::test_harness!(thread::init);
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+fn spin() {
+    while !STOP.load(Ordering::Relaxed) {
+        core::hint::spin_loop();
+    }
+}
+
+fn cpu_ticks(id: ThreadId) -> u64 {
+    thread::info(id).map_or(0, |info| info.cpu_ticks)
+}
+
+#[test_case]
+fn lower_nice_gets_more_cpu() {
+    STOP.store(false, Ordering::Relaxed);
+    let favoured = thread::spawn("favoured", spin);
+    let other = thread::spawn("other", spin);
+    thread::set_nice(other, 5).unwrap();
+
+    thread::sleep(100);
+    let (favoured_ticks, other_ticks) = (cpu_ticks(favoured), cpu_ticks(other));
+    STOP.store(true, Ordering::Relaxed);
+    thread::join(favoured);
+    thread::join(other);
+
+    // the weights differ by about 3x
+    assert!(favoured_ticks > other_ticks * 2);
+}
+
+#[test_case]
+fn sleep_is_accounted_on_wakeup() {
+    STOP.store(false, Ordering::Relaxed);
+    let sleeper = thread::spawn("sleeper", || {
+        thread::sleep(10);
+        spin();
+    });
+    thread::sleep(20);
+    let info = thread::info(sleeper);
+    STOP.store(true, Ordering::Relaxed);
+    thread::join(sleeper);
+
+    let info = info.expect("sleeper is still running");
+    assert!(info.sleep_ticks >= 9);
+}
+
+#[test_case]
+fn nice_is_range_checked() {
+    let me = thread::current();
+    assert_eq!(thread::set_nice(me, 20), Err(NiceError::OutOfRange));
+    assert_eq!(thread::set_nice(me, -20), Ok(()));
+    assert_eq!(thread::set_nice(me, 0), Ok(()));
+}
+
+#[test_case]
+fn init_frees_exited_threads() {
+    let id = thread::spawn("short", || {});
+    thread::join(id);
+    assert!(
+        thread::poll_until(100, || thread::info(id).is_none()),
+        "exited thread was not freed"
+    );
+}
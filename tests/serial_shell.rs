@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::{config::CONFIG, keyboard::Keymap, line_editor::LINE_EDITOR};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kernel::alocator;
+    use kernel::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    alocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+fn type_line(line: &[u8]) {
+    let mut editor = LINE_EDITOR.lock();
+    for byte in line {
+        editor.handle_byte(*byte);
+    }
+}
+
+#[test_case]
+fn command_from_serial_bytes() {
+    type_line(b"keymap us104\r");
+    assert_eq!(CONFIG.keymap(), Keymap::Us104);
+}
+
+#[test_case]
+fn serial_backspace_edits_line() {
+    type_line(b"keymap uk10x\x7f5\r");
+    assert_eq!(CONFIG.keymap(), Keymap::Uk105);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
@@ -0,0 +1,145 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use kernel::{
+    exec,
+    filesystem::file_tree::{self, File},
+    signal::Signal,
+    syscall,
+    thread::{self, ExitStatus, KillError, ThreadId, ThreadState},
+};
+
+kernel::test_harness!(|| {
+    thread::init();
+    // jmp $
+    file_tree::insert_content(File::new(
+        String::from("spin"),
+        exec::program_image(&[0xeb, 0xfe], false),
+    ));
+    file_tree::insert_content(File::new(
+        String::from("catch"),
+        exec::program_image(&catch_code(), true),
+    ));
+    // hlt
+    file_tree::insert_content(File::new(
+        String::from("privileged"),
+        exec::program_image(&[0xf4], false),
+    ));
+    file_tree::insert_content(File::new(
+        String::from("forge"),
+        exec::program_image(&forge_code(), false),
+    ));
+});
+
+/// Installs a SIGUSR1 handler setting a flag, spins until the flag is set
+/// and exits with 42.
+fn catch_code() -> Vec<u8> {
+    let mut code = Vec::new();
+    code.push(0xb8); // mov eax, SYS_SIGACTION
+    code.extend((syscall::SYS_SIGACTION as u32).to_le_bytes());
+    code.push(0xbf); // mov edi, SIGUSR1
+    code.extend((Signal::SIGUSR1 as u32).to_le_bytes());
+    code.extend([0x48, 0x8d, 0x35]); // lea rsi, [rip + handler]
+    code.extend(24u32.to_le_bytes());
+    code.extend([0x0f, 0x05]); // syscall
+
+    // loop:
+    code.extend([0x8a, 0x05]); // mov al, [rip + flag]
+    code.extend(24u32.to_le_bytes());
+    code.extend([0x84, 0xc0]); // test al, al
+    code.extend([0x74, 0xf6]); // jz loop
+    code.push(0xb8); // mov eax, SYS_EXIT
+    code.extend((syscall::SYS_EXIT as u32).to_le_bytes());
+    code.push(0xbf); // mov edi, 42
+    code.extend(42u32.to_le_bytes());
+    code.extend([0x0f, 0x05]); // syscall
+
+    // handler:
+    code.extend([0xc6, 0x05]); // mov byte [rip + flag], 1
+    code.extend(1u32.to_le_bytes());
+    code.push(1);
+    code.push(0xc3); // ret
+
+    // flag:
+    code.push(0);
+    code
+}
+
+/// Calls `sigreturn` without a handler, on a frame whose saved `rip` is a
+/// kernel address.
+fn forge_code() -> Vec<u8> {
+    let mut code = Vec::new();
+    code.extend([0x48, 0x81, 0xec]); // sub rsp, 0x100
+    code.extend(0x100u32.to_le_bytes());
+    // the frame starts 8 bytes below rsp, its rip after 3 words and 15 registers
+    code.extend([0x48, 0xc7, 0x84, 0x24]); // mov qword [rsp + 136], -1
+    code.extend(136u32.to_le_bytes());
+    code.extend((-1i32).to_le_bytes());
+    code.push(0xb8); // mov eax, SYS_SIGRETURN
+    code.extend((syscall::SYS_SIGRETURN as u32).to_le_bytes());
+    code.extend([0x0f, 0x05]); // syscall
+    code.push(0xb8); // mov eax, SYS_EXIT
+    code.extend((syscall::SYS_EXIT as u32).to_le_bytes());
+    code.extend([0x31, 0xff]); // xor edi, edi
+    code.extend([0x0f, 0x05]); // syscall
+    code
+}
+
+fn state(id: ThreadId) -> Option<ThreadState> {
+    thread::info(id).map(|info| info.state)
+}
+
+#[test_case]
+fn stop_continue_and_interrupt() {
+    let id = exec::spawn("spin", &[], &[]).expect("spin failed to start");
+    thread::sleep(5);
+
+    thread::send_signal(id, Signal::SIGSTOP).unwrap();
+    assert!(thread::poll_until(10, || state(id) == Some(ThreadState::Stopped)));
+
+    thread::send_signal(id, Signal::SIGCONT).unwrap();
+    assert!(thread::poll_until(10, || state(id) != Some(ThreadState::Stopped)));
+
+    thread::send_signal(id, Signal::SIGINT).unwrap();
+    assert_eq!(thread::wait(id), Ok(ExitStatus::Signaled(Signal::SIGINT)));
+}
+
+#[test_case]
+fn handler_runs_and_returns() {
+    let id = exec::spawn("catch", &[], &[]).expect("catch failed to start");
+    // give it time to install the handler
+    thread::sleep(10);
+    thread::send_signal(id, Signal::SIGUSR1).unwrap();
+    assert_eq!(thread::wait(id), Ok(ExitStatus::Code(42)));
+}
+
+#[test_case]
+fn forged_sigreturn_frame_is_rejected() {
+    let id = exec::spawn("forge", &[], &[]).expect("forge failed to start");
+    assert_eq!(thread::wait(id), Ok(ExitStatus::Signaled(Signal::SIGSEGV)));
+}
+
+#[test_case]
+fn privileged_instruction_kills_only_the_program() {
+    let id = exec::spawn("privileged", &[], &[]).expect("privileged failed to start");
+    assert_eq!(thread::wait(id), Ok(ExitStatus::Signaled(Signal::SIGSEGV)));
+}
+
+#[test_case]
+fn kernel_threads_get_no_signals() {
+    let me = thread::current();
+    assert_eq!(
+        thread::send_signal(me, Signal::SIGTERM),
+        Err(KillError::KernelThread)
+    );
+    assert_eq!(
+        thread::send_signal(ThreadId::from_u64(u64::MAX), Signal::SIGTERM),
+        Err(KillError::NoSuchThread)
+    );
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use kernel::{
+    acpi,
+    smp::{self, CpuState},
+    task::timer,
+};
+
+kernel::test_harness!(smp::init);
+
+#[test_case]
+fn every_processor_comes_online() {
+    let cpus: Vec<_> = smp::cpus().collect();
+    assert_eq!(cpus.len(), acpi::processors().len().max(1));
+    assert!(cpus.iter().all(|cpu| cpu.state() == CpuState::Online));
+    assert_eq!(smp::cpu_id(), 0);
+}
+
+#[test_case]
+fn shootdown_reaches_every_processor() {
+    let before: Vec<u64> = smp::cpus().map(|cpu| cpu.tlb_shootdowns()).collect();
+    smp::tlb_shootdown(None);
+    for (cpu, before) in smp::cpus().zip(before).skip(1) {
+        assert_eq!(cpu.tlb_shootdowns(), before + 1);
+    }
+}
+
+#[test_case]
+fn reschedule_ipi_is_delivered() {
+    let bsp = smp::current();
+    let before = bsp.reschedules();
+    smp::send_reschedule();
+    let deadline = timer::ticks() + 10;
+    while bsp.reschedules() == before && timer::ticks() < deadline {
+        core::hint::spin_loop();
+    }
+    assert_eq!(bsp.reschedules(), before + 1);
+}
@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use kernel::{
+    exec::{self, ExecError},
+    thread::{self, ExitStatus, WaitError},
+};
+
+kernel::test_harness!(|| {
+    thread::init();
+    exec::install_builtin_programs();
+});
+
+#[test_case]
+fn builtin_program_runs_to_exit() {
+    let id = exec::spawn("hello", &[], &[]).expect("hello failed to start");
+    assert_eq!(thread::wait(id), Ok(ExitStatus::Code(0)));
+    // the zombie is gone once its status has been collected
+    assert_eq!(thread::wait(id), Err(WaitError::NoSuchThread));
+}
+
+#[test_case]
+fn missing_program_is_not_found() {
+    assert_eq!(
+        exec::spawn("no-such-program", &[], &[]),
+        Err(ExecError::NotFound)
+    );
+}
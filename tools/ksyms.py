@@ -0,0 +1,86 @@
+#!/usr/bin/env python3
+"""Embed the kernel's symbol table into its `.ksyms` section.
+
+Usage: tools/ksyms.py target/x86_64-zlatovlas-os/debug/kernel
+
+`tools/runner.py` does this for every kernel `cargo run` and `cargo test`
+boot. The layout must match `src/ksyms.rs`.
+"""
+
+import struct
+import subprocess
+import sys
+
+ENTRY = struct.Struct("<QII")
+HEADER = struct.Struct("<4sI")
+
+
+def section(elf, name):
+    shoff, = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x3A)
+
+    def header(index):
+        return struct.unpack_from("<IIQQQQIIQQ", elf, shoff + index * shentsize)
+
+    strtab_offset = header(shstrndx)[4]
+    for index in range(shnum):
+        sh = header(index)
+        start = strtab_offset + sh[0]
+        end = elf.index(b"\0", start)
+        if elf[start:end].decode() == name:
+            return sh[4], sh[5]
+    raise SystemExit(f"{name} section not found")
+
+
+def symbols(path):
+    out = subprocess.run(
+        ["nm", "--defined-only", "--demangle", "-n", path],
+        check=True,
+        capture_output=True,
+        text=True,
+    ).stdout
+    for line in out.splitlines():
+        parts = line.split(" ", 2)
+        if len(parts) == 3 and parts[1] in "Tt":
+            yield int(parts[0], 16), parts[2]
+
+
+def build(syms, size):
+    entries = []
+    names = bytearray()
+    for addr, name in syms:
+        entries.append((addr, name.encode()))
+    while True:
+        names_offset = HEADER.size + len(entries) * ENTRY.size
+        names_size = sum(len(name) for _, name in entries)
+        if names_offset + names_size <= size:
+            break
+        entries = entries[: len(entries) * 9 // 10]
+
+    table = bytearray(HEADER.pack(b"KSYM", len(entries)))
+    for addr, name in entries:
+        table += ENTRY.pack(addr, names_offset + len(names), len(name))
+        names += name
+    table += names
+    return bytes(table)
+
+
+def embed(path):
+    with open(path, "rb") as f:
+        elf = bytearray(f.read())
+    offset, size = section(elf, ".ksyms")
+    table = build(symbols(path), size)
+    elf[offset : offset + size] = table.ljust(size, b"\0")
+    with open(path, "wb") as f:
+        f.write(elf)
+    print(f"embedded {struct.unpack_from('<I', table, 4)[0]} symbols", file=sys.stderr)
+
+
+def main():
+    if len(sys.argv) != 2:
+        raise SystemExit(__doc__)
+    embed(sys.argv[1])
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,31 @@
+#!/usr/bin/env python3
+"""Cargo runner: boots a kernel or test binary with `bootimage runner`.
+
+Usage: tools/runner.py KERNEL [ARGS...]
+
+Embeds the symbol table for backtraces first, see `tools/ksyms.py`. Only
+`tests/smp.rs` gets more than one processor, everything else runs on the
+bootstrap processor alone. ARGS are passed on to QEMU.
+"""
+
+import os
+import sys
+
+import ksyms
+
+SMP_ARGS = ["-smp", "4"]
+
+
+def main():
+    if len(sys.argv) < 2:
+        raise SystemExit(__doc__)
+    kernel, args = sys.argv[1], sys.argv[2:]
+    ksyms.embed(kernel)
+    # test binaries are named after their file, plus a hash
+    if os.path.basename(kernel).startswith("smp-"):
+        args = SMP_ARGS + args
+    os.execvp("bootimage", ["bootimage", "runner", kernel, *args])
+
+
+if __name__ == "__main__":
+    main()